@@ -0,0 +1,245 @@
+#!/bin/bash
+# Generate a fake sysfs, udev database, /proc, and /dev tree in root/ for
+# the blkinfo tests.
+#
+# loop0       7:0    xfs (no udev record; probed from dev/loop0)
+# sda         8:0    disk
+#   sda1      8:1      BIOS-BOOT
+#   sda2      8:2      EFI-SYSTEM (vfat)
+#   sda3      8:3      boot (ext4, /boot)
+#   sda4      8:4      root (xfs, /sysroot and "/var/my dir")
+# sdb, sdc    8:16, 8:32
+#   dm-0      253:0    multipath mpatha
+#     dm-1    253:1      mpatha1, boot (ext4)
+# sdd         8:48   USB stick
+#   sdd1      8:49     LIVE (vfat)
+# md127       9:127  raid1 of nvme0n1p2 and nvme0n1p3 (LVM PV)
+# sr0         11:0   CD-ROM
+# nvme0n1     259:0  disk
+#   nvme0n1p1 259:1    LUKS
+#     dm-2    253:2      luks-root (LVM PV)
+#   nvme0n1p2 259:2    md member
+#   nvme0n1p3 259:3    md member
+#   nvme0n1p4 259:4    swap
+# dm-3        253:3  LVM vg0/var on luks-root and md127 (xfs, /var)
+
+set -euo pipefail
+
+cd "$(dirname "$0")"
+rm -rf root
+mkdir root
+cd root
+mkdir -p sys/class/block sys/dev/block run/udev/data proc/self dev/mapper
+
+# dev <sysfs-dir> <maj:min> <size-in-sectors> [attr=value...]
+dev() {
+    local dir="sys/devices/$1" devnum="$2" size="$3" name
+    shift 3
+    name=$(basename "$dir")
+    mkdir -p "$dir"
+    echo "$devnum" > "$dir/dev"
+    echo "$size" > "$dir/size"
+    echo 0 > "$dir/ro"
+    for attr in "$@"; do
+        mkdir -p "$(dirname "$dir/${attr%%=*}")"
+        echo "${attr#*=}" > "$dir/${attr%%=*}"
+    done
+    ln -sr "$dir" "sys/class/block/$name"
+    ln -sr "$dir" "sys/dev/block/$devnum"
+    touch "dev/$name"
+}
+
+# hold <holder> <device>
+hold() {
+    local holder device
+    holder=$(readlink -f "sys/class/block/$1")
+    device=$(readlink -f "sys/class/block/$2")
+    mkdir -p "$holder/slaves" "$device/holders"
+    ln -sr "$device" "$holder/slaves/$2"
+    ln -sr "$holder" "$device/holders/$1"
+}
+
+# udev <maj:min> <line...>
+udev() {
+    local devnum="$1"
+    shift
+    printf "%s\n" "$@" > "run/udev/data/b$devnum"
+}
+
+# SCSI
+scsi=platform/host0/target0:0:0
+for lun in 0 1 2 3; do
+    mkdir -p "sys/devices/$scsi/0:0:0:$lun"
+    echo 0 > "sys/devices/$scsi/0:0:0:$lun/type"
+    echo "QEMU HARDDISK   " > "sys/devices/$scsi/0:0:0:$lun/model"
+done
+echo 5 > "sys/devices/$scsi/0:0:0:3/type"
+echo "QEMU DVD-ROM    " > "sys/devices/$scsi/0:0:0:3/model"
+
+disk="$scsi/0:0:0:0/block/sda"
+dev "$disk" 8:0 33554432 removable=0 queue/rotational=1 \
+    queue/logical_block_size=512
+ln -s ../../../0:0:0:0 "sys/devices/$disk/device"
+dev "$disk/sda1" 8:1 2048 partition=1
+dev "$disk/sda2" 8:2 260096 partition=2
+dev "$disk/sda3" 8:3 786432 partition=3
+dev "$disk/sda4" 8:4 32503775 partition=4
+udev 8:0 "S:disk/by-id/scsi-0QEMU_QEMU_HARDDISK_drive-scsi0" \
+    "E:ID_BUS=scsi" "E:ID_SERIAL=0QEMU_QEMU_HARDDISK_drive-scsi0" \
+    "E:ID_SERIAL_SHORT=drive-scsi0" "E:ID_PART_TABLE_TYPE=gpt" \
+    "E:DEVTYPE=disk"
+udev 8:1 "E:ID_SERIAL_SHORT=drive-scsi0" "E:ID_PART_ENTRY_NAME=BIOS-BOOT" \
+    "E:ID_PART_ENTRY_TYPE=21686148-6449-6e6f-744e-656564454649" \
+    "E:DEVTYPE=partition"
+udev 8:2 "S:disk/by-label/EFI-SYSTEM" "E:ID_SERIAL_SHORT=drive-scsi0" \
+    "E:ID_FS_TYPE=vfat" "E:ID_FS_LABEL=EFI-SYSTEM" \
+    "E:ID_FS_LABEL_ENC=EFI-SYSTEM" "E:ID_FS_UUID=1B2D-4F1A" \
+    "E:ID_FS_UUID_ENC=1B2D-4F1A" "E:ID_PART_ENTRY_NAME=EFI-SYSTEM" \
+    "E:ID_PART_ENTRY_TYPE=c12a7328-f81f-11d2-ba4b-00a0c93ec93b" \
+    "E:DEVTYPE=partition"
+udev 8:3 "S:disk/by-label/boot\\x20label" "E:ID_SERIAL_SHORT=drive-scsi0" \
+    "E:ID_FS_TYPE=ext4" "E:ID_FS_LABEL=boot_label" \
+    "E:ID_FS_LABEL_ENC=boot\\x20label" \
+    "E:ID_FS_UUID=96d15588-3596-4b3c-adca-a2ff7279ea63" \
+    "E:ID_PART_ENTRY_NAME=boot" \
+    "E:ID_PART_ENTRY_TYPE=0fc63daf-8483-4772-8e79-3d69d8477de4" \
+    "E:DEVTYPE=partition"
+udev 8:4 "S:disk/by-label/root" "E:ID_SERIAL_SHORT=drive-scsi0" \
+    "E:ID_FS_TYPE=xfs" "E:ID_FS_LABEL=root" \
+    "E:ID_FS_UUID=910678ff-f77e-4a7d-8d53-86f2ac47a823" \
+    "E:ID_PART_ENTRY_NAME=root" \
+    "E:ID_PART_ENTRY_TYPE=0fc63daf-8483-4772-8e79-3d69d8477de4" \
+    "E:DEVTYPE=partition"
+
+# multipath
+for lun in 1 2; do
+    name=sd$(printf "\\x$(printf %x $((97 + lun)))")
+    disk="$scsi/0:0:0:$lun/block/$name"
+    dev "$disk" "8:$((lun * 16))" 67108864 removable=0 queue/rotational=0 \
+        queue/logical_block_size=512
+    ln -s "../../../0:0:0:$lun" "sys/devices/$disk/device"
+    udev "8:$((lun * 16))" "E:ID_BUS=scsi" "E:ID_SERIAL_SHORT=mpath-lun" \
+        "E:ID_WWN=0x6001405fd6fbbbdc" "E:DM_MULTIPATH_DEVICE_PATH=1" \
+        "E:DEVTYPE=disk"
+done
+dev virtual/block/dm-0 253:0 67108864 removable=0 queue/rotational=0 \
+    queue/logical_block_size=512 \
+    dm/name=mpatha dm/uuid=mpath-36001405fd6fbbbdc
+dev virtual/block/dm-1 253:1 786432 removable=0 queue/rotational=0 \
+    queue/logical_block_size=512 \
+    dm/name=mpatha1 dm/uuid=part1-mpath-36001405fd6fbbbdc
+hold dm-0 sdb
+hold dm-0 sdc
+hold dm-1 dm-0
+ln -s ../dm-0 dev/mapper/mpatha
+ln -s ../dm-1 dev/mapper/mpatha1
+udev 253:0 "S:mapper/mpatha" "E:DM_NAME=mpatha" \
+    "E:DM_UUID=mpath-36001405fd6fbbbdc" "E:DEVTYPE=disk"
+udev 253:1 "S:mapper/mpatha1" "E:DM_NAME=mpatha1" \
+    "E:DM_UUID=part1-mpath-36001405fd6fbbbdc" "E:ID_FS_TYPE=ext4" \
+    "E:ID_FS_LABEL=boot" "E:ID_FS_UUID=5c8e9e5a-8f4b-4f73-9c3e-0a6c47d6a2d1" \
+    "E:ID_PART_ENTRY_NAME=boot" "E:DEVTYPE=disk"
+
+# USB
+usb=pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0
+mkdir -p "sys/devices/$usb"
+echo 0 > "sys/devices/$usb/type"
+echo "Flash Drive     " > "sys/devices/$usb/model"
+disk="$usb/block/sdd"
+dev "$disk" 8:48 60437492 removable=1 queue/rotational=1 \
+    queue/logical_block_size=512
+ln -s ../../../6:0:0:0 "sys/devices/$disk/device"
+dev "$disk/sdd1" 8:49 60435456 partition=1
+udev 8:48 "E:ID_BUS=usb" "E:ID_SERIAL_SHORT=4C530001230507104445" \
+    "E:ID_PART_TABLE_TYPE=dos" "E:DEVTYPE=disk"
+udev 8:49 "E:ID_BUS=usb" "E:ID_SERIAL_SHORT=4C530001230507104445" \
+    "E:ID_FS_TYPE=vfat" "E:ID_FS_LABEL=LIVE" "E:ID_FS_UUID=4A2B-11C0" \
+    "E:DEVTYPE=partition"
+
+# CD-ROM
+disk="$scsi/0:0:0:3/block/sr0"
+dev "$disk" 11:0 0 removable=1 queue/rotational=1 \
+    queue/logical_block_size=2048
+echo 1 > "sys/devices/$disk/ro"
+ln -s ../../../0:0:0:3 "sys/devices/$disk/device"
+udev 11:0 "E:ID_CDROM=1" "E:ID_BUS=ata" "E:DEVTYPE=disk"
+
+# NVMe
+ctrl=pci0000:00/0000:00:06.0/nvme/nvme0
+mkdir -p "sys/devices/$ctrl"
+echo "Samsung SSD 970 EVO 500GB               " > "sys/devices/$ctrl/model"
+echo "S466NX0M123456      " > "sys/devices/$ctrl/serial"
+disk="$ctrl/nvme0n1"
+dev "$disk" 259:0 976773168 removable=0 queue/rotational=0 \
+    queue/logical_block_size=4096 \
+    wwid=eui.0025385391b0e1a2
+ln -s ../../nvme0 "sys/devices/$disk/device"
+dev "$disk/nvme0n1p1" 259:1 209715200 partition=1
+dev "$disk/nvme0n1p2" 259:2 20971520 partition=2
+dev "$disk/nvme0n1p3" 259:3 20971520 partition=3
+dev "$disk/nvme0n1p4" 259:4 8388608 partition=4
+udev 259:0 "E:ID_SERIAL_SHORT=S466NX0M123456" "E:ID_PART_TABLE_TYPE=gpt" \
+    "E:DEVTYPE=disk"
+udev 259:1 "E:ID_FS_TYPE=crypto_LUKS" \
+    "E:ID_FS_UUID=6b1d0e6c-53f8-4a6e-9e0b-3d7b2a7c9f10" \
+    "E:DEVTYPE=partition"
+udev 259:2 "E:ID_FS_TYPE=linux_raid_member" "E:DEVTYPE=partition"
+udev 259:3 "E:ID_FS_TYPE=linux_raid_member" "E:DEVTYPE=partition"
+udev 259:4 "E:ID_FS_TYPE=swap" \
+    "E:ID_FS_UUID=0f4b7b38-2d7a-4c4a-8a1b-6f0c5e3e9d21" \
+    "E:DEVTYPE=partition"
+
+# LUKS
+dev virtual/block/dm-2 253:2 209682432 removable=0 queue/rotational=0 \
+    queue/logical_block_size=4096 \
+    dm/name=luks-root \
+    dm/uuid=CRYPT-LUKS2-6b1d0e6c53f84a6e9e0b3d7b2a7c9f10-luks-root
+hold dm-2 nvme0n1p1
+ln -s ../dm-2 dev/mapper/luks-root
+udev 253:2 "S:mapper/luks-root" "E:DM_NAME=luks-root" \
+    "E:ID_FS_TYPE=LVM2_member" "E:DEVTYPE=disk"
+
+# RAID
+dev virtual/block/md127 9:127 20969472 removable=0 queue/rotational=0 \
+    queue/logical_block_size=4096 \
+    md/level=raid1
+hold md127 nvme0n1p2
+hold md127 nvme0n1p3
+udev 9:127 "E:MD_LEVEL=raid1" "E:ID_FS_TYPE=LVM2_member" "E:DEVTYPE=disk"
+
+# LVM, spanning the LUKS and RAID devices
+dev virtual/block/dm-3 253:3 230651904 removable=0 queue/rotational=0 \
+    queue/logical_block_size=4096 \
+    dm/name=vg0-var \
+    dm/uuid=LVM-q3Jm4bKd9sXyF2hT7vWcN1pLz8RgE5uA0oYi6MnBkCfDjHlQwSxZtVrUe
+hold dm-3 dm-2
+hold dm-3 md127
+ln -s ../dm-3 dev/mapper/vg0-var
+udev 253:3 "S:mapper/vg0-var" "E:DM_NAME=vg0-var" "E:DM_VG_NAME=vg0" \
+    "E:DM_LV_NAME=var" "E:ID_FS_TYPE=xfs" "E:ID_FS_LABEL=var" \
+    "E:ID_FS_UUID=1e2d3c4b-5a69-4788-97a6-b5c4d3e2f101" "E:DEVTYPE=disk"
+
+# loop device with an XFS superblock and no udev record
+dev virtual/block/loop0 7:0 8 removable=0 queue/rotational=1 \
+    queue/logical_block_size=512
+{
+    printf 'XFSB'
+    head -c 28 /dev/zero
+    printf '\xa8\xf3\xb6\xf2\x03\xc1\x4c\x8e\x9a\x7e\x2f\x9b\x5c\x1d\x0e\x11'
+    head -c 60 /dev/zero
+    printf 'loopfs'
+    head -c $((4096 - 114)) /dev/zero
+} > dev/loop0
+
+cat > proc/self/mountinfo <<EOF
+22 1 8:4 / /sysroot rw,relatime shared:1 - xfs /dev/sda4 rw,attr2,inode64
+23 22 8:3 / /boot rw,relatime shared:2 - ext4 /dev/sda3 rw
+24 22 8:4 /var/mydir /var/my\\040dir rw,relatime shared:1 - xfs /dev/sda4 rw,attr2,inode64
+25 22 253:3 / /var rw,relatime shared:3 - xfs /dev/mapper/vg0-var rw
+26 22 0:21 / /proc rw,nosuid,nodev,noexec,relatime shared:4 - proc proc rw
+EOF
+
+cat > proc/swaps <<EOF
+Filename				Type		Size		Used		Priority
+/dev/nvme0n1p4                          partition	4194300		0		-2
+EOF
@@ -18,6 +18,7 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use libcoreinst::blkinfo::BlkInfo;
 use libcoreinst::blockdev::*;
 use libcoreinst::io::*;
 use libcoreinst::util::*;
@@ -85,13 +86,14 @@ pub fn get_boot_mount_from_cmdline_args(
     if let Some(path) = boot_mount {
         Ok(Some(Mount::from_existing(path)?))
     } else if let Some(devpath) = boot_device {
-        let devinfo = lsblk_single(Path::new(devpath))?;
-        let fs = devinfo
-            .get("FSTYPE")
+        let info = BlkInfo::default();
+        let fs = info
+            .with_fs_info(info.device(Path::new(devpath))?)
+            .fstype
             .with_context(|| format!("failed to query filesystem for {}", devpath))?;
         Ok(Some(Mount::try_mount(
             devpath,
-            fs,
+            &fs,
             mount::MsFlags::empty(),
         )?))
     } else {
@@ -100,10 +102,7 @@ pub fn get_boot_mount_from_cmdline_args(
 }
 
 fn device_to_kargs(root: &Mount, device: PathBuf) -> Result<Option<Vec<String>>> {
-    let blkinfo = lsblk_single(&device)?;
-    let blktype = blkinfo
-        .get("TYPE")
-        .with_context(|| format!("missing TYPE for {}", device.display()))?;
+    let blktype = BlkInfo::default().device(&device)?.kind;
     // a `match {}` construct would be nice here, but for RAID it's a prefix match
     if blktype.starts_with("raid") || blktype == "linear" {
         Ok(Some(get_raid_kargs(&device)?))
@@ -0,0 +1,866 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Block device information from sysfs, the udev database, and the
+//! mount tables, plus superblock probing.  This replaces parsing the
+//! output of lsblk and blkid.
+
+use anyhow::{bail, Context, Result};
+use nix::sys::stat::{major, minor};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::{metadata, read_dir, read_to_string, File};
+use std::io::ErrorKind;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+mod probe;
+mod udev;
+
+pub use self::udev::UdevRecord;
+
+/// Where to find information about block devices.  Normally this is the
+/// running system, but tests can point it at a fake tree.
+#[derive(Debug, Clone)]
+pub struct BlkInfo {
+    sys: PathBuf,
+    udev_data: PathBuf,
+    proc: PathBuf,
+    dev: PathBuf,
+    /// Read on first use, so a BlkInfo sees the mounts as of its first
+    /// lookup.
+    mount_tables: RefCell<Option<Rc<MountTables>>>,
+}
+
+/// The parts of /proc/self/mountinfo and /proc/swaps we care about.
+#[derive(Debug, Default)]
+struct MountTables {
+    mounts: Vec<MountEntry>,
+    /// Paths of active swap devices.
+    swaps: Vec<String>,
+}
+
+#[derive(Debug)]
+struct MountEntry {
+    /// "major:minor"
+    devnum: String,
+    source: Option<String>,
+    mountpoint: String,
+}
+
+impl Default for BlkInfo {
+    fn default() -> Self {
+        Self::with_root(Path::new("/"))
+    }
+}
+
+/// A block device, roughly as described by lsblk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDevice {
+    /// Kernel name, e.g. "sda1" or "dm-0".
+    pub name: String,
+    /// Path to the device node, e.g. "/dev/sda1" or "/dev/mapper/luks".
+    pub path: String,
+    pub major: u64,
+    pub minor: u64,
+    /// Device type as reported by lsblk, e.g. "disk", "part", "mpath",
+    /// "crypt", "lvm", "raid1", "loop", or "rom".
+    pub kind: String,
+    /// Size in bytes.
+    pub size: u64,
+    pub removable: bool,
+    pub read_only: bool,
+    pub rotational: bool,
+    /// Logical sector size in bytes.
+    pub sector_size: Option<u64>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub wwn: Option<String>,
+    /// Transport as reported by lsblk, e.g. "nvme", "sata", "scsi",
+    /// "usb", or "virtio".
+    pub transport: Option<String>,
+    pub fstype: Option<String>,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub parttype: Option<String>,
+    pub partlabel: Option<String>,
+    pub mountpoints: Vec<String>,
+    pub swap: bool,
+    /// The udev database record, if udev knows about the device.
+    pub udev: Option<UdevRecord>,
+    /// Kernel name of the containing disk, if this is a kernel partition.
+    parent: Option<String>,
+    sysfs_dir: PathBuf,
+}
+
+impl BlkInfo {
+    /// Use the sysfs, udev, proc, and dev trees under `root`.
+    pub fn with_root(root: &Path) -> Self {
+        Self {
+            sys: root.join("sys"),
+            udev_data: root.join("run/udev/data"),
+            proc: root.join("proc"),
+            dev: root.join("dev"),
+            mount_tables: RefCell::new(None),
+        }
+    }
+
+    /// Return all block devices, ordered by device number.
+    pub fn devices(&self) -> Result<Vec<BlockDevice>> {
+        let class_dir = self.sys.join("class/block");
+        let mut ret = Vec::new();
+        for ent in
+            read_dir(&class_dir).with_context(|| format!("reading {}", class_dir.display()))?
+        {
+            let ent = ent.with_context(|| format!("reading {} entry", class_dir.display()))?;
+            ret.push(self.device_by_name(&ent.file_name().to_string_lossy())?);
+        }
+        ret.sort_by_key(|d| (d.major, d.minor));
+        Ok(ret)
+    }
+
+    /// Look up a block device by kernel name, e.g. "sda".
+    pub fn device_by_name(&self, name: &str) -> Result<BlockDevice> {
+        let link = self.sys.join("class/block").join(name);
+        let dir = link
+            .canonicalize()
+            .with_context(|| format!("couldn't find block device {}", name))?;
+        self.read_device(&dir)
+    }
+
+    /// Look up a block device by device number.
+    pub fn device_by_number(&self, maj: u64, min: u64) -> Result<BlockDevice> {
+        let link = self.sys.join(format!("dev/block/{}:{}", maj, min));
+        let dir = link
+            .canonicalize()
+            .with_context(|| format!("couldn't find block device {}:{}", maj, min))?;
+        self.read_device(&dir)
+    }
+
+    /// Look up a block device by the path to its device node, or a symlink
+    /// to it.
+    pub fn device(&self, path: &Path) -> Result<BlockDevice> {
+        let canon = path
+            .canonicalize()
+            .with_context(|| format!("canonicalizing {}", path.display()))?;
+        let meta =
+            metadata(&canon).with_context(|| format!("getting metadata for {}", path.display()))?;
+        if meta.file_type().is_block_device() {
+            return self.device_by_number(major(meta.rdev()), minor(meta.rdev()));
+        }
+        // not a device node; only expected in a fake tree, where device
+        // nodes are regular files named after the kernel device
+        for dev in self.devices()? {
+            if self.kernel_node(&dev.name).canonicalize().ok().as_ref() == Some(&canon) {
+                return Ok(dev);
+            }
+        }
+        bail!("{} is not a block device", path.display());
+    }
+
+    /// Return the partitions of a device and the devices holding it, such
+    /// as device-mapper or RAID devices.
+    pub fn children(&self, dev: &BlockDevice) -> Result<Vec<BlockDevice>> {
+        let mut ret = Vec::new();
+        for ent in read_dir(&dev.sysfs_dir)
+            .with_context(|| format!("reading {}", dev.sysfs_dir.display()))?
+        {
+            let ent = ent.with_context(|| format!("reading {} entry", dev.sysfs_dir.display()))?;
+            if ent.path().join("partition").exists() {
+                ret.push(self.read_device(&ent.path())?);
+            }
+        }
+        ret.sort_by_key(|d| (d.major, d.minor));
+        for name in list_dir(&dev.sysfs_dir.join("holders"))? {
+            ret.push(self.device_by_name(&name)?);
+        }
+        Ok(ret)
+    }
+
+    /// Return the device and all of its descendants, depth-first, like
+    /// `lsblk <dev>`.
+    pub fn tree(&self, dev: &BlockDevice) -> Result<Vec<BlockDevice>> {
+        let mut ret = Vec::new();
+        let mut seen = HashSet::new();
+        self.walk(dev.clone(), &mut seen, &mut ret, &|d| self.children(d))?;
+        Ok(ret)
+    }
+
+    /// Return the disk containing a partition and the devices a
+    /// device-mapper or RAID device is built on.
+    pub fn parents(&self, dev: &BlockDevice) -> Result<Vec<BlockDevice>> {
+        let mut ret = Vec::new();
+        if let Some(parent) = &dev.parent {
+            ret.push(self.device_by_name(parent)?);
+        }
+        for name in list_dir(&dev.sysfs_dir.join("slaves"))? {
+            ret.push(self.device_by_name(&name)?);
+        }
+        Ok(ret)
+    }
+
+    /// Return the device and all of its ancestors, depth-first, like
+    /// `lsblk --inverse <dev>`.
+    pub fn inverse_tree(&self, dev: &BlockDevice) -> Result<Vec<BlockDevice>> {
+        let mut ret = Vec::new();
+        let mut seen = HashSet::new();
+        self.walk(dev.clone(), &mut seen, &mut ret, &|d| self.parents(d))?;
+        Ok(ret)
+    }
+
+    fn walk<F>(
+        &self,
+        dev: BlockDevice,
+        seen: &mut HashSet<String>,
+        out: &mut Vec<BlockDevice>,
+        next: &F,
+    ) -> Result<()>
+    where
+        F: Fn(&BlockDevice) -> Result<Vec<BlockDevice>>,
+    {
+        if !seen.insert(dev.name.clone()) {
+            return Ok(());
+        }
+        let next_devs = next(&dev)?;
+        out.push(dev);
+        for d in next_devs {
+            self.walk(d, seen, out, next)?;
+        }
+        Ok(())
+    }
+
+    /// Fill in the filesystem information of a device that udev doesn't
+    /// know about by reading its superblock.  This is best-effort, like
+    /// lsblk; an unreadable device is returned without filesystem
+    /// information.  Lookups don't probe, since most callers don't need
+    /// filesystem information and probing every device is slow.
+    pub fn with_fs_info(&self, mut dev: BlockDevice) -> BlockDevice {
+        if dev.udev.is_none() {
+            let _ = self.probe(&mut dev);
+        }
+        dev
+    }
+
+    /// Update the filesystem information of a device by reading its
+    /// superblock, rather than trusting the udev database.  Returns false
+    /// if the device couldn't be read or contains an unrecognized format.
+    pub fn probe(&self, dev: &mut BlockDevice) -> Result<bool> {
+        let mut f = match File::open(self.kernel_node(&dev.name)) {
+            Ok(f) => f,
+            Err(_) => return Ok(false),
+        };
+        match probe::probe(&mut f).with_context(|| format!("probing {}", dev.path))? {
+            Some(info) => {
+                dev.fstype = Some(info.fstype);
+                dev.label = info.label;
+                dev.uuid = info.uuid;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn read_device(&self, dir: &Path) -> Result<BlockDevice> {
+        let name = dir
+            .file_name()
+            .with_context(|| format!("sysfs path {} has no filename", dir.display()))?
+            .to_string_lossy()
+            .into_owned();
+        let (maj, min) = parse_devnum(&read_attr(dir, "dev")?.unwrap_or_default())
+            .with_context(|| format!("reading device number of {}", name))?;
+        let is_partition = dir.join("partition").exists();
+        // partitions don't have queue or device attributes; use the disk's
+        let disk_dir = if is_partition {
+            dir.parent()
+                .with_context(|| format!("partition {} has no parent", dir.display()))?
+        } else {
+            dir
+        };
+        let udev = UdevRecord::read(&self.udev_data, maj, min)?;
+        let prop = |key| udev.as_ref().and_then(|u| u.get_decoded(key));
+
+        let dm_name = read_attr(dir, "dm/name")?;
+        let dm_uuid = read_attr(dir, "dm/uuid")?;
+        let kind = if is_partition {
+            "part".into()
+        } else if let Some(uuid) = &dm_uuid {
+            match uuid.split('-').next().unwrap_or_default() {
+                "mpath" => "mpath",
+                "LVM" => "lvm",
+                "CRYPT" => "crypt",
+                p if p.starts_with("part") => "part",
+                _ => "dm",
+            }
+            .into()
+        } else if let Some(level) = read_attr(dir, "md/level")? {
+            level
+        } else if name.starts_with("loop") {
+            "loop".into()
+        } else if read_attr(dir, "device/type")?.as_deref() == Some("5") {
+            "rom".into()
+        } else {
+            "disk".into()
+        };
+        let path = match &dm_name {
+            Some(dm_name) => self.dev.join("mapper").join(dm_name),
+            None => self.kernel_node(&name),
+        };
+        let path = path
+            .to_str()
+            .with_context(|| format!("path {} is not UTF-8", path.display()))?
+            .to_string();
+
+        let mut dev = BlockDevice {
+            path,
+            major: maj,
+            minor: min,
+            kind,
+            size: read_attr(dir, "size")?
+                .unwrap_or_default()
+                .parse::<u64>()
+                .with_context(|| format!("parsing size of {}", name))?
+                * 512,
+            removable: read_attr(disk_dir, "removable")?.as_deref() == Some("1"),
+            read_only: read_attr(dir, "ro")?.as_deref() == Some("1"),
+            rotational: read_attr(disk_dir, "queue/rotational")?.as_deref() == Some("1"),
+            sector_size: match read_attr(disk_dir, "queue/logical_block_size")? {
+                Some(size) => Some(
+                    size.parse()
+                        .with_context(|| format!("parsing sector size of {}", name))?,
+                ),
+                None => None,
+            },
+            model: read_attr(disk_dir, "device/model")?,
+            serial: match prop("ID_SERIAL_SHORT") {
+                Some(serial) => Some(serial),
+                None => read_attr(disk_dir, "device/serial")?,
+            },
+            wwn: match prop("ID_WWN_WITH_EXTENSION").or_else(|| prop("ID_WWN")) {
+                Some(wwn) => Some(wwn),
+                None => read_attr(disk_dir, "wwid")?.or(read_attr(disk_dir, "device/wwid")?),
+            },
+            transport: transport(disk_dir, prop("ID_BUS").as_deref()),
+            fstype: prop("ID_FS_TYPE"),
+            label: prop("ID_FS_LABEL"),
+            uuid: prop("ID_FS_UUID"),
+            parttype: prop("ID_PART_ENTRY_TYPE"),
+            partlabel: prop("ID_PART_ENTRY_NAME"),
+            mountpoints: Vec::new(),
+            swap: false,
+            udev,
+            parent: if is_partition {
+                disk_dir
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+            } else {
+                None
+            },
+            sysfs_dir: dir.to_path_buf(),
+            name,
+        };
+        self.read_mounts(&mut dev)?;
+        Ok(dev)
+    }
+
+    fn read_mounts(&self, dev: &mut BlockDevice) -> Result<()> {
+        let tables = self.mount_tables()?;
+        let devnum = format!("{}:{}", dev.major, dev.minor);
+        // the device number is anonymous for e.g. btrfs, so also check the
+        // mount source
+        dev.mountpoints = tables
+            .mounts
+            .iter()
+            .filter(|m| {
+                m.devnum == devnum
+                    || m.source
+                        .as_ref()
+                        .map_or(false, |s| self.is_node_for(dev, s))
+            })
+            .map(|m| m.mountpoint.clone())
+            .collect();
+        dev.swap = tables.swaps.iter().any(|s| self.is_node_for(dev, s));
+        Ok(())
+    }
+
+    fn mount_tables(&self) -> Result<Rc<MountTables>> {
+        if let Some(tables) = &*self.mount_tables.borrow() {
+            return Ok(tables.clone());
+        }
+        let tables = Rc::new(self.read_mount_tables()?);
+        *self.mount_tables.borrow_mut() = Some(tables.clone());
+        Ok(tables)
+    }
+
+    fn read_mount_tables(&self) -> Result<MountTables> {
+        let mut tables = MountTables::default();
+
+        let mountinfo_path = self.proc.join("self/mountinfo");
+        let mountinfo = read_to_string(&mountinfo_path)
+            .with_context(|| format!("reading {}", mountinfo_path.display()))?;
+        for line in mountinfo.lines() {
+            // 36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw
+            let fields: Vec<&str> = line.split(' ').collect();
+            if fields.len() < 5 {
+                bail!("invalid line in {}: {}", mountinfo_path.display(), line);
+            }
+            tables.mounts.push(MountEntry {
+                devnum: fields[2].to_string(),
+                source: fields
+                    .iter()
+                    .position(|&f| f == "-")
+                    .and_then(|i| fields.get(i + 2))
+                    .map(|s| unescape_mount_field(s)),
+                mountpoint: unescape_mount_field(fields[4]),
+            });
+        }
+
+        let swaps_path = self.proc.join("swaps");
+        let swaps = read_to_string(&swaps_path)
+            .with_context(|| format!("reading {}", swaps_path.display()))?;
+        tables.swaps = swaps
+            .lines()
+            .skip(1)
+            .map(|line| unescape_mount_field(line.split_whitespace().next().unwrap_or_default()))
+            .collect();
+        Ok(tables)
+    }
+
+    /// Whether a path from the mount tables refers to the device node.
+    fn is_node_for(&self, dev: &BlockDevice, path: &str) -> bool {
+        let path = match path.strip_prefix("/dev/") {
+            Some(rel) => self.dev.join(rel),
+            None => return false,
+        };
+        path == self.kernel_node(&dev.name) || path == Path::new(&dev.path)
+    }
+
+    /// Path to the kernel's name for the device node.
+    fn kernel_node(&self, name: &str) -> PathBuf {
+        // e.g. cciss!c0d0 -> cciss/c0d0
+        self.dev.join(name.replace('!', "/"))
+    }
+}
+
+impl BlockDevice {
+    /// Whether this is a whole-disk device not built on other devices.
+    pub fn is_toplevel(&self) -> bool {
+        self.parent.is_none()
+            && list_dir(&self.sysfs_dir.join("slaves")).map_or(true, |s| s.is_empty())
+    }
+
+    /// Return the kernel names of the devices this device is built on.
+    pub fn slaves(&self) -> Result<Vec<String>> {
+        list_dir(&self.sysfs_dir.join("slaves"))
+    }
+
+    /// Return the kernel names of the devices built on this one.
+    pub fn holders(&self) -> Result<Vec<String>> {
+        list_dir(&self.sysfs_dir.join("holders"))
+    }
+}
+
+/// Whether the filesystem on a device has a journal or log that must be
+/// replayed before the filesystem is consistent.
+pub fn needs_recovery(path: &Path) -> Result<bool> {
+    let mut f = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    Ok(probe::probe(&mut f)
+        .with_context(|| format!("probing {}", path.display()))?
+        .map_or(false, |info| info.needs_recovery))
+}
+
+/// Determine the transport of a disk from its sysfs path, falling back
+/// to the bus reported by udev.
+fn transport(disk_dir: &Path, bus: Option<&str>) -> Option<String> {
+    let path = disk_dir.to_string_lossy();
+    let has = |s| path.contains(s);
+    let transport = if has("/devices/virtual/") {
+        return None;
+    } else if has("/nvme/") {
+        "nvme"
+    } else if has("/usb") {
+        "usb"
+    } else if has("/ata") {
+        "sata"
+    } else if has("/mmc_host/") {
+        "mmc"
+    } else if has("/rport-") {
+        "fc"
+    } else if has("/session") {
+        "iscsi"
+    } else if has("/end_device-") {
+        "sas"
+    } else if has("/virtio") && !has("/host") {
+        "virtio"
+    } else if has("/host") && has("/target") {
+        "scsi"
+    } else {
+        match bus? {
+            "ata" => "sata",
+            "usb" => "usb",
+            "scsi" => "scsi",
+            _ => return None,
+        }
+    };
+    Some(transport.into())
+}
+
+/// Read a sysfs attribute, returning None if it doesn't exist or is
+/// empty.
+fn read_attr(dir: &Path, attr: &str) -> Result<Option<String>> {
+    let path = dir.join(attr);
+    match read_to_string(&path) {
+        Ok(s) => {
+            let s = s.trim();
+            Ok(if s.is_empty() { None } else { Some(s.into()) })
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Return the sorted entry names of a directory, or an empty list if it
+/// doesn't exist.
+fn list_dir(dir: &Path) -> Result<Vec<String>> {
+    let iter = match read_dir(dir) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", dir.display())),
+        Ok(it) => it,
+    };
+    let mut ret = Vec::new();
+    for ent in iter {
+        let ent = ent.with_context(|| format!("reading {} entry", dir.display()))?;
+        ret.push(ent.file_name().to_string_lossy().into_owned());
+    }
+    ret.sort();
+    Ok(ret)
+}
+
+fn parse_devnum(s: &str) -> Result<(u64, u64)> {
+    let (maj, min) = s
+        .split_once(':')
+        .with_context(|| format!("invalid device number \"{}\"", s))?;
+    Ok((
+        maj.parse()
+            .with_context(|| format!("invalid major number \"{}\"", maj))?,
+        min.parse()
+            .with_context(|| format!("invalid minor number \"{}\"", min))?,
+    ))
+}
+
+/// Undo the octal escaping of whitespace and backslashes in
+/// /proc/self/mountinfo and /proc/swaps.
+fn unescape_mount_field(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 4 <= bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 4])
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 8).ok())
+            {
+                out.push(b);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> BlkInfo {
+        BlkInfo::with_root(&Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/sysfs/root"))
+    }
+
+    fn names(devs: &[BlockDevice]) -> Vec<&str> {
+        devs.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_devices() {
+        let info = fixture();
+        let devs = info.devices().unwrap();
+        assert_eq!(
+            names(&devs),
+            vec![
+                "loop0",
+                "sda",
+                "sda1",
+                "sda2",
+                "sda3",
+                "sda4",
+                "sdb",
+                "sdc",
+                "sdd",
+                "sdd1",
+                "md127",
+                "sr0",
+                "dm-0",
+                "dm-1",
+                "dm-2",
+                "dm-3",
+                "nvme0n1",
+                "nvme0n1p1",
+                "nvme0n1p2",
+                "nvme0n1p3",
+                "nvme0n1p4"
+            ]
+        );
+        let kinds: Vec<&str> = devs.iter().map(|d| d.kind.as_str()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "loop", "disk", "part", "part", "part", "part", "disk", "disk", "disk", "part",
+                "raid1", "rom", "mpath", "part", "crypt", "lvm", "disk", "part", "part", "part",
+                "part"
+            ]
+        );
+        let toplevel: Vec<&str> = devs
+            .iter()
+            .filter(|d| d.is_toplevel())
+            .map(|d| d.name.as_str())
+            .collect();
+        assert_eq!(
+            toplevel,
+            vec!["loop0", "sda", "sdb", "sdc", "sdd", "sr0", "nvme0n1"]
+        );
+    }
+
+    #[test]
+    fn test_device_properties() {
+        let info = fixture();
+        let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/sysfs/root/dev");
+
+        let sda = info.device_by_name("sda").unwrap();
+        assert_eq!(sda.path, root.join("sda").to_str().unwrap());
+        assert_eq!((sda.major, sda.minor), (8, 0));
+        assert_eq!(sda.size, 16 * 1024 * 1024 * 1024);
+        assert!(sda.rotational);
+        assert_eq!(sda.sector_size, Some(512));
+        assert!(!sda.removable);
+        assert!(!sda.read_only);
+        assert_eq!(sda.model.as_deref(), Some("QEMU HARDDISK"));
+        assert_eq!(sda.serial.as_deref(), Some("drive-scsi0"));
+        assert_eq!(sda.wwn, None);
+        assert_eq!(sda.transport.as_deref(), Some("scsi"));
+        assert_eq!(sda.fstype, None);
+
+        let sda2 = info.device_by_name("sda2").unwrap();
+        assert_eq!(sda2.fstype.as_deref(), Some("vfat"));
+        assert_eq!(sda2.label.as_deref(), Some("EFI-SYSTEM"));
+        assert_eq!(sda2.uuid.as_deref(), Some("1B2D-4F1A"));
+        assert_eq!(sda2.partlabel.as_deref(), Some("EFI-SYSTEM"));
+        assert_eq!(
+            sda2.parttype.as_deref(),
+            Some("c12a7328-f81f-11d2-ba4b-00a0c93ec93b")
+        );
+        // inherited from disk
+        assert!(sda2.rotational);
+        assert_eq!(sda2.sector_size, Some(512));
+        assert_eq!(sda2.model.as_deref(), Some("QEMU HARDDISK"));
+        assert!(sda2.mountpoints.is_empty());
+
+        let sda3 = info.device_by_name("sda3").unwrap();
+        assert_eq!(sda3.label.as_deref(), Some("boot label"));
+        assert_eq!(sda3.mountpoints, vec!["/boot"]);
+        let sda4 = info.device_by_name("sda4").unwrap();
+        assert_eq!(sda4.mountpoints, vec!["/sysroot", "/var/my dir"]);
+
+        let nvme = info.device_by_name("nvme0n1").unwrap();
+        assert!(!nvme.rotational);
+        assert_eq!(nvme.sector_size, Some(4096));
+        assert_eq!(nvme.model.as_deref(), Some("Samsung SSD 970 EVO 500GB"));
+        assert_eq!(nvme.serial.as_deref(), Some("S466NX0M123456"));
+        assert_eq!(nvme.wwn.as_deref(), Some("eui.0025385391b0e1a2"));
+        assert_eq!(nvme.transport.as_deref(), Some("nvme"));
+
+        let usb = info.device_by_name("sdd1").unwrap();
+        assert!(usb.removable);
+        assert_eq!(usb.transport.as_deref(), Some("usb"));
+        assert_eq!(usb.model.as_deref(), Some("Flash Drive"));
+        let swap = info.device_by_name("nvme0n1p4").unwrap();
+        assert!(swap.swap);
+        assert_eq!(swap.fstype.as_deref(), Some("swap"));
+
+        let mpath = info.device_by_name("dm-0").unwrap();
+        assert_eq!(mpath.path, root.join("mapper/mpatha").to_str().unwrap());
+        assert_eq!(mpath.transport, None);
+        let crypt = info.device_by_name("dm-2").unwrap();
+        assert_eq!(crypt.path, root.join("mapper/luks-root").to_str().unwrap());
+        assert_eq!(crypt.kind, "crypt");
+        let lv = info.device_by_name("dm-3").unwrap();
+        assert_eq!(lv.kind, "lvm");
+        assert_eq!(lv.mountpoints, vec!["/var"]);
+
+        let sr0 = info.device_by_name("sr0").unwrap();
+        assert!(sr0.removable);
+        assert!(sr0.read_only);
+        assert_eq!(sr0.sector_size, Some(2048));
+
+        // by number and by path
+        assert_eq!(info.device_by_number(8, 3).unwrap(), sda3);
+        assert_eq!(info.device(&root.join("sda3")).unwrap(), sda3);
+        assert_eq!(info.device(&root.join("mapper/luks-root")).unwrap(), crypt);
+        info.device_by_name("sdz").unwrap_err();
+        info.device_by_number(8, 100).unwrap_err();
+        info.device(&root.join("nonexistent")).unwrap_err();
+    }
+
+    #[test]
+    fn test_probe_without_udev() {
+        // loop0 has no udev record, so its superblock is probed on request
+        let info = fixture();
+        let loop0 = info.device_by_name("loop0").unwrap();
+        assert!(loop0.udev.is_none());
+        assert_eq!(loop0.fstype, None);
+        let loop0 = info.with_fs_info(loop0);
+        assert_eq!(loop0.fstype.as_deref(), Some("xfs"));
+        assert_eq!(loop0.label.as_deref(), Some("loopfs"));
+        assert_eq!(
+            loop0.uuid.as_deref(),
+            Some("a8f3b6f2-03c1-4c8e-9a7e-2f9b5c1d0e11")
+        );
+    }
+
+    #[test]
+    fn test_trees() {
+        let info = fixture();
+        let sda = info.device_by_name("sda").unwrap();
+        assert_eq!(
+            names(&info.tree(&sda).unwrap()),
+            vec!["sda", "sda1", "sda2", "sda3", "sda4"]
+        );
+        let sdb = info.device_by_name("sdb").unwrap();
+        assert_eq!(
+            names(&info.tree(&sdb).unwrap()),
+            vec!["sdb", "dm-0", "dm-1"]
+        );
+        let nvme = info.device_by_name("nvme0n1").unwrap();
+        // md127 and dm-3 are held by two devices but only reported once
+        assert_eq!(
+            names(&info.tree(&nvme).unwrap()),
+            vec![
+                "nvme0n1",
+                "nvme0n1p1",
+                "dm-2",
+                "dm-3",
+                "nvme0n1p2",
+                "md127",
+                "nvme0n1p3",
+                "nvme0n1p4"
+            ]
+        );
+
+        let part = info.device_by_name("dm-1").unwrap();
+        assert_eq!(
+            names(&info.inverse_tree(&part).unwrap()),
+            vec!["dm-1", "dm-0", "sdb", "sdc"]
+        );
+        let md = info.device_by_name("md127").unwrap();
+        assert_eq!(
+            names(&info.inverse_tree(&md).unwrap()),
+            vec!["md127", "nvme0n1p2", "nvme0n1", "nvme0n1p3"]
+        );
+        assert_eq!(names(&info.parents(&sda).unwrap()), Vec::<&str>::new());
+        assert_eq!(md.slaves().unwrap(), vec!["nvme0n1p2", "nvme0n1p3"]);
+        assert_eq!(
+            info.device_by_name("sdb").unwrap().holders().unwrap(),
+            vec!["dm-0"]
+        );
+    }
+
+    #[test]
+    fn test_transport() {
+        let t = |path: &str, bus| transport(Path::new(path), bus);
+        assert_eq!(
+            t(
+                "/sys/devices/pci0000:00/0000:00:1f.2/ata1/host0/target0:0:0/0:0:0:0/block/sda",
+                None
+            )
+            .as_deref(),
+            Some("sata")
+        );
+        assert_eq!(
+            t(
+                "/sys/devices/pci0000:00/0000:00:04.0/virtio1/block/vda",
+                None
+            )
+            .as_deref(),
+            Some("virtio")
+        );
+        assert_eq!(
+            t(
+                "/sys/devices/pci0000:00/0000:00:05.0/virtio2/host0/target0:0:0/0:0:0:0/block/sda",
+                None
+            )
+            .as_deref(),
+            Some("scsi")
+        );
+        assert_eq!(
+            t("/sys/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb", None).as_deref(),
+            Some("usb")
+        );
+        assert_eq!(
+            t(
+                "/sys/devices/pci0000:00/0000:00:1d.0/0000:3d:00.0/nvme/nvme0/nvme0n1",
+                None
+            )
+            .as_deref(),
+            Some("nvme")
+        );
+        assert_eq!(
+            t(
+                "/sys/devices/platform/soc/mmc_host/mmc0/mmc0:0001/block/mmcblk0",
+                None
+            )
+            .as_deref(),
+            Some("mmc")
+        );
+        assert_eq!(t("/sys/devices/virtual/block/dm-0", Some("ata")), None);
+        assert_eq!(
+            t("/sys/devices/platform/foo/block/bar", Some("ata")).as_deref(),
+            Some("sata")
+        );
+        assert_eq!(
+            t("/sys/devices/platform/foo/block/bar", Some("other")),
+            None
+        );
+        assert_eq!(t("/sys/devices/platform/foo/block/bar", None), None);
+    }
+
+    #[test]
+    fn test_parse_devnum() {
+        assert_eq!(parse_devnum("8:1").unwrap(), (8, 1));
+        assert_eq!(parse_devnum("259:10").unwrap(), (259, 10));
+        parse_devnum("8").unwrap_err();
+        parse_devnum("8:x").unwrap_err();
+        parse_devnum("").unwrap_err();
+    }
+
+    #[test]
+    fn test_unescape_mount_field() {
+        assert_eq!(unescape_mount_field("/var/my\\040dir"), "/var/my dir");
+        assert_eq!(unescape_mount_field("a\\011b\\134c"), "a\tb\\c");
+        assert_eq!(unescape_mount_field("a\\09"), "a\\09");
+        assert_eq!(unescape_mount_field("/"), "/");
+    }
+}
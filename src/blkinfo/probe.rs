@@ -0,0 +1,365 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Superblock probing for the filesystems and volume formats we care
+//! about.  This covers a small subset of what libblkid detects; devices
+//! with other formats fall back to the udev database.
+
+use anyhow::{Context, Result};
+use std::io::{ErrorKind, Read};
+use uuid::Uuid;
+
+/// Enough to cover the btrfs superblock at 64 KiB.
+const PROBE_SIZE: usize = 65536 + 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsInfo {
+    pub fstype: String,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    /// The journal or log must be replayed before the filesystem is
+    /// consistent.  Only known for ext3/4 and btrfs; XFS doesn't record
+    /// this in the superblock.
+    pub needs_recovery: bool,
+}
+
+/// Read the start of a device and identify its filesystem.
+pub fn probe(dev: &mut impl Read) -> Result<Option<FsInfo>> {
+    let mut buf = vec![0u8; PROBE_SIZE];
+    let mut len = 0;
+    while len < buf.len() {
+        match dev.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e).context("reading superblock"),
+        }
+    }
+    buf.truncate(len);
+    Ok(probe_buf(&buf))
+}
+
+type Prober = fn(&[u8]) -> Option<FsInfo>;
+
+fn probe_buf(buf: &[u8]) -> Option<FsInfo> {
+    // probe formats with strong magic first; FAT goes last
+    let probes: &[Prober] = &[
+        probe_luks,
+        probe_xfs,
+        probe_btrfs,
+        probe_ext,
+        probe_swap,
+        probe_iso9660,
+        probe_vfat,
+    ];
+    probes.iter().find_map(|probe| probe(buf))
+}
+
+fn probe_luks(buf: &[u8]) -> Option<FsInfo> {
+    if slice(buf, 0, 6)? != b"LUKS\xba\xbe" {
+        return None;
+    }
+    let version = u16::from_be_bytes(slice(buf, 6, 2)?.try_into().ok()?);
+    Some(FsInfo {
+        fstype: "crypto_LUKS".into(),
+        label: match version {
+            2 => string(slice(buf, 24, 48)?),
+            _ => None,
+        },
+        uuid: string(slice(buf, 168, 40)?),
+        needs_recovery: false,
+    })
+}
+
+fn probe_xfs(buf: &[u8]) -> Option<FsInfo> {
+    if slice(buf, 0, 4)? != b"XFSB" {
+        return None;
+    }
+    Some(FsInfo {
+        fstype: "xfs".into(),
+        label: string(slice(buf, 108, 12)?),
+        uuid: uuid(slice(buf, 32, 16)?),
+        needs_recovery: false,
+    })
+}
+
+fn probe_btrfs(buf: &[u8]) -> Option<FsInfo> {
+    const SB: usize = 65536;
+    if slice(buf, SB + 64, 8)? != b"_BHRfS_M" {
+        return None;
+    }
+    Some(FsInfo {
+        fstype: "btrfs".into(),
+        label: string(slice(buf, SB + 299, 256)?),
+        uuid: uuid(slice(buf, SB + 32, 16)?),
+        // log tree root
+        needs_recovery: slice(buf, SB + 96, 8)? != [0; 8],
+    })
+}
+
+fn probe_ext(buf: &[u8]) -> Option<FsInfo> {
+    const SB: usize = 1024;
+    const COMPAT_HAS_JOURNAL: u32 = 0x4;
+    const INCOMPAT_RECOVER: u32 = 0x4;
+    // features that ext3 doesn't support: extents, 64bit, flex_bg,
+    // inline data
+    const INCOMPAT_EXT4: u32 = 0x40 | 0x80 | 0x200 | 0x8000;
+    if slice(buf, SB + 56, 2)? != [0x53, 0xef] {
+        return None;
+    }
+    let compat = u32::from_le_bytes(slice(buf, SB + 92, 4)?.try_into().ok()?);
+    let incompat = u32::from_le_bytes(slice(buf, SB + 96, 4)?.try_into().ok()?);
+    let fstype = if incompat & INCOMPAT_EXT4 != 0 {
+        "ext4"
+    } else if compat & COMPAT_HAS_JOURNAL != 0 {
+        "ext3"
+    } else {
+        "ext2"
+    };
+    Some(FsInfo {
+        fstype: fstype.into(),
+        label: string(slice(buf, SB + 120, 16)?),
+        uuid: uuid(slice(buf, SB + 104, 16)?),
+        needs_recovery: incompat & INCOMPAT_RECOVER != 0,
+    })
+}
+
+fn probe_swap(buf: &[u8]) -> Option<FsInfo> {
+    // the signature is at the end of the first page, and the page size
+    // of the system that created it isn't recorded
+    [4096, 8192, 16384, 65536]
+        .iter()
+        .find(|&&page| {
+            matches!(
+                slice(buf, page - 10, 10),
+                Some(b"SWAPSPACE2") | Some(b"SWAP-SPACE")
+            )
+        })
+        .and_then(|_| {
+            Some(FsInfo {
+                fstype: "swap".into(),
+                label: string(slice(buf, 1024 + 28, 16)?),
+                uuid: uuid(slice(buf, 1024 + 12, 16)?),
+                needs_recovery: false,
+            })
+        })
+}
+
+fn probe_iso9660(buf: &[u8]) -> Option<FsInfo> {
+    const PVD: usize = 32768;
+    if slice(buf, PVD + 1, 5)? != b"CD001" {
+        return None;
+    }
+    // libblkid derives the UUID from the volume creation timestamp
+    let created = slice(buf, PVD + 813, 16)?;
+    let uuid = if created.iter().all(|&b| b == b'0' || b == 0) {
+        None
+    } else {
+        let c = std::str::from_utf8(created).ok()?;
+        Some(format!(
+            "{}-{}-{}-{}-{}-{}-{}",
+            &c[0..4],
+            &c[4..6],
+            &c[6..8],
+            &c[8..10],
+            &c[10..12],
+            &c[12..14],
+            &c[14..16]
+        ))
+    };
+    Some(FsInfo {
+        fstype: "iso9660".into(),
+        label: string(slice(buf, PVD + 40, 32)?),
+        uuid,
+        needs_recovery: false,
+    })
+}
+
+fn probe_vfat(buf: &[u8]) -> Option<FsInfo> {
+    if slice(buf, 510, 2)? != [0x55, 0xaa] {
+        return None;
+    }
+    let (serial_offset, label_offset) = if slice(buf, 82, 5)? == b"FAT32" {
+        (67, 71)
+    } else if slice(buf, 54, 4)? == b"FAT1" {
+        (39, 43)
+    } else {
+        return None;
+    };
+    let serial = u32::from_le_bytes(slice(buf, serial_offset, 4)?.try_into().ok()?);
+    Some(FsInfo {
+        fstype: "vfat".into(),
+        label: string(slice(buf, label_offset, 11)?).filter(|l| l != "NO NAME"),
+        uuid: Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xffff)),
+        needs_recovery: false,
+    })
+}
+
+fn slice(buf: &[u8], offset: usize, len: usize) -> Option<&[u8]> {
+    buf.get(offset..offset + len)
+}
+
+/// Convert a NUL- or space-padded string field, returning None if empty.
+fn string(field: &[u8]) -> Option<String> {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    let s = String::from_utf8_lossy(&field[..end]);
+    let s = s.trim_end_matches(' ');
+    if s.is_empty() {
+        None
+    } else {
+        Some(s.into())
+    }
+}
+
+/// Format a binary UUID, returning None if it's all zeroes.
+fn uuid(field: &[u8]) -> Option<String> {
+    if field.iter().all(|&b| b == 0) {
+        return None;
+    }
+    Uuid::from_slice(field).ok().map(|u| u.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID_BYTES: [u8; 16] = [
+        0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc, 0xde, 0xf0, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd,
+        0xef,
+    ];
+    const UUID_STR: &str = "12345678-9abc-def0-0123-456789abcdef";
+
+    fn put(buf: &mut [u8], offset: usize, data: &[u8]) {
+        buf[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn info(fstype: &str, label: Option<&str>, uuid: Option<&str>) -> Option<FsInfo> {
+        Some(FsInfo {
+            fstype: fstype.into(),
+            label: label.map(String::from),
+            uuid: uuid.map(String::from),
+            needs_recovery: false,
+        })
+    }
+
+    #[test]
+    fn test_probe_empty() {
+        assert_eq!(probe(&mut &[][..]).unwrap(), None);
+        assert_eq!(probe(&mut &vec![0u8; PROBE_SIZE][..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_probe_xfs() {
+        let mut buf = vec![0u8; 4096];
+        put(&mut buf, 0, b"XFSB");
+        put(&mut buf, 32, &UUID_BYTES);
+        put(&mut buf, 108, b"root");
+        assert_eq!(probe_buf(&buf), info("xfs", Some("root"), Some(UUID_STR)));
+    }
+
+    #[test]
+    fn test_probe_ext() {
+        let mut buf = vec![0u8; 4096];
+        put(&mut buf, 1024 + 56, &[0x53, 0xef]);
+        put(&mut buf, 1024 + 104, &UUID_BYTES);
+        put(&mut buf, 1024 + 120, b"boot");
+        assert_eq!(probe_buf(&buf), info("ext2", Some("boot"), Some(UUID_STR)));
+        put(&mut buf, 1024 + 92, &4u32.to_le_bytes());
+        assert_eq!(probe_buf(&buf).unwrap().fstype, "ext3");
+        put(&mut buf, 1024 + 96, &0x2c2u32.to_le_bytes());
+        assert_eq!(probe_buf(&buf).unwrap().fstype, "ext4");
+        assert!(!probe_buf(&buf).unwrap().needs_recovery);
+        put(&mut buf, 1024 + 96, &0x2c6u32.to_le_bytes());
+        assert!(probe_buf(&buf).unwrap().needs_recovery);
+        // truncated device
+        assert_eq!(probe_buf(&buf[..1100]), None);
+    }
+
+    #[test]
+    fn test_probe_btrfs() {
+        let mut buf = vec![0u8; PROBE_SIZE];
+        put(&mut buf, 65536 + 64, b"_BHRfS_M");
+        put(&mut buf, 65536 + 32, &UUID_BYTES);
+        assert_eq!(probe_buf(&buf), info("btrfs", None, Some(UUID_STR)));
+        put(&mut buf, 65536 + 96, &0x1d4000u64.to_le_bytes());
+        assert!(probe_buf(&buf).unwrap().needs_recovery);
+    }
+
+    #[test]
+    fn test_probe_swap() {
+        for page in [4096, 65536] {
+            let mut buf = vec![0u8; PROBE_SIZE];
+            put(&mut buf, page - 10, b"SWAPSPACE2");
+            put(&mut buf, 1024 + 12, &UUID_BYTES);
+            put(&mut buf, 1024 + 28, b"swap");
+            assert_eq!(probe_buf(&buf), info("swap", Some("swap"), Some(UUID_STR)));
+        }
+    }
+
+    #[test]
+    fn test_probe_luks() {
+        let mut buf = vec![0u8; 4096];
+        put(&mut buf, 0, b"LUKS\xba\xbe\x00\x02");
+        put(&mut buf, 24, b"crypt");
+        put(&mut buf, 168, UUID_STR.as_bytes());
+        assert_eq!(
+            probe_buf(&buf),
+            info("crypto_LUKS", Some("crypt"), Some(UUID_STR))
+        );
+        put(&mut buf, 6, b"\x00\x01");
+        assert_eq!(probe_buf(&buf), info("crypto_LUKS", None, Some(UUID_STR)));
+    }
+
+    #[test]
+    fn test_probe_iso9660() {
+        let mut buf = vec![0u8; 36864];
+        put(&mut buf, 32769, b"CD001");
+        put(
+            &mut buf,
+            32808,
+            format!("{:32}", "fedora-coreos-35").as_bytes(),
+        );
+        put(&mut buf, 32768 + 813, b"2022021619562800");
+        assert_eq!(
+            probe_buf(&buf),
+            info(
+                "iso9660",
+                Some("fedora-coreos-35"),
+                Some("2022-02-16-19-56-28-00")
+            )
+        );
+    }
+
+    #[test]
+    fn test_probe_vfat() {
+        let mut buf = vec![0u8; 4096];
+        put(&mut buf, 510, &[0x55, 0xaa]);
+        assert_eq!(probe_buf(&buf), None);
+        // FAT16
+        put(&mut buf, 54, b"FAT16   ");
+        put(&mut buf, 39, &0x1234abcdu32.to_le_bytes());
+        put(&mut buf, 43, b"NO NAME    ");
+        assert_eq!(probe_buf(&buf), info("vfat", None, Some("1234-ABCD")));
+        // FAT32
+        let mut buf = vec![0u8; 4096];
+        put(&mut buf, 510, &[0x55, 0xaa]);
+        put(&mut buf, 82, b"FAT32   ");
+        put(&mut buf, 67, &0xb5f4c2d3u32.to_le_bytes());
+        put(&mut buf, 71, b"EFI-SYSTEM ");
+        assert_eq!(
+            probe_buf(&buf),
+            info("vfat", Some("EFI-SYSTEM"), Some("B5F4-C2D3"))
+        );
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reader for the udev database in /run/udev/data.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/// The udev database record for a device.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UdevRecord {
+    /// Device properties (`E:` lines).
+    pub properties: HashMap<String, String>,
+    /// Symlinks to the device node, relative to /dev (`S:` lines).
+    pub symlinks: Vec<String>,
+}
+
+impl UdevRecord {
+    /// Read the record for block device `maj:min` from the database
+    /// directory.  Returns None if udev has no record for the device.
+    pub fn read(data_dir: &Path, major: u64, minor: u64) -> Result<Option<Self>> {
+        let path = data_dir.join(format!("b{}:{}", major, minor));
+        match read_to_string(&path) {
+            Ok(data) => Ok(Some(Self::parse(&data))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+        }
+    }
+
+    fn parse(data: &str) -> Self {
+        let mut record = Self::default();
+        for line in data.lines() {
+            if let Some(prop) = line.strip_prefix("E:") {
+                if let Some((key, value)) = prop.split_once('=') {
+                    record.properties.insert(key.into(), value.into());
+                }
+            } else if let Some(link) = line.strip_prefix("S:") {
+                record.symlinks.push(link.into());
+            }
+        }
+        record
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.properties
+            .get(key)
+            .map(|v| v.as_str())
+            .filter(|v| !v.is_empty())
+    }
+
+    /// Get a property, preferring the `_ENC` variant (which udev escapes
+    /// with `\xHH` sequences rather than mangling) if present.
+    pub fn get_decoded(&self, key: &str) -> Option<String> {
+        match self.get(&format!("{}_ENC", key)) {
+            Some(v) => Some(decode(v)),
+            None => self.get(key).map(String::from),
+        }
+    }
+}
+
+/// Decode `\xHH` escape sequences in a udev property value.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x') && i + 4 <= bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 2..i + 4])
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+            {
+                out.push(b);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let record = UdevRecord::parse(
+            "S:disk/by-label/my\\x20label\nS:disk/by-id/abc\nW:26\nI:123\nE:ID_FS_LABEL=my_label\nE:ID_FS_LABEL_ENC=my\\x20label\nE:ID_FS_UUID=\nE:EQUALS=a=b\nG:systemd\n",
+        );
+        assert_eq!(
+            record.symlinks,
+            vec!["disk/by-label/my\\x20label", "disk/by-id/abc"]
+        );
+        assert_eq!(record.get("ID_FS_LABEL"), Some("my_label"));
+        assert_eq!(
+            record.get_decoded("ID_FS_LABEL").as_deref(),
+            Some("my label")
+        );
+        assert_eq!(record.get("ID_FS_UUID"), None);
+        assert_eq!(record.get_decoded("ID_FS_UUID"), None);
+        assert_eq!(record.get("EQUALS"), Some("a=b"));
+        assert_eq!(record.properties.len(), 4);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode("abc"), "abc");
+        assert_eq!(decode("a\\x20b\\x2fc"), "a b/c");
+        assert_eq!(decode("\\xc3\\xa9"), "é");
+        assert_eq!(decode("a\\x2"), "a\\x2");
+        assert_eq!(decode("a\\xzz"), "a\\xzz");
+        assert_eq!(decode("\\"), "\\");
+    }
+}
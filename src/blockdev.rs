@@ -17,11 +17,8 @@ use gptman::{GPTPartitionEntry, GPT};
 use nix::sys::stat::{major, minor};
 use nix::{errno::Errno, mount, sched};
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
-use std::fs::{
-    canonicalize, metadata, read_dir, read_to_string, remove_dir, symlink_metadata, File,
-    OpenOptions,
-};
+use std::collections::HashSet;
+use std::fs::{canonicalize, metadata, read_dir, read_to_string, remove_dir, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::num::{NonZeroU32, NonZeroU64};
 use std::os::linux::fs::MetadataExt;
@@ -34,7 +31,8 @@ use std::thread::sleep;
 use std::time::Duration;
 use uuid::Uuid;
 
-use crate::cmdline::PartitionFilter;
+use crate::blkinfo::{needs_recovery, BlkInfo, BlockDevice};
+use crate::cmdline::{DeviceMatch, DeviceMatchTerm, DeviceSelect, PartitionFilter};
 use crate::util::*;
 
 use crate::{runcmd, runcmd_output};
@@ -65,6 +63,20 @@ impl Disk {
     }
 
     pub fn mount_partition_by_label(&self, label: &str, flags: mount::MsFlags) -> Result<Mount> {
+        let (path, fstype) = self.find_partition_by_label(label)?;
+        Mount::try_mount(&path, &fstype, flags)
+    }
+
+    /// Mount the partition with the specified label read-only, without
+    /// replaying its journal.  See `Mount::try_mount_readonly()`.
+    pub fn mount_partition_by_label_readonly(&self, label: &str) -> Result<Mount> {
+        let (path, fstype) = self.find_partition_by_label(label)?;
+        Mount::try_mount_readonly(&path, &fstype)
+    }
+
+    /// Find the partition with the specified label and return its path
+    /// and filesystem type.
+    fn find_partition_by_label(&self, label: &str) -> Result<(String, String)> {
         // get partition list
         let partitions = self.get_partitions()?;
         if partitions.is_empty() {
@@ -86,9 +98,8 @@ impl Disk {
             ),
         };
 
-        // mount it
         match &part.fstype {
-            Some(fstype) => Mount::try_mount(&part.path, fstype, flags),
+            Some(fstype) => Ok((part.path.clone(), fstype.clone())),
             None => bail!(
                 "couldn't get filesystem type of {} device for {}",
                 label,
@@ -98,29 +109,25 @@ impl Disk {
     }
 
     fn get_partitions(&self) -> Result<Vec<Partition>> {
-        // walk each device in the output
+        let info = BlkInfo::default();
+        let disk = info.device(Path::new(&self.path))?;
+        // walk each device in the tree
         let mut result: Vec<Partition> = Vec::new();
-        for devinfo in lsblk(Path::new(&self.path), true)? {
-            if let Some(name) = devinfo.get("NAME") {
-                // Only return partitions.  Skip the whole-disk device, as well
-                // as holders like LVM or RAID devices using one of the partitions.
-                if devinfo.get("TYPE").map(|s| s.as_str()) != Some("part") {
-                    continue;
-                }
-                let (mountpoint, swap) = match devinfo.get("MOUNTPOINT") {
-                    Some(mp) if mp == "[SWAP]" => (None, true),
-                    Some(mp) => (Some(mp.to_string()), false),
-                    None => (None, false),
-                };
-                result.push(Partition {
-                    path: name.to_owned(),
-                    label: devinfo.get("LABEL").map(<_>::to_string),
-                    fstype: devinfo.get("FSTYPE").map(<_>::to_string),
-                    parent: self.path.to_owned(),
-                    mountpoint,
-                    swap,
-                });
+        for dev in info.tree(&disk)? {
+            // Only return partitions.  Skip the whole-disk device, as well
+            // as holders like LVM or RAID devices using one of the partitions.
+            if dev.kind != "part" {
+                continue;
             }
+            let dev = info.with_fs_info(dev);
+            result.push(Partition {
+                path: dev.path,
+                label: dev.label,
+                fstype: dev.fstype,
+                parent: self.path.to_owned(),
+                mountpoint: dev.mountpoints.into_iter().next(),
+                swap: dev.swap,
+            });
         }
         Ok(result)
     }
@@ -341,63 +348,14 @@ impl Partition {
         Ok((start_offset, end_offset))
     }
 
+    /// Return the paths of the devices built on this partition.
     pub fn get_holders(&self) -> Result<Vec<String>> {
-        let holders = self.get_sysfs_dir()?.join("holders");
-        let mut ret: Vec<String> = Vec::new();
-        for ent in read_dir(&holders).with_context(|| format!("reading {}", &holders.display()))? {
-            let ent = ent.with_context(|| format!("reading {} entry", &holders.display()))?;
-            ret.push(format!("/dev/{}", ent.file_name().to_string_lossy()));
-        }
-        Ok(ret)
-    }
-
-    // Try to locate the device directory in sysfs.
-    fn get_sysfs_dir(&self) -> Result<PathBuf> {
-        let basedir = Path::new("/sys/block");
-
-        // First assume we have a regular partition.
-        // /sys/block/sda/sda1
-        let devdir = basedir
-            .join(
-                Path::new(&self.parent)
-                    .file_name()
-                    .with_context(|| format!("parent {} has no filename", self.parent))?,
-            )
-            .join(
-                Path::new(&self.path)
-                    .file_name()
-                    .with_context(|| format!("path {} has no filename", self.path))?,
-            );
-        if devdir.exists() {
-            return Ok(devdir);
-        }
-
-        // Now assume a kpartx "partition", where the path is a symlink to
-        // an unpartitioned DM device node.
-        // /sys/block/dm-1
-        let is_link = symlink_metadata(&self.path)
-            .with_context(|| format!("reading metadata for {}", self.path))?
-            .file_type()
-            .is_symlink();
-        if is_link {
-            let target = canonicalize(&self.path)
-                .with_context(|| format!("getting absolute path to {}", self.path))?;
-            let devdir = basedir.join(
-                target
-                    .file_name()
-                    .with_context(|| format!("target {} has no filename", target.display()))?,
-            );
-            if devdir.exists() {
-                return Ok(devdir);
-            }
-        }
-
-        // Give up
-        bail!(
-            "couldn't find /sys/block directory for partition {} of {}",
-            &self.path,
-            &self.parent
-        );
+        let info = BlkInfo::default();
+        info.device(Path::new(&self.path))?
+            .holders()?
+            .iter()
+            .map(|h| Ok(info.device_by_name(h)?.path))
+            .collect()
     }
 }
 
@@ -411,6 +369,32 @@ pub struct Mount {
 
 impl Mount {
     pub fn try_mount(device: &str, fstype: &str, flags: mount::MsFlags) -> Result<Mount> {
+        Self::try_mount_with_data(device, fstype, flags, None)
+    }
+
+    /// Mount a filesystem read-only for examination.  A read-only mount
+    /// still replays a dirty journal, writing to the device, so disable
+    /// journal replay, and refuse filesystems whose superblock says they
+    /// need it, since their contents may be inconsistent.
+    pub fn try_mount_readonly(device: &str, fstype: &str) -> Result<Mount> {
+        if needs_recovery(Path::new(device))? {
+            bail!("filesystem on {} needs journal recovery", device);
+        }
+        let data = match fstype {
+            "ext3" | "ext4" => Some("noload"),
+            "xfs" => Some("norecovery"),
+            "btrfs" => Some("nologreplay"),
+            _ => None,
+        };
+        Self::try_mount_with_data(device, fstype, mount::MsFlags::MS_RDONLY, data)
+    }
+
+    fn try_mount_with_data(
+        device: &str,
+        fstype: &str,
+        flags: mount::MsFlags,
+        data: Option<&str>,
+    ) -> Result<Mount> {
         let tempdir = tempfile::Builder::new()
             .prefix("coreos-installer-")
             .tempdir()
@@ -424,7 +408,7 @@ impl Mount {
         // should be safe.
         sched::unshare(sched::CloneFlags::CLONE_NEWNS).context("unsharing mount namespace")?;
 
-        mount::mount::<str, Path, str, str>(Some(device), &mountpoint, Some(fstype), flags, None)
+        mount::mount::<str, Path, str, str>(Some(device), &mountpoint, Some(fstype), flags, data)
             .with_context(|| format!("mounting device {} on {}", device, mountpoint.display()))?;
 
         Ok(Mount {
@@ -466,10 +450,9 @@ impl Mount {
     }
 
     pub fn get_filesystem_uuid(&self) -> Result<String> {
-        let devinfo = lsblk_single(Path::new(&self.device))?;
-        devinfo
-            .get("UUID")
-            .map(String::from)
+        let info = BlkInfo::default();
+        info.with_fs_info(info.device(Path::new(&self.device))?)
+            .uuid
             .with_context(|| format!("filesystem {} has no UUID", self.device))
     }
 }
@@ -772,137 +755,68 @@ fn read_sysfs_dev_block_value(maj: u64, min: u64, field: &str) -> Result<String>
     Ok(read_to_string(&path)?.trim_end().into())
 }
 
-pub fn lsblk_single(dev: &Path) -> Result<HashMap<String, String>> {
-    let mut devinfos = lsblk(Path::new(dev), false)?;
-    if devinfos.is_empty() {
-        // this should never happen because `lsblk` itself would've failed
-        bail!("no lsblk results for {}", dev.display());
-    }
-    Ok(devinfos.remove(0))
-}
-
-/// Returns all available filesystems.
+/// Returns all available filesystems, probing their superblocks so the
+/// results are current.
 /// rereadpt mitigates possible issue with outdated UUIDs on different
 /// paths to the same disk: after 'ignition-ostree-firstboot-uuid'
 /// '/dev/sdaX' path gets new UUID, but '/dev/sdbX/' path has an old one
-fn get_all_filesystems(rereadpt: bool) -> Result<Vec<HashMap<String, String>>> {
+fn get_all_filesystems(info: &BlkInfo, rereadpt: bool) -> Result<Vec<BlockDevice>> {
     if rereadpt {
-        let mut cmd = Command::new("lsblk");
-        cmd.arg("--noheadings")
-            .arg("--nodeps")
-            .arg("--list")
-            .arg("--paths")
-            .arg("--output")
-            .arg("NAME");
-        let output = cmd_output(&mut cmd)?;
-        for dev in output.lines() {
-            if let Ok(mut fd) = std::fs::File::open(dev) {
+        for dev in info.devices()?.iter().filter(|d| d.is_toplevel()) {
+            if let Ok(mut fd) = std::fs::File::open(&dev.path) {
                 // best-effort reread of disk that may have busy partitions; don't retry
                 let _ = reread_partition_table(&mut fd, false);
             }
         }
         udev_settle()?;
     }
-    blkid()
+    let mut result = Vec::new();
+    for mut dev in info.devices()? {
+        // best-effort, like blkid; fall back to the udev database
+        let _ = info.probe(&mut dev);
+        if dev.fstype.is_some() {
+            result.push(dev);
+        }
+    }
+    Ok(result)
 }
 
 /// Returns filesystems with given label.
 /// If multiple filesystems with the label have the same UUID, we only return one of them.
 pub fn get_filesystems_with_label(label: &str, rereadpt: bool) -> Result<Vec<String>> {
+    filesystems_with_label(&BlkInfo::default(), label, rereadpt)
+}
+
+fn filesystems_with_label(info: &BlkInfo, label: &str, rereadpt: bool) -> Result<Vec<String>> {
     let mut uuids = HashSet::new();
-    let result = get_all_filesystems(rereadpt)?
-        .iter()
-        .filter(|v| v.get("LABEL").map(|l| l.as_str()) == Some(label))
-        .filter(|v| match v.get("UUID") {
-            Some(uuid) => {
-                if !uuid.is_empty() {
-                    uuids.insert(uuid)
-                } else {
-                    true
-                }
-            }
+    let result = get_all_filesystems(info, rereadpt)?
+        .into_iter()
+        .filter(|d| d.label.as_deref() == Some(label))
+        .filter(|d| match &d.uuid {
+            Some(uuid) => uuids.insert(uuid.clone()),
             None => true,
         })
-        .filter_map(|v| v.get("NAME").map(<_>::to_owned))
+        .map(|d| d.path)
         .collect();
     Ok(result)
 }
 
-pub fn lsblk(dev: &Path, with_deps: bool) -> Result<Vec<HashMap<String, String>>> {
-    let mut cmd = Command::new("lsblk");
-    // Older lsblk, e.g. in CentOS 7.6, doesn't support PATH, but --paths option
-    cmd.arg("--pairs")
-        .arg("--paths")
-        .arg("--output")
-        .arg("NAME,LABEL,FSTYPE,TYPE,MOUNTPOINT,UUID")
-        .arg(dev);
-    if !with_deps {
-        cmd.arg("--nodeps");
-    }
-    let output = cmd_output(&mut cmd)?;
-    let mut result: Vec<HashMap<String, String>> = Vec::new();
-    for line in output.lines() {
-        // parse key-value pairs
-        result.push(split_lsblk_line(line));
-    }
-    Ok(result)
-}
-
-/// Parse key-value pairs from blkid.
-fn split_blkid_line(line: &str) -> HashMap<String, String> {
-    let (name, data) = match line.find(':') {
-        Some(n) => line.split_at(n),
-        None => return HashMap::new(),
-    };
-
-    let (name, data) = (name.trim(), data[1..].trim());
-    if name.is_empty() {
-        return HashMap::new();
-    }
-
-    let mut fields = split_lsblk_line(data);
-    fields.insert("NAME".to_string(), name.to_string());
-    fields
-}
-
-fn blkid() -> Result<Vec<HashMap<String, String>>> {
-    let mut cmd = Command::new("blkid");
-    let output = cmd_output(&mut cmd)?;
-
-    let mut result: Vec<HashMap<String, String>> = Vec::new();
-    for line in output.lines() {
-        result.push(split_blkid_line(line));
-    }
-    Ok(result)
-}
-
 /// This is a bit fuzzy, but... this function will return every block device in the parent
 /// hierarchy of `device` capable of containing other partitions. So e.g. parent devices of type
 /// "part" doesn't match, but "disk" and "mpath" does.
 pub fn find_parent_devices(device: &str) -> Result<Vec<String>> {
-    let mut cmd = Command::new("lsblk");
-    // Older lsblk, e.g. in CentOS 7.6, doesn't support PATH, but --paths option
-    cmd.arg("--pairs")
-        .arg("--paths")
-        .arg("--inverse")
-        .arg("--output")
-        .arg("NAME,TYPE")
-        .arg(device);
-    let output = cmd_output(&mut cmd)?;
+    parent_devices(&BlkInfo::default(), device)
+}
+
+fn parent_devices(info: &BlkInfo, device: &str) -> Result<Vec<String>> {
+    let dev = info.device(Path::new(device))?;
     let mut parents = Vec::new();
-    // skip first line, which is the device itself
-    for line in output.lines().skip(1) {
-        let dev = split_lsblk_line(line);
-        let name = dev
-            .get("NAME")
-            .with_context(|| format!("device in hierarchy of {} missing NAME", device))?;
-        let kind = dev
-            .get("TYPE")
-            .with_context(|| format!("device in hierarchy of {} missing TYPE", device))?;
-        if kind == "disk" {
-            parents.push(name.clone());
-        } else if kind == "mpath" {
-            parents.push(name.clone());
+    // skip first entry, which is the device itself
+    for dev in info.inverse_tree(&dev)?.into_iter().skip(1) {
+        if dev.kind == "disk" {
+            parents.push(dev.path);
+        } else if dev.kind == "mpath" {
+            parents.push(dev.path);
             // we don't need to know what disks back the multipath
             break;
         }
@@ -913,38 +827,313 @@ pub fn find_parent_devices(device: &str) -> Result<Vec<String>> {
     Ok(parents)
 }
 
+/// Find the disk matching the criteria, and return the path to its device
+/// node, or None if no disk matches.  Fails if multiple disks match, after
+/// applying any selection term.
+pub fn find_matching_disk(criteria: &DeviceMatch) -> Result<Option<String>> {
+    matching_disk(&BlkInfo::default(), criteria)
+}
+
+fn matching_disk(info: &BlkInfo, criteria: &DeviceMatch) -> Result<Option<String>> {
+    let mut matches = Vec::new();
+    for dev in info.devices()? {
+        if let Some(props) = install_candidate(info, &dev)? {
+            if criteria.terms.iter().all(|t| disk_matches(t, &props)) {
+                matches.push(dev);
+            }
+        }
+    }
+    let select = criteria.terms.iter().find_map(|t| match t {
+        DeviceMatchTerm::Select(s) => Some(*s),
+        _ => None,
+    });
+    // matches are ordered by device number, so ties go to the lowest one
+    let dev = match (matches.len(), select) {
+        (0, _) => return Ok(None),
+        (1, _) => matches.remove(0),
+        (_, Some(DeviceSelect::Smallest)) => matches.into_iter().min_by_key(|d| d.size).unwrap(),
+        (_, Some(DeviceSelect::Largest)) => {
+            matches.into_iter().rev().max_by_key(|d| d.size).unwrap()
+        }
+        (_, None) => bail!(
+            "multiple disks match '{}': {}\nAdd criteria or select=smallest/largest.",
+            criteria,
+            matches
+                .iter()
+                .map(|d| d.path.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    Ok(Some(dev.path))
+}
+
+/// If the device is a disk we could install to, return the device whose
+/// properties should be matched against.
+fn install_candidate(info: &BlkInfo, dev: &BlockDevice) -> Result<Option<BlockDevice>> {
+    if dev.read_only || dev.size == 0 {
+        return Ok(None);
+    }
+    whole_disk_properties(info, dev)
+}
+
+/// If the device is a whole disk, return the device whose hardware
+/// properties describe it: the device itself, or for a multipath device,
+/// its first path.  The paths of a multipath device are not considered
+/// whole disks, since they're covered by the multipath device itself.
+pub(crate) fn whole_disk_properties(
+    info: &BlkInfo,
+    dev: &BlockDevice,
+) -> Result<Option<BlockDevice>> {
+    match dev.kind.as_str() {
+        "disk" => {
+            for holder in dev.holders()? {
+                if info.device_by_name(&holder)?.kind == "mpath" {
+                    return Ok(None);
+                }
+            }
+            Ok(Some(dev.clone()))
+        }
+        "mpath" => match dev.slaves()?.first() {
+            Some(path) => {
+                let mut props = info.device_by_name(path)?;
+                props.size = dev.size;
+                Ok(Some(props))
+            }
+            None => Ok(None),
+        },
+        _ => Ok(None),
+    }
+}
+
+fn disk_matches(term: &DeviceMatchTerm, dev: &BlockDevice) -> bool {
+    use DeviceMatchTerm::*;
+    let glob = |pattern: &glob::Pattern, value: &Option<String>| {
+        value.as_deref().map_or(false, |v| pattern.matches(v))
+    };
+    match term {
+        Size(min, max) => {
+            min.map_or(true, |min| dev.size >= min) && max.map_or(true, |max| dev.size <= max)
+        }
+        Rotational(r) => dev.rotational == *r,
+        Removable(r) => dev.removable == *r,
+        Transport(t) => dev.transport.as_ref().map_or(false, |d| t.contains(d)),
+        Model(pattern) => glob(pattern, &dev.model),
+        Serial(pattern) => glob(pattern, &dev.serial),
+        Wwn(pattern) => glob(pattern, &dev.wwn),
+        Select(_) => true,
+    }
+}
+
 /// Find ESP partitions which sit at the same hierarchy level as `device`.
 pub fn find_colocated_esps(device: &str) -> Result<Vec<String>> {
+    colocated_esps(&BlkInfo::default(), device)
+}
+
+fn colocated_esps(info: &BlkInfo, device: &str) -> Result<Vec<String>> {
     const ESP_TYPE_GUID: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
 
     // first, get the parent device
-    let parent_devices = find_parent_devices(device)
+    let parent_devices = parent_devices(info, device)
         .with_context(|| format!("while looking for colocated ESPs of '{}'", device))?;
 
     // now, look for all ESPs on those devices
     let mut esps = Vec::new();
     for parent_device in parent_devices {
-        let mut cmd = Command::new("lsblk");
-        // Older lsblk, e.g. in CentOS 7.6, doesn't support PATH, but --paths option
-        cmd.arg("--pairs")
-            .arg("--paths")
-            .arg("--output")
-            .arg("NAME,PARTTYPE")
-            .arg(parent_device);
-        for line in cmd_output(&mut cmd)?.lines() {
-            let dev = split_lsblk_line(line);
-            if dev.get("PARTTYPE").map(|t| t.as_str()) == Some(ESP_TYPE_GUID) {
-                esps.push(
-                    dev.get("NAME")
-                        .cloned()
-                        .context("ESP device with missing NAME")?,
-                )
+        let parent = info.device(Path::new(&parent_device))?;
+        for dev in info.tree(&parent)? {
+            if dev.parttype.as_deref() == Some(ESP_TYPE_GUID) {
+                esps.push(dev.path)
             }
         }
     }
     Ok(esps)
 }
 
+/// Return the kernel name and size of the block device at `path`, if it
+/// exists, has a nonzero size, and has been processed by udev.
+pub fn get_settled_device(path: &str) -> Result<Option<(String, u64)>> {
+    settled_device(&BlkInfo::default(), path)
+}
+
+fn settled_device(info: &BlkInfo, path: &str) -> Result<Option<(String, u64)>> {
+    if !Path::new(path).exists() {
+        return Ok(None);
+    }
+    let dev = info.device(Path::new(path))?;
+    if dev.size == 0 || dev.udev.is_none() {
+        return Ok(None);
+    }
+    Ok(Some((dev.name, dev.size)))
+}
+
+/// If the device is one of the paths of a multipath device, return the
+/// path to the multipath device node.
+pub fn find_multipath_holder(device: &str) -> Result<Option<String>> {
+    multipath_holder(&BlkInfo::default(), device)
+}
+
+fn multipath_holder(info: &BlkInfo, device: &str) -> Result<Option<String>> {
+    for holder in info.device(Path::new(device))?.holders()? {
+        let holder = info.device_by_name(&holder)?;
+        if holder.kind == "mpath" {
+            return Ok(Some(holder.path));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether the device is a multipath device.
+pub fn is_multipath(device: &str) -> Result<bool> {
+    Ok(BlkInfo::default().device(Path::new(device))?.kind == "mpath")
+}
+
+/// Describe what is using a device or the devices stacked on it: mounts,
+/// swap, and holders such as LVM, md RAID, dm-crypt, or multipath devices.
+pub fn describe_device_users(device: &str) -> Result<Vec<String>> {
+    device_users(&BlkInfo::default(), device)
+}
+
+fn device_users(info: &BlkInfo, device: &str) -> Result<Vec<String>> {
+    let mut ret = Vec::new();
+    for dev in info.tree(&info.device(Path::new(device))?)? {
+        for mountpoint in &dev.mountpoints {
+            ret.push(format!("{} mounted on {}", dev.path, mountpoint));
+        }
+        if dev.swap {
+            ret.push(format!("{} is swap device", dev.path));
+        }
+        for holder in dev.holders()? {
+            let holder = info.device_by_name(&holder)?;
+            ret.push(format!(
+                "{} in use by {} {}",
+                dev.path,
+                describe_holder(&holder),
+                holder.path
+            ));
+        }
+    }
+    Ok(ret)
+}
+
+fn describe_holder(dev: &BlockDevice) -> String {
+    let prop = |key| dev.udev.as_ref().and_then(|u| u.get(key));
+    match dev.kind.as_str() {
+        "lvm" => match (prop("DM_VG_NAME"), prop("DM_LV_NAME")) {
+            (Some(vg), Some(lv)) => format!("LVM logical volume {}/{}", vg, lv),
+            _ => "LVM logical volume".into(),
+        },
+        "crypt" => "dm-crypt mapping".into(),
+        "mpath" => "multipath device".into(),
+        "part" => "device-mapper partition".into(),
+        "dm" => "device-mapper device".into(),
+        kind if dev.name.starts_with("md") => format!("md {} array", kind),
+        kind => format!("{} device", kind),
+    }
+}
+
+/// Unmount, swapoff, and stop the LVM, md RAID, dm-crypt, multipath, and
+/// other device-mapper devices stacked on a disk, so we can get exclusive
+/// access to it.  The disk's own partitions are left in place.
+pub fn deactivate_holders(device: &str) -> Result<()> {
+    let commands = deactivation_commands(&BlkInfo::default(), device)?;
+    if commands.is_empty() {
+        return Ok(());
+    }
+    for command in &commands {
+        eprintln!("Running {}", command.join(" "));
+        cmd_output(Command::new(&command[0]).args(&command[1..]))?;
+    }
+    udev_settle()
+}
+
+fn deactivation_commands(info: &BlkInfo, device: &str) -> Result<Vec<Vec<String>>> {
+    let disk = info.device(Path::new(device))?;
+    let partitions: HashSet<String> = info
+        .children(&disk)?
+        .into_iter()
+        .filter(|d| d.kind == "part")
+        .map(|d| d.name)
+        .collect();
+    let tree = info.tree(&disk)?;
+
+    // unmount nested mounts before their parents
+    let mut mountpoints: Vec<&str> = tree
+        .iter()
+        .flat_map(|d| d.mountpoints.iter().map(|m| m.as_str()))
+        .collect();
+    mountpoints.sort_unstable_by(|a, b| b.cmp(a));
+    mountpoints.dedup();
+    let mut commands: Vec<Vec<String>> = mountpoints
+        .into_iter()
+        .map(|m| vec!["umount".into(), m.into()])
+        .collect();
+    commands.extend(
+        tree.iter()
+            .filter(|d| d.swap)
+            .map(|d| vec!["swapoff".into(), d.path.clone()]),
+    );
+
+    for dev in teardown_order(info, &tree)? {
+        if dev.name == disk.name || partitions.contains(&dev.name) {
+            continue;
+        }
+        let dm_name = || {
+            Path::new(&dev.path)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .with_context(|| format!("path {} has no filename", dev.path))
+        };
+        let prop = |key| dev.udev.as_ref().and_then(|u| u.get(key));
+        let command: Vec<String> = match dev.kind.as_str() {
+            "lvm" => match (prop("DM_VG_NAME"), prop("DM_LV_NAME")) {
+                (Some(vg), Some(lv)) => {
+                    vec!["lvchange".into(), "-an".into(), format!("{}/{}", vg, lv)]
+                }
+                _ => vec!["dmsetup".into(), "remove".into(), dm_name()?],
+            },
+            "crypt" => vec!["cryptsetup".into(), "close".into(), dm_name()?],
+            "mpath" => vec!["multipath".into(), "-f".into(), dm_name()?],
+            "part" | "dm" => vec!["dmsetup".into(), "remove".into(), dm_name()?],
+            _ if dev.name.starts_with("md") => {
+                vec!["mdadm".into(), "--stop".into(), dev.path.clone()]
+            }
+            kind => bail!("don't know how to deactivate {} device {}", kind, dev.path),
+        };
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+/// Order devices so that each one comes after all of its holders.  A
+/// holder can be built on several devices in the tree (e.g. an LVM
+/// volume spanning a RAID array and a LUKS device), so the reverse of a
+/// depth-first walk isn't enough.
+fn teardown_order(info: &BlkInfo, devs: &[BlockDevice]) -> Result<Vec<BlockDevice>> {
+    fn visit(
+        info: &BlkInfo,
+        dev: &BlockDevice,
+        seen: &mut HashSet<String>,
+        out: &mut Vec<BlockDevice>,
+    ) -> Result<()> {
+        if !seen.insert(dev.name.clone()) {
+            return Ok(());
+        }
+        for name in dev.holders()? {
+            visit(info, &info.device_by_name(&name)?, seen, out)?;
+        }
+        out.push(dev.clone());
+        Ok(())
+    }
+
+    let mut seen = HashSet::new();
+    let mut out = Vec::new();
+    for dev in devs {
+        visit(info, dev, &mut seen, &mut out)?;
+    }
+    Ok(out)
+}
+
 /// This is basically a Rust version of:
 /// https://github.com/coreos/coreos-assembler/blob/d3c7ec094a02/src/cmd-buildextend-live#L492-L495
 pub fn find_efi_vendor_dir(efi_mount: &Mount) -> Result<PathBuf> {
@@ -971,41 +1160,13 @@ pub fn find_efi_vendor_dir(efi_mount: &Mount) -> Result<PathBuf> {
     Ok(vendor_dir.pop().unwrap())
 }
 
-/// Parse key-value pairs from lsblk --pairs.
-/// Newer versions of lsblk support JSON but the one in CentOS 7 doesn't.
-fn split_lsblk_line(line: &str) -> HashMap<String, String> {
-    let re = Regex::new(r#"([A-Z-_]+)="([^"]+)""#).unwrap();
-    let mut fields: HashMap<String, String> = HashMap::new();
-    for cap in re.captures_iter(line) {
-        fields.insert(cap[1].to_string(), cap[2].to_string());
-    }
-    fields
-}
-
 pub fn get_blkdev_deps(device: &Path) -> Result<Vec<PathBuf>> {
-    let deps = {
-        let mut p = PathBuf::from("/sys/block");
-        p.push(
-            device
-                .canonicalize()
-                .with_context(|| format!("canonicalizing {}", device.display()))?
-                .file_name()
-                .with_context(|| format!("path {} has no filename", device.display()))?,
-        );
-        p.push("slaves");
-        p
-    };
-    let mut ret: Vec<PathBuf> = Vec::new();
-    let dir_iter = match read_dir(&deps) {
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(ret),
-        Err(e) => return Err(e).with_context(|| format!("reading dir {}", &deps.display())),
-        Ok(it) => it,
-    };
-    for ent in dir_iter {
-        let ent = ent.with_context(|| format!("reading {} entry", &deps.display()))?;
-        ret.push(Path::new("/dev").join(ent.file_name()));
-    }
-    Ok(ret)
+    Ok(BlkInfo::default()
+        .device(device)?
+        .slaves()?
+        .into_iter()
+        .map(|name| Path::new("/dev").join(name))
+        .collect())
 }
 
 pub fn get_blkdev_deps_recursing(device: &Path) -> Result<Vec<PathBuf>> {
@@ -1093,14 +1254,14 @@ pub fn get_gpt_size(file: &mut (impl Read + Seek)) -> Result<u64> {
 
 pub fn udev_settle() -> Result<()> {
     // "udevadm settle" silently no-ops if the udev socket is missing, and
-    // then lsblk can't find partition labels.  Catch this early.
+    // then the udev database won't have partition labels.  Catch this early.
     if !Path::new("/run/udev/control").exists() {
         bail!("udevd socket missing; are we running in a container without /run/udev mounted?");
     }
 
     // There's a potential window after rereading the partition table where
     // udevd hasn't yet received updates from the kernel, settle will return
-    // immediately, and lsblk won't pick up partition labels.  Try to sleep
+    // immediately, and we won't pick up partition labels.  Try to sleep
     // our way out of this.
     sleep(Duration::from_millis(200));
 
@@ -1134,7 +1295,7 @@ pub fn is_dasd(device: &str, fd: Option<&mut File>) -> Result<bool> {
     }
     let read_magic = |device: &str, disk: &mut File| -> Result<[u8; 4]> {
         let offset = disk
-            .seek(SeekFrom::Current(0))
+            .stream_position()
             .with_context(|| format!("saving offset {}", device))?;
         disk.seek(SeekFrom::Start(8194))
             .with_context(|| format!("seeking {}", device))?;
@@ -1171,88 +1332,239 @@ mod ioctl {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use maplit::hashmap;
     use std::io::copy;
     use tempfile::tempfile;
     use xz2::read::XzDecoder;
 
+    fn fixture_root() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/sysfs/root")
+    }
+
     #[test]
-    fn lsblk_split() {
+    fn test_filesystems_with_label() {
+        let info = BlkInfo::with_root(&fixture_root());
+        let dev = |p: &str| fixture_root().join("dev").join(p).display().to_string();
         assert_eq!(
-            split_lsblk_line(r#"NAME="sda" LABEL="" FSTYPE="""#),
-            hashmap! {
-                String::from("NAME") => String::from("sda"),
-            }
+            filesystems_with_label(&info, "boot", false).unwrap(),
+            vec![dev("mapper/mpatha1")]
         );
         assert_eq!(
-            split_lsblk_line(r#"NAME="sda1" LABEL="" FSTYPE="vfat""#),
-            hashmap! {
-                String::from("NAME") => String::from("sda1"),
-                String::from("FSTYPE") => String::from("vfat")
-            }
+            filesystems_with_label(&info, "boot label", false).unwrap(),
+            vec![dev("sda3")]
         );
         assert_eq!(
-            split_lsblk_line(r#"NAME="sda2" LABEL="boot" FSTYPE="ext4""#),
-            hashmap! {
-                String::from("NAME") => String::from("sda2"),
-                String::from("LABEL") => String::from("boot"),
-                String::from("FSTYPE") => String::from("ext4"),
-            }
+            filesystems_with_label(&info, "loopfs", false).unwrap(),
+            vec![dev("loop0")]
         );
+        assert!(filesystems_with_label(&info, "missing", false)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_matching_disk() {
+        let info = BlkInfo::with_root(&fixture_root());
+        let dev = |p: &str| Some(fixture_root().join("dev").join(p).display().to_string());
+        let find = |s: &str| matching_disk(&info, &s.parse::<DeviceMatch>().unwrap());
+        // candidates are sda (16 GiB), sdd (USB), mpatha (32 GiB), and
+        // nvme0n1 (~466 GiB)
+        assert_eq!(find("transport=nvme").unwrap(), dev("nvme0n1"));
+        assert_eq!(find("transport=usb").unwrap(), dev("sdd"));
+        assert_eq!(find("removable=true").unwrap(), dev("sdd"));
+        assert_eq!(find("model=Samsung*").unwrap(), dev("nvme0n1"));
+        assert_eq!(find("serial=drive-*").unwrap(), dev("sda"));
+        assert_eq!(find("wwn=eui.*").unwrap(), dev("nvme0n1"));
+        // multipath device matches on the properties of its paths
+        assert_eq!(find("wwn=0x6001405*").unwrap(), dev("mapper/mpatha"));
         assert_eq!(
-            split_lsblk_line(r#"NAME="sda3" LABEL="foo=\x22bar\x22 baz" FSTYPE="ext4""#),
-            hashmap! {
-                String::from("NAME") => String::from("sda3"),
-                // for now, we don't care about resolving lsblk's hex escapes,
-                // so we just pass them through
-                String::from("LABEL") => String::from(r#"foo=\x22bar\x22 baz"#),
-                String::from("FSTYPE") => String::from("ext4"),
-            }
+            find("transport=scsi,size=20G-").unwrap(),
+            dev("mapper/mpatha")
+        );
+        assert_eq!(find("size=-20G").unwrap(), dev("sda"));
+        assert_eq!(find("size=16G-16G").unwrap(), dev("sda"));
+        assert_eq!(
+            find("rotational=false,select=smallest").unwrap(),
+            dev("mapper/mpatha")
+        );
+        assert_eq!(
+            find("removable=false,select=largest").unwrap(),
+            dev("nvme0n1")
         );
+        assert_eq!(find("removable=false,select=smallest").unwrap(), dev("sda"));
+
+        assert_eq!(find("transport=sata").unwrap(), None);
+        let err = find("removable=false").unwrap_err().to_string();
+        assert!(err.contains("multiple disks match"), "{}", err);
+        assert!(err.contains(&dev("sda").unwrap()), "{}", err);
+        // read-only and empty devices are never candidates
+        assert_eq!(find("model=QEMU DVD-ROM").unwrap(), None);
     }
 
     #[test]
-    fn blkid_split() {
-        assert_eq!(split_blkid_line(r#""#), std::collections::HashMap::new());
-        assert_eq!(split_blkid_line(r#" : "#), std::collections::HashMap::new());
+    fn test_settled_device() {
+        let info = BlkInfo::with_root(&fixture_root());
+        let dev = |p: &str| fixture_root().join("dev").join(p).display().to_string();
+        assert_eq!(
+            settled_device(&info, &dev("sda")).unwrap(),
+            Some(("sda".into(), 16 * 1024 * 1024 * 1024))
+        );
+        assert_eq!(
+            settled_device(&info, &dev("mapper/mpatha")).unwrap(),
+            Some(("dm-0".into(), 32 * 1024 * 1024 * 1024))
+        );
+        // not yet present
+        assert_eq!(settled_device(&info, &dev("sdz")).unwrap(), None);
+        // empty
+        assert_eq!(settled_device(&info, &dev("sr0")).unwrap(), None);
+        // not processed by udev
+        assert_eq!(settled_device(&info, &dev("loop0")).unwrap(), None);
+    }
 
+    #[test]
+    fn test_multipath_holder() {
+        let info = BlkInfo::with_root(&fixture_root());
+        let dev = |p: &str| fixture_root().join("dev").join(p).display().to_string();
         assert_eq!(
-            split_blkid_line(r#": UUID="0000""#),
-            std::collections::HashMap::new()
+            multipath_holder(&info, &dev("sdb")).unwrap(),
+            Some(dev("mapper/mpatha"))
         );
+        assert_eq!(
+            multipath_holder(&info, &dev("sdc")).unwrap(),
+            Some(dev("mapper/mpatha"))
+        );
+        assert_eq!(
+            multipath_holder(&info, &dev("mapper/mpatha")).unwrap(),
+            None
+        );
+        assert_eq!(multipath_holder(&info, &dev("sda")).unwrap(), None);
+        // LUKS isn't multipath
+        assert_eq!(multipath_holder(&info, &dev("nvme0n1p1")).unwrap(), None);
+    }
 
+    #[test]
+    fn test_device_users() {
+        let info = BlkInfo::with_root(&fixture_root());
+        let dev = |p: &str| fixture_root().join("dev").join(p).display().to_string();
         assert_eq!(
-            split_blkid_line(r#"/dev/empty:"#),
-            hashmap! {
-                String::from("NAME") => String::from("/dev/empty")
-            }
+            device_users(&info, &dev("sda4")).unwrap(),
+            vec![
+                format!("{} mounted on /sysroot", dev("sda4")),
+                format!("{} mounted on /var/my dir", dev("sda4")),
+            ]
+        );
+        assert_eq!(
+            device_users(&info, &dev("nvme0n1p1")).unwrap(),
+            vec![
+                format!(
+                    "{} in use by dm-crypt mapping {}",
+                    dev("nvme0n1p1"),
+                    dev("mapper/luks-root")
+                ),
+                format!(
+                    "{} in use by LVM logical volume vg0/var {}",
+                    dev("mapper/luks-root"),
+                    dev("mapper/vg0-var")
+                ),
+                format!("{} mounted on /var", dev("mapper/vg0-var")),
+            ]
+        );
+        assert_eq!(
+            device_users(&info, &dev("nvme0n1p2")).unwrap(),
+            vec![
+                format!(
+                    "{} in use by md raid1 array {}",
+                    dev("nvme0n1p2"),
+                    dev("md127")
+                ),
+                format!(
+                    "{} in use by LVM logical volume vg0/var {}",
+                    dev("md127"),
+                    dev("mapper/vg0-var")
+                ),
+                format!("{} mounted on /var", dev("mapper/vg0-var")),
+            ]
+        );
+        assert_eq!(
+            device_users(&info, &dev("nvme0n1p4")).unwrap(),
+            vec![format!("{} is swap device", dev("nvme0n1p4"))]
+        );
+        assert!(device_users(&info, &dev("sda1")).unwrap().is_empty());
+
+        let mut lv = info.device_by_name("dm-2").unwrap();
+        lv.kind = "lvm".into();
+        assert_eq!(describe_holder(&lv), "LVM logical volume");
+        let udev = lv.udev.as_mut().unwrap();
+        udev.properties.insert("DM_VG_NAME".into(), "vg0".into());
+        udev.properties.insert("DM_LV_NAME".into(), "root".into());
+        assert_eq!(describe_holder(&lv), "LVM logical volume vg0/root");
+    }
+
+    #[test]
+    fn test_deactivation_commands() {
+        let info = BlkInfo::with_root(&fixture_root());
+        let dev = |p: &str| fixture_root().join("dev").join(p).display().to_string();
+        let commands = |d: &str| {
+            deactivation_commands(&info, &dev(d))
+                .unwrap()
+                .into_iter()
+                .map(|c| c.join(" "))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            commands("nvme0n1"),
+            vec![
+                "umount /var".to_string(),
+                format!("swapoff {}", dev("nvme0n1p4")),
+                // the LV spans both the LUKS device and the RAID array,
+                // so it must be stopped before either
+                "lvchange -an vg0/var".to_string(),
+                "cryptsetup close luks-root".to_string(),
+                format!("mdadm --stop {}", dev("md127")),
+            ]
+        );
+        assert_eq!(
+            commands("sda"),
+            vec!["umount /var/my dir", "umount /sysroot", "umount /boot"]
         );
+        // partitions of a multipath disk are left alone
+        assert!(commands("mapper/mpatha").is_empty());
+        // a multipath path
+        assert_eq!(
+            commands("sdb"),
+            vec!["dmsetup remove mpatha1", "multipath -f mpatha"]
+        );
+        assert!(commands("sdd").is_empty());
+    }
 
+    #[test]
+    fn test_parent_devices() {
+        let info = BlkInfo::with_root(&fixture_root());
+        let dev = |p: &str| fixture_root().join("dev").join(p).display().to_string();
         assert_eq!(
-            split_blkid_line(
-                r#"/dev/mapper/luks-f022921b-0100-4d48-9812-cfa6c225060a: UUID="2ff16ac3-103f-41d4-8e02-03686e255270" BLOCK_SIZE="4096" TYPE="ext4""#
-            ),
-            hashmap! {
-                String::from("NAME") => String::from("/dev/mapper/luks-f022921b-0100-4d48-9812-cfa6c225060a"),
-                String::from("UUID") => String::from("2ff16ac3-103f-41d4-8e02-03686e255270"),
-                String::from("TYPE") => String::from("ext4"),
-                String::from("BLOCK_SIZE") => String::from("4096")
-            }
+            parent_devices(&info, &dev("sda4")).unwrap(),
+            vec![dev("sda")]
         );
+        // stop at the multipath device
+        assert_eq!(
+            parent_devices(&info, &dev("mapper/mpatha1")).unwrap(),
+            vec![dev("mapper/mpatha")]
+        );
+        // RAID on two partitions of the same disk
+        assert_eq!(
+            parent_devices(&info, &dev("md127")).unwrap(),
+            vec![dev("nvme0n1")]
+        );
+        assert_eq!(
+            parent_devices(&info, &dev("mapper/luks-root")).unwrap(),
+            vec![dev("nvme0n1")]
+        );
+        parent_devices(&info, &dev("sda")).unwrap_err();
 
         assert_eq!(
-            split_blkid_line(
-                r#"/dev/vdb4: UUID="fdc69fb1-d7f3-4696-846e-b2275504f63c" LABEL="crypt_rootfs" TYPE="crypto_LUKS" PARTLABEL="root" PARTUUID="835753cb-d7f0-465e-84db-07860d3da2f6""#
-            ),
-            hashmap! {
-                String::from("NAME") => String::from("/dev/vdb4"),
-                String::from("LABEL") => String::from("crypt_rootfs"),
-                String::from("UUID") => String::from("fdc69fb1-d7f3-4696-846e-b2275504f63c"),
-                String::from("TYPE") => String::from("crypto_LUKS"),
-                String::from("PARTLABEL") => String::from("root"),
-                String::from("PARTUUID") => String::from("835753cb-d7f0-465e-84db-07860d3da2f6"),
-            }
+            colocated_esps(&info, &dev("sda3")).unwrap(),
+            vec![dev("sda2")]
         );
+        assert!(colocated_esps(&info, &dev("md127")).unwrap().is_empty());
     }
 
     #[test]
@@ -1347,7 +1659,7 @@ mod tests {
 
         let index = |i| Some(NonZeroU32::new(i).unwrap());
         let label = |l| Label(glob::Pattern::new(l).unwrap());
-        let tests = vec![
+        let tests = [
             // Partition range
             (
                 vec![Index(index(5), None)],
@@ -1469,7 +1781,7 @@ mod tests {
             let mut disk = make_disk(512, &merge_base_parts);
             saved.merge(&mut image, &mut disk).unwrap();
             assert!(
-                disk_has_mbr(&mut disk) == !expected_blank.is_empty(),
+                disk_has_mbr(&mut disk) != expected_blank.is_empty(),
                 "test {}",
                 testnum
             );
@@ -1497,11 +1809,11 @@ mod tests {
         }
 
         // ensure overwrite clobbers every byte of MBR
-        for sector_size in [512 as usize, 4096 as usize].iter() {
+        for sector_size in [512_usize, 4096_usize].iter() {
             let mut disk = make_unformatted_disk();
             disk.write_all(&vec![0xdau8; *sector_size]).unwrap();
             let saved =
-                SavedPartitions::new_from_file(&mut disk, *sector_size as u64, &vec![]).unwrap();
+                SavedPartitions::new_from_file(&mut disk, *sector_size as u64, &[]).unwrap();
             saved.overwrite(&mut disk).unwrap();
             assert!(disk_has_mbr(&mut disk), "{}", *sector_size);
             disk.seek(SeekFrom::Start(0)).unwrap();
@@ -1519,7 +1831,7 @@ mod tests {
 
         // test merging with unformatted initial disk
         let mut disk = make_unformatted_disk();
-        let saved = SavedPartitions::new_from_file(&mut disk, 512, &vec![label("z")]).unwrap();
+        let saved = SavedPartitions::new_from_file(&mut disk, 512, &[label("z")]).unwrap();
         let mut disk = make_disk(512, &merge_base_parts);
         saved.merge(&mut image, &mut disk).unwrap();
         let result = GPT::find_from(&mut disk).unwrap();
@@ -1527,8 +1839,7 @@ mod tests {
 
         // test overlapping partitions
         let saved =
-            SavedPartitions::new_from_file(&mut base, 512, &vec![Index(index(1), index(1))])
-                .unwrap();
+            SavedPartitions::new_from_file(&mut base, 512, &[Index(index(1), index(1))]).unwrap();
         let mut disk = make_disk(512, &merge_base_parts);
         let err = saved.merge(&mut image, &mut disk).unwrap_err();
         assert!(
@@ -1538,7 +1849,7 @@ mod tests {
         );
 
         // test sector size mismatch
-        let saved = SavedPartitions::new_from_file(&mut base, 512, &vec![label("*i*")]).unwrap();
+        let saved = SavedPartitions::new_from_file(&mut base, 512, &[label("*i*")]).unwrap();
         let mut image_4096 = make_disk(4096, &image_parts);
         assert_eq!(
             get_gpt_size(&mut image_4096).unwrap(),
@@ -1561,7 +1872,7 @@ mod tests {
         let data = include_bytes!("../fixtures/gpt-512-duplicate-partition-guids.xz");
         copy(&mut XzDecoder::new(&data[..]), &mut disk).unwrap();
         assert_eq!(
-            SavedPartitions::new_from_file(&mut disk, 512, &vec![label("*")])
+            SavedPartitions::new_from_file(&mut disk, 512, &[label("*")])
                 .unwrap_err()
                 .to_string(),
             "failed dry run restoring saved partitions; input partition table may be invalid"
@@ -1572,23 +1883,23 @@ mod tests {
             let sector_size: u64 = *sector_size;
             // backup corrupt
             let mut disk = make_damaged_disk(sector_size, &base_parts, false, true);
-            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &vec![]).unwrap();
+            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &[]).unwrap();
             assert!(!saved.is_saved());
-            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &vec![label("one")])
-                .unwrap();
+            let saved =
+                SavedPartitions::new_from_file(&mut disk, sector_size, &[label("one")]).unwrap();
             assert!(saved.is_saved());
             // primary corrupt
             let mut disk = make_damaged_disk(sector_size, &base_parts, true, false);
-            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &vec![]).unwrap();
+            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &[]).unwrap();
             assert!(!saved.is_saved());
-            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &vec![label("one")])
-                .unwrap();
+            let saved =
+                SavedPartitions::new_from_file(&mut disk, sector_size, &[label("one")]).unwrap();
             assert!(saved.is_saved());
             // both corrupt
             let mut disk = make_damaged_disk(sector_size, &base_parts, true, true);
-            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &vec![]).unwrap();
+            let saved = SavedPartitions::new_from_file(&mut disk, sector_size, &[]).unwrap();
             assert!(!saved.is_saved());
-            let err = SavedPartitions::new_from_file(&mut disk, sector_size, &vec![label("one")])
+            let err = SavedPartitions::new_from_file(&mut disk, sector_size, &[label("one")])
                 .unwrap_err();
             assert!(
                 format!("{:#}", err).contains("could not read primary header"),
@@ -1674,7 +1985,7 @@ mod tests {
         sig == [0x55, 0xaa]
     }
 
-    fn assert_partitions_eq(expected: &Vec<(u32, GPTPartitionEntry)>, found: &GPT, message: &str) {
+    fn assert_partitions_eq(expected: &[(u32, GPTPartitionEntry)], found: &GPT, message: &str) {
         assert_eq!(
             expected
                 .iter()
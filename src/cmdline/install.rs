@@ -184,6 +184,15 @@ pub struct InstallConfig {
     #[serde(skip_serializing_if = "is_default")]
     #[clap(long, help_heading = ADVANCED)]
     pub offline: bool,
+    /// Offline install of this OS version (glob)
+    ///
+    /// Perform an offline installation using the locally available image
+    /// whose OS version, such as "35.20211203.3.0", or description, such as
+    /// "Fedora CoreOS 35.20211203.3.0", matches this glob.  Implies
+    /// --offline.
+    #[clap(long, value_name = "glob", help_heading = ADVANCED)]
+    #[clap(conflicts_with_all = &["stream", "image-file", "image-url"])]
+    pub offline_version: Option<String>,
     /// Skip signature verification
     #[serde(skip_serializing_if = "is_default")]
     #[clap(long, help_heading = ADVANCED)]
@@ -214,13 +223,71 @@ pub struct InstallConfig {
     #[serde(skip_serializing_if = "is_default")]
     #[clap(long, value_name = "N", default_value_t, help_heading = ADVANCED)]
     pub fetch_retries: FetchRetries,
+    /// Select destination disk by criteria
+    ///
+    /// Install to the disk matching all of these comma-separated criteria,
+    /// instead of specifying a destination device.  Criteria are:
+    /// size=MIN-MAX (either bound optional; units K, M, G, T or KB, MB,
+    /// GB, TB), rotational=BOOL, removable=BOOL, transport=T1|T2 (e.g.
+    /// nvme, sata, scsi, usb, virtio), model=GLOB, serial=GLOB, wwn=GLOB.
+    /// Only writable whole disks and multipath devices are considered.
+    /// It's an error if no disk or more than one disk matches, unless
+    /// select=smallest or select=largest is specified.
+    #[clap(long, value_name = "spec", help_heading = ADVANCED)]
+    #[clap(conflicts_with = "dest-device")]
+    pub dest_device_match: Option<DeviceMatch>,
+    /// Install over an existing OS
+    ///
+    /// Install even if the destination device already contains CoreOS or
+    /// another OS.  By default, the installer refuses to overwrite a disk
+    /// with boot loader entries, an OS root filesystem, or a vendor's EFI
+    /// boot loader.
+    #[serde(skip_serializing_if = "is_default")]
+    #[clap(long, help_heading = ADVANCED)]
+    pub force_overwrite_os: bool,
+    /// Release devices using the destination
+    ///
+    /// Before installing, unmount filesystems and disable swap on the
+    /// destination device, and stop any LVM, md RAID, dm-crypt, multipath,
+    /// or other device-mapper devices built on it, such as those left
+    /// behind by a previous OS.
+    #[serde(skip_serializing_if = "is_default")]
+    #[clap(long, help_heading = ADVANCED)]
+    pub deactivate_holders: bool,
+    /// Append kargs for multipath destination
+    ///
+    /// When installing to a multipath device, append the kernel arguments
+    /// needed to boot from a multipath root: rd.multipath=default,
+    /// root=/dev/disk/by-label/dm-mpath-root, and rw.  Ignored if an
+    /// rd.multipath kernel argument is already being appended.
+    #[serde(skip_serializing_if = "is_default")]
+    #[clap(long, help_heading = ADVANCED)]
+    pub multipath_kargs: bool,
+    /// Wait for the destination device to appear
+    ///
+    /// Wait up to this many seconds for the destination device, or a disk
+    /// matching --dest-device-match, to appear and stop changing before
+    /// installing.  Useful when the disk is discovered late, such as over
+    /// iSCSI, Fibre Channel, or slow USB.
+    #[clap(long, value_name = "secs", help_heading = ADVANCED)]
+    pub wait_for_device: Option<u64>,
+    /// Check for problems without installing
+    ///
+    /// Check that the image fits on the destination device, that its
+    /// sector size is supported, and that the image's architecture and
+    /// firmware requirements match this system, then exit without writing
+    /// anything.  These checks always run before installing.
+    #[serde(skip_serializing_if = "is_default")]
+    #[clap(long, help_heading = ADVANCED)]
+    pub preflight_only: bool,
 
     // positional args
     /// Destination device
     ///
     /// Path to the device node for the destination disk.  The beginning of
-    /// the device will be overwritten without further confirmation.
-    #[clap(required_unless_present = "config-file")]
+    /// the device will be overwritten without further confirmation, unless
+    /// it contains an existing OS.
+    #[clap(required_unless_present_any = &["config-file", "dest-device-match"])]
     pub dest_device: Option<String>,
 }
 
@@ -314,11 +381,18 @@ mod test {
             save_partlabel: vec!["p".into(), "q".into()],
             save_partindex: vec!["r".into(), "s".into()],
             offline: true,
+            offline_version: Some("t2".into()),
             insecure: true,
             insecure_ignition: true,
             stream_base_url: Some(Url::parse("http://example.com/t").unwrap()),
             preserve_on_error: true,
             fetch_retries: FetchRetries::from_str("3").unwrap(),
+            dest_device_match: Some(DeviceMatch::from_str("size=1G-,select=largest").unwrap()),
+            force_overwrite_os: true,
+            deactivate_holders: true,
+            multipath_kargs: true,
+            wait_for_device: Some(60),
+            preflight_only: true,
             dest_device: Some("u".into()),
         };
         let expected = vec![
@@ -358,6 +432,8 @@ mod test {
             "--save-partindex",
             "s",
             "--offline",
+            "--offline-version",
+            "t2",
             "--insecure",
             "--insecure-ignition",
             "--stream-base-url",
@@ -365,6 +441,14 @@ mod test {
             "--preserve-on-error",
             "--fetch-retries",
             "3",
+            "--dest-device-match",
+            "size=1G-,select=largest",
+            "--force-overwrite-os",
+            "--deactivate-holders",
+            "--multipath-kargs",
+            "--wait-for-device",
+            "60",
+            "--preflight-only",
             "u",
         ];
         assert_eq!(config.to_args().unwrap(), expected);
@@ -394,6 +478,11 @@ insecure-ignition: true
 stream-base-url: http://example.com/t
 preserve-on-error: true
 fetch-retries: 3
+force-overwrite-os: true
+deactivate-holders: true
+multipath-kargs: true
+wait-for-device: 60
+preflight-only: true
 dest-device: u
 "#
                 .as_bytes(),
@@ -427,11 +516,20 @@ dest-device: u
             save_partlabel: vec!["p".into(), "q".into()],
             save_partindex: vec!["r".into(), "s".into()],
             offline: true,
+            // conflict
+            offline_version: None,
             insecure: true,
             insecure_ignition: true,
             stream_base_url: Some(Url::parse("http://example.com/t").unwrap()),
             preserve_on_error: true,
             fetch_retries: FetchRetries::from_str("3").unwrap(),
+            // conflict
+            dest_device_match: None,
+            force_overwrite_os: true,
+            deactivate_holders: true,
+            multipath_kargs: true,
+            wait_for_device: Some(60),
+            preflight_only: true,
             dest_device: Some("u".into()),
         };
         let config = InstallConfig::from_args(&["--config-file", f.path().to_str().unwrap()])
@@ -515,4 +613,38 @@ dest-device: u
         .expand_config_files()
         .unwrap_err();
     }
+
+    /// Test selecting the target device by criteria
+    #[test]
+    fn install_config_dest_device_match() {
+        let config = InstallConfig::from_args(&["--dest-device-match", "transport=nvme"]).unwrap();
+        assert_eq!(config.dest_device, None);
+        assert_eq!(
+            config.dest_device_match,
+            Some(DeviceMatch::from_str("transport=nvme").unwrap())
+        );
+
+        let mut f = NamedTempFile::new().unwrap();
+        f.as_file_mut()
+            .write_all(b"dest-device-match: size=100G-,select=smallest")
+            .unwrap();
+        let config = InstallConfig::from_args(&["--config-file", f.path().to_str().unwrap()])
+            .unwrap()
+            .expand_config_files()
+            .unwrap();
+        assert_eq!(
+            config.dest_device_match.unwrap().to_string(),
+            "size=100G-,select=smallest"
+        );
+
+        // device and criteria conflict
+        InstallConfig::from_args(&["--dest-device-match", "transport=nvme", "/dev/missing"])
+            .unwrap_err();
+        InstallConfig::from_args(&["--config-file", f.path().to_str().unwrap(), "/dev/missing"])
+            .unwrap()
+            .expand_config_files()
+            .unwrap_err();
+        // invalid criteria
+        InstallConfig::from_args(&["--dest-device-match", "color=blue"]).unwrap_err();
+    }
 }
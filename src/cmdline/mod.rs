@@ -18,6 +18,8 @@
 use clap::{AppSettings, Parser};
 use reqwest::Url;
 
+use crate::io::IgnitionHash;
+
 mod install;
 #[cfg(feature = "mangen")]
 mod man;
@@ -46,6 +48,12 @@ pub enum Cmd {
     Download(DownloadConfig),
     /// List available images in a Fedora CoreOS stream
     ListStream(ListStreamConfig),
+    /// List disks available for installation
+    ListDevices(ListDevicesConfig),
+    /// Report the CoreOS install on a disk
+    Inspect(InspectConfig),
+    /// Change the configuration of an installed disk
+    Reconfigure(ReconfigureConfig),
     /// Commands to manage a CoreOS live ISO image
     #[clap(subcommand)]
     Iso(IsoCmd),
@@ -90,6 +98,10 @@ pub enum IsoCmd {
     Extract(IsoExtractCmd),
     /// Restore a CoreOS live ISO image to default settings
     Reset(IsoResetConfig),
+    /// Verify the embedded checksum of a CoreOS live ISO image
+    Verify(IsoVerifyConfig),
+    /// Report customizations of a CoreOS live ISO image
+    Inspect(IsoInspectConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -128,6 +140,8 @@ pub enum IsoExtractCmd {
     Pxe(IsoExtractPxeConfig),
     /// Extract a minimal ISO from a CoreOS live ISO image
     MinimalIso(IsoExtractMinimalIsoConfig),
+    /// Extract the EFI boot image from an ISO image
+    Efiboot(IsoExtractEfibootConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -140,6 +154,8 @@ pub enum PxeCmd {
     /// Commands to manage live PXE network settings
     #[clap(subcommand)]
     Network(PxeNetworkCmd),
+    /// Report customizations of a live PXE initrd
+    Inspect(PxeInspectConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -181,6 +197,15 @@ pub enum DevCmd {
     /// Commands to extract data
     #[clap(subcommand)]
     Extract(DevExtractCmd),
+    /// Commands to create data
+    #[clap(subcommand)]
+    Create(DevCreateCmd),
+    /// Commands to convert data
+    #[clap(subcommand)]
+    Convert(DevConvertCmd),
+    /// Commands to verify data
+    #[clap(subcommand)]
+    Verify(DevVerifyCmd),
 }
 
 #[derive(Debug, Parser)]
@@ -191,6 +216,8 @@ pub enum DevShowCmd {
     Initrd(DevShowInitrdConfig),
     /// Print file extent mapping of specific file
     Fiemap(DevShowFiemapConfig),
+    /// Show the metadata of an osmet file
+    Osmet(DevShowOsmetConfig),
 }
 
 #[derive(Debug, Parser)]
@@ -201,6 +228,24 @@ pub enum DevExtractCmd {
     Initrd(DevExtractInitrdConfig),
 }
 
+#[derive(Debug, Parser)]
+pub enum DevCreateCmd {
+    /// Create an initrd image from a directory tree
+    Initrd(DevCreateInitrdConfig),
+}
+
+#[derive(Debug, Parser)]
+pub enum DevVerifyCmd {
+    /// Check that an osmet file unpacks correctly from an OSTree repo
+    Osmet(DevVerifyOsmetConfig),
+}
+
+#[derive(Debug, Parser)]
+pub enum DevConvertCmd {
+    /// Upgrade an osmet file to the current format
+    Osmet(DevConvertOsmetConfig),
+}
+
 #[derive(Debug, Parser)]
 pub struct DownloadConfig {
     /// Fedora CoreOS stream
@@ -245,6 +290,95 @@ pub struct ListStreamConfig {
     pub stream_base_url: Option<Url>,
 }
 
+#[derive(Debug, Parser)]
+pub struct ListDevicesConfig {
+    /// Output JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Parser)]
+pub struct InspectConfig {
+    /// Output JSON
+    #[clap(long)]
+    pub json: bool,
+    /// Disk containing a CoreOS install
+    #[clap(value_name = "DEVICE")]
+    pub device: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct ReconfigureConfig {
+    /// Replace Ignition config with one from a file
+    ///
+    /// Embed the specified Ignition config in the installed system,
+    /// replacing any existing one.  The config is only applied if
+    /// first-boot provisioning hasn't run yet, or is re-armed with
+    /// --rearm-firstboot.
+    #[clap(short, long, value_name = "path")]
+    pub ignition_file: Option<String>,
+    /// Digest (type-value) of the Ignition config
+    ///
+    /// Verify that the Ignition config matches the specified digest,
+    /// formatted as <type>-<hexvalue>.  <type> can be sha256 or sha512.
+    #[clap(long, value_name = "digest", requires = "ignition-file")]
+    pub ignition_hash: Option<IgnitionHash>,
+    /// Override the Ignition platform ID
+    ///
+    /// Configure the installed system to run on the specified cloud or
+    /// virtualization platform, such as "vmware", including the
+    /// platform's kernel arguments and console settings.
+    #[clap(short, long, value_name = "name")]
+    pub platform: Option<String>,
+    /// Kernel and bootloader console
+    ///
+    /// Configure the installed system's kernel and GRUB to use the
+    /// specified console, replacing the existing console settings.  Use
+    /// the syntax of the console= kernel argument, such as "tty0",
+    /// "ttyS0,115200n8", or "ttyAMA0,115200".  GRUB can only use tty<n>
+    /// and ttyS<n> consoles.  Can be specified multiple times; the last
+    /// console is the primary one.
+    #[clap(long, value_name = "spec")]
+    pub console: Vec<Console>,
+    /// Append default kernel arg
+    ///
+    /// Add a kernel argument to the installed system.
+    #[clap(long, value_name = "arg")]
+    pub append_karg: Vec<String>,
+    /// Delete default kernel arg
+    ///
+    /// Delete a default kernel argument from the installed system.
+    #[clap(long, value_name = "arg")]
+    pub delete_karg: Vec<String>,
+    /// Copy network config from this environment
+    ///
+    /// Copy NetworkManager keyfiles from the current environment to the
+    /// installed system.
+    #[clap(short = 'n', long)]
+    pub copy_network: bool,
+    /// Override NetworkManager keyfile dir for -n
+    ///
+    /// Specify the path to NetworkManager keyfiles to be copied with
+    /// --copy-network.
+    ///
+    /// [default: /etc/NetworkManager/system-connections/]
+    #[clap(long, value_name = "path", default_value_t)]
+    // showing the default converts every option to multiline help
+    #[clap(hide_default_value = true)]
+    pub network_dir: DefaultedString<NetworkDir>,
+    /// Run Ignition again on next boot
+    ///
+    /// Recreate the ignition.firstboot stamp file so first-boot
+    /// provisioning runs on the next boot.  Ignition expects to start from
+    /// a pristine system, so this is only safe if the disk hasn't booted
+    /// far enough to modify the root filesystem.
+    #[clap(long)]
+    pub rearm_firstboot: bool,
+    /// Disk containing a CoreOS install
+    #[clap(value_name = "DEVICE")]
+    pub device: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct CommonCustomizeConfig {
     /// Ignition config fragment for dest sys
@@ -345,6 +479,12 @@ pub struct IsoCustomizeConfig {
     /// Overwrite existing customizations
     #[clap(short, long)]
     pub force: bool,
+    /// Update the embedded checksum
+    ///
+    /// Recompute the implantisomd5-compatible checksum after customizing, so
+    /// checkisomd5 and rd.live.check accept the customized image.
+    #[clap(long)]
+    pub implant_checksum: bool,
     /// Write ISO to a new output file
     #[clap(short, long, value_name = "path")]
     pub output: Option<String>,
@@ -391,6 +531,15 @@ pub struct IsoIgnitionEmbedConfig {
     /// Overwrite an existing Ignition config
     #[clap(short, long)]
     pub force: bool,
+    /// Merge with an existing Ignition config
+    ///
+    /// Combine the new Ignition config with the one already embedded in the
+    /// ISO image, if any, instead of replacing it.
+    #[clap(long, conflicts_with = "force")]
+    pub merge: bool,
+    /// Print changes made by merging
+    #[clap(long, requires = "merge")]
+    pub show_diff: bool,
     /// Ignition config to embed [default: stdin]
     #[clap(short, long, value_name = "path")]
     pub ignition_file: Option<String>,
@@ -517,6 +666,30 @@ pub struct IsoExtractPxeConfig {
     /// Output directory
     #[clap(short, long, value_name = "PATH", default_value = ".")]
     pub output_dir: String,
+    /// Combine initramfs and rootfs into one file
+    ///
+    /// Write a single initramfs containing the live initramfs, the live
+    /// rootfs, and any customizations embedded in the ISO image, so the
+    /// system can be booted with only a kernel and initramfs.
+    #[clap(long)]
+    pub single_initrd: bool,
+    /// Also write netboot configs of these types
+    ///
+    /// Write an ipxe, pxelinux, or grub boot config, pointing to the extracted
+    /// artifacts under the base URL and using the kernel arguments of the
+    /// ISO image.
+    #[clap(long, value_name = "type", requires = "base-url")]
+    // Allow argument multiple times, but one value each.  Allow "a,b" in
+    // one argument.
+    #[clap(number_of_values = 1, require_value_delimiter = true)]
+    #[clap(value_delimiter = ',')]
+    pub boot_config: Vec<BootConfigType>,
+    /// URL where the artifacts will be served
+    #[clap(long, value_name = "URL", requires = "boot-config")]
+    pub base_url: Option<Url>,
+    /// Ignition config URL for the netboot configs
+    #[clap(long, value_name = "URL", requires = "boot-config")]
+    pub ignition_url: Option<Url>,
 }
 
 #[derive(Debug, Parser)]
@@ -535,6 +708,16 @@ pub struct IsoExtractMinimalIsoConfig {
     pub rootfs_url: Option<String>,
 }
 
+#[derive(Debug, Parser)]
+pub struct IsoExtractEfibootConfig {
+    /// ISO image
+    #[clap(value_name = "ISO")]
+    pub input: String,
+    /// EFI boot image output file
+    #[clap(value_name = "OUTPUT", default_value = "-")]
+    pub output: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct PackMinimalIsoConfig {
     /// ISO image
@@ -558,6 +741,23 @@ pub struct IsoResetConfig {
     pub input: String,
 }
 
+#[derive(Debug, Parser)]
+pub struct IsoVerifyConfig {
+    /// ISO image
+    #[clap(value_name = "ISO")]
+    pub input: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct IsoInspectConfig {
+    /// Output JSON
+    #[clap(long)]
+    pub json: bool,
+    /// ISO image
+    #[clap(value_name = "ISO")]
+    pub input: String,
+}
+
 #[derive(Debug, Parser)]
 // default usage line lists all mandatory options and so exceeds 80 characters
 #[clap(override_usage = "coreos-installer pack osmet [OPTIONS]")]
@@ -588,12 +788,35 @@ pub struct DevExtractOsmetConfig {
     /// osmet file
     #[clap(long, required = true, value_name = "PATH")]
     pub osmet: String,
+    /// Write to sparse file or stdout ("-"), not device
+    #[clap(short, long, value_name = "PATH", conflicts_with = "device")]
+    pub output: Option<String>,
     /// OSTree repo
     #[clap(value_name = "PATH")]
     pub repo: String,
     /// Destination device
-    #[clap(value_name = "DEV")]
-    pub device: String,
+    #[clap(value_name = "DEV", required_unless_present = "output")]
+    pub device: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevVerifyOsmetConfig {
+    /// osmet file
+    #[clap(long, required = true, value_name = "PATH")]
+    pub osmet: String,
+    /// OSTree repo
+    #[clap(value_name = "PATH")]
+    pub repo: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct DevConvertOsmetConfig {
+    /// Path to osmet file to write
+    #[clap(short, long, value_name = "PATH")]
+    pub output: String,
+    /// osmet file to convert
+    #[clap(value_name = "PATH")]
+    pub input: String,
 }
 
 #[derive(Debug, Parser)]
@@ -603,12 +826,30 @@ pub struct DevShowFiemapConfig {
     pub file: String,
 }
 
+#[derive(Debug, Parser)]
+pub struct DevShowOsmetConfig {
+    /// Output JSON
+    #[clap(long)]
+    pub json: bool,
+    /// osmet file
+    #[clap(value_name = "PATH")]
+    pub osmet: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct PxeCustomizeConfig {
     // Customizations
     #[clap(flatten)]
     pub common: CommonCustomizeConfig,
 
+    /// Append live rootfs image to initramfs
+    ///
+    /// Include the specified live rootfs image in the output, so the
+    /// system can be booted with only a kernel and initramfs.  The
+    /// resulting initramfs is large, and some firmware cannot load it.
+    #[clap(long, value_name = "path")]
+    pub embed_rootfs: Option<String>,
+
     // I/O configuration
     /// Output file
     #[clap(short, long, value_name = "path")]
@@ -623,6 +864,15 @@ pub struct PxeIgnitionWrapConfig {
     /// Ignition config to wrap [default: stdin]
     #[clap(short, long, value_name = "path")]
     pub ignition_file: Option<String>,
+    /// Merge with the Ignition config in an initrd
+    ///
+    /// Combine the new Ignition config with the one wrapped in an existing
+    /// initrd image, if any.
+    #[clap(long, value_name = "initrd")]
+    pub merge: Option<String>,
+    /// Print changes made by merging
+    #[clap(long, requires = "merge")]
+    pub show_diff: bool,
     /// Write to a file instead of stdout
     #[clap(short, long, value_name = "path")]
     pub output: Option<String>,
@@ -657,6 +907,16 @@ pub struct PxeNetworkUnwrapConfig {
     pub input: Option<String>,
 }
 
+#[derive(Debug, Parser)]
+pub struct PxeInspectConfig {
+    /// Output JSON
+    #[clap(long)]
+    pub json: bool,
+    /// initrd image ("-" for stdin)
+    #[clap(value_name = "initrd")]
+    pub input: String,
+}
+
 #[derive(Debug, Parser)]
 pub struct DevShowInitrdConfig {
     /// initrd image ("-" for stdin)
@@ -683,6 +943,22 @@ pub struct DevExtractInitrdConfig {
     pub filter: Vec<String>,
 }
 
+#[derive(Debug, Parser)]
+pub struct DevCreateInitrdConfig {
+    /// Source directory
+    #[clap(short = 'C', long, value_name = "path", default_value = ".")]
+    pub directory: String,
+    /// Timestamp for all files (seconds since epoch)
+    #[clap(long, value_name = "seconds")]
+    pub mtime: Option<u32>,
+    /// Write to a file instead of stdout
+    #[clap(short, long, value_name = "path")]
+    pub output: Option<String>,
+    /// Files or globs to include
+    #[clap(value_name = "glob")]
+    pub filter: Vec<String>,
+}
+
 #[cfg(feature = "mangen")]
 #[derive(Debug, Parser)]
 pub struct PackManConfig {
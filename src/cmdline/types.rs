@@ -65,6 +65,321 @@ impl Default for FetchRetries {
     }
 }
 
+/// Format of a netboot config generated by `iso extract pxe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootConfigType {
+    Ipxe,
+    Pxelinux,
+    Grub,
+}
+
+impl FromStr for BootConfigType {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipxe" => Ok(Self::Ipxe),
+            "pxelinux" => Ok(Self::Pxelinux),
+            "grub" => Ok(Self::Grub),
+            _ => Err(anyhow!("unknown boot config type: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for BootConfigType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ipxe => write!(f, "ipxe"),
+            Self::Pxelinux => write!(f, "pxelinux"),
+            Self::Grub => write!(f, "grub"),
+        }
+    }
+}
+
+/// Criteria for selecting a destination disk, as a comma-separated list of
+/// `key=value` terms.
+#[derive(Debug, DeserializeFromStr, SerializeDisplay, Clone, PartialEq, Eq)]
+pub struct DeviceMatch {
+    spec: String,
+    pub terms: Vec<DeviceMatchTerm>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceMatchTerm {
+    /// Inclusive size range in bytes.
+    Size(Option<u64>, Option<u64>),
+    Rotational(bool),
+    Removable(bool),
+    /// Any of these transports.
+    Transport(Vec<String>),
+    Model(glob::Pattern),
+    Serial(glob::Pattern),
+    Wwn(glob::Pattern),
+    /// Tie-breaker when multiple disks match.
+    Select(DeviceSelect),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceSelect {
+    Smallest,
+    Largest,
+}
+
+impl FromStr for DeviceMatch {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut terms = Vec::new();
+        for term in s.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let (key, value) = term
+                .split_once('=')
+                .ok_or_else(|| anyhow!("device match term '{}' is not key=value", term))?;
+            let glob = || {
+                glob::Pattern::new(value)
+                    .map_err(|e| anyhow!("couldn't parse {} glob '{}': {}", key, value, e))
+            };
+            terms.push(match key {
+                "size" => {
+                    let (min, max) = value
+                        .split_once('-')
+                        .ok_or_else(|| anyhow!("size '{}' is not a range like 100G-2T", value))?;
+                    let parse = |v: &str| match v {
+                        "" => Ok(None),
+                        v => parse_size(v).map(Some),
+                    };
+                    DeviceMatchTerm::Size(parse(min)?, parse(max)?)
+                }
+                "rotational" => DeviceMatchTerm::Rotational(parse_bool(key, value)?),
+                "removable" => DeviceMatchTerm::Removable(parse_bool(key, value)?),
+                "transport" => {
+                    DeviceMatchTerm::Transport(value.split('|').map(String::from).collect())
+                }
+                "model" => DeviceMatchTerm::Model(glob()?),
+                "serial" => DeviceMatchTerm::Serial(glob()?),
+                "wwn" => DeviceMatchTerm::Wwn(glob()?),
+                "select" => DeviceMatchTerm::Select(match value {
+                    "smallest" => DeviceSelect::Smallest,
+                    "largest" => DeviceSelect::Largest,
+                    _ => return Err(anyhow!("unknown device selection: {}", value)),
+                }),
+                _ => return Err(anyhow!("unknown device match key: {}", key)),
+            });
+        }
+        if terms.is_empty() {
+            return Err(anyhow!("empty device match expression"));
+        }
+        Ok(Self {
+            spec: s.to_string(),
+            terms,
+        })
+    }
+}
+
+impl fmt::Display for DeviceMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.spec)
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(anyhow!("{} must be true or false, not '{}'", key, value)),
+    }
+}
+
+/// Parse a size with an optional binary (K, M, G, T, or KiB etc.) or
+/// decimal (KB, MB, GB, TB) suffix.
+fn parse_size(s: &str) -> Result<u64> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (num, suffix) = s.split_at(split);
+    let num: f64 = num
+        .parse()
+        .map_err(|_| anyhow!("couldn't parse size '{}'", s))?;
+    let suffix = suffix.to_ascii_uppercase();
+    let (prefix, base) = match suffix.as_str() {
+        "" | "B" => ("", 1024u64),
+        s if s.ends_with("IB") => (&s[..s.len() - 2], 1024),
+        s if s.ends_with('B') => (&s[..s.len() - 1], 1000),
+        s => (s, 1024),
+    };
+    let exp = match prefix {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        "P" => 5,
+        _ => return Err(anyhow!("unknown unit in size '{}'", s)),
+    };
+    Ok((num * base.pow(exp) as f64).round() as u64)
+}
+
+/// A kernel and bootloader console, in the syntax of the `console=` kernel
+/// argument: `tty<n>` for a virtual terminal,
+/// `ttyS<n>[,<speed>[<parity>[<bits>]]]` for a PC serial port, or any other
+/// console device name with optional driver-specific options, such as
+/// `ttyAMA0,115200` or `hvc0`.
+#[derive(Debug, DeserializeFromStr, SerializeDisplay, Clone, PartialEq, Eq)]
+pub enum Console {
+    Graphical(u32),
+    Serial {
+        unit: u32,
+        speed: u32,
+        /// 'n', 'o', or 'e'
+        parity: char,
+        bits: u32,
+    },
+    /// A console GRUB doesn't know how to use, such as a non-PC serial
+    /// port or a hypervisor console.  Only the kernel is configured.
+    Other {
+        device: String,
+        options: Option<String>,
+    },
+}
+
+impl FromStr for Console {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || {
+            anyhow!(
+                "couldn't parse console '{}'; expected e.g. 'tty0' or 'ttyS0,115200n8'",
+                s
+            )
+        };
+        let (device, options) = match s.split_once(',') {
+            Some((device, options)) => (device, Some(options)),
+            None => (s, None),
+        };
+        let numbered = |prefix| {
+            device
+                .strip_prefix(prefix)
+                .filter(|num: &&str| !num.is_empty() && num.chars().all(|c| c.is_ascii_digit()))
+        };
+        if let Some(unit) = numbered("ttyS") {
+            let unit = unit.parse().map_err(|_| err())?;
+            // kernel defaults
+            let (mut speed, mut parity, mut bits) = (9600, 'n', 8);
+            if let Some(options) = options {
+                let split = options
+                    .find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(options.len());
+                let (num, rest) = options.split_at(split);
+                speed = num.parse().map_err(|_| err())?;
+                let mut chars = rest.chars();
+                if let Some(c) = chars.next() {
+                    parity = Some(c).filter(|c| "noe".contains(*c)).ok_or_else(err)?;
+                }
+                if let Some(c) = chars.next() {
+                    bits = c
+                        .to_digit(10)
+                        .filter(|b| (5..=8).contains(b))
+                        .ok_or_else(err)?;
+                }
+                if chars.next().is_some() {
+                    return Err(err());
+                }
+            }
+            Ok(Self::Serial {
+                unit,
+                speed,
+                parity,
+                bits,
+            })
+        } else if let Some(num) = numbered("tty") {
+            if options.is_some() {
+                return Err(err());
+            }
+            Ok(Self::Graphical(num.parse().map_err(|_| err())?))
+        } else if device.starts_with(|c: char| c.is_ascii_alphabetic())
+            && device
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && options.map_or(true, |o| {
+                !o.is_empty() && !o.contains(|c: char| c.is_whitespace())
+            })
+        {
+            Ok(Self::Other {
+                device: device.into(),
+                options: options.map(String::from),
+            })
+        } else {
+            Err(err())
+        }
+    }
+}
+
+impl fmt::Display for Console {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Graphical(num) => write!(f, "tty{}", num),
+            Self::Serial {
+                unit,
+                speed,
+                parity,
+                bits,
+            } => write!(f, "ttyS{},{}{}{}", unit, speed, parity, bits),
+            Self::Other {
+                device,
+                options: Some(options),
+            } => write!(f, "{},{}", device, options),
+            Self::Other {
+                device,
+                options: None,
+            } => write!(f, "{}", device),
+        }
+    }
+}
+
+impl Console {
+    /// The kernel argument selecting this console.
+    pub fn karg(&self) -> String {
+        format!("console={}", self)
+    }
+
+    /// The GRUB commands for using the specified consoles.
+    pub fn grub_commands(consoles: &[Self]) -> Result<Vec<String>> {
+        let mut commands = Vec::new();
+        let mut terminals = Vec::new();
+        for console in consoles {
+            match console {
+                Self::Graphical(_) => {
+                    if !terminals.contains(&"console") {
+                        terminals.push("console");
+                    }
+                }
+                Self::Serial {
+                    unit,
+                    speed,
+                    parity,
+                    bits,
+                } => {
+                    if terminals.contains(&"serial") {
+                        return Err(anyhow!("GRUB supports only one serial console"));
+                    }
+                    let parity = match parity {
+                        'o' => "odd",
+                        'e' => "even",
+                        _ => "no",
+                    };
+                    commands.push(format!(
+                        "serial --unit={} --speed={} --word={} --parity={}",
+                        unit, speed, bits, parity
+                    ));
+                    terminals.push("serial");
+                }
+                Self::Other { .. } => (),
+            }
+        }
+        if !terminals.is_empty() {
+            commands.push(format!("terminal_input {}", terminals.join(" ")));
+            commands.push(format!("terminal_output {}", terminals.join(" ")));
+        }
+        Ok(commands)
+    }
+}
+
 /// A String wrapper that takes a parameterized type defining the default
 /// value of the String.
 #[derive(Debug, PartialEq, Eq)]
@@ -158,3 +473,137 @@ impl DefaultString for NetworkDir {
 pub(super) fn is_default<T: Default + PartialEq>(value: &T) -> bool {
     value == &T::default()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_console() {
+        for (input, output, karg) in [
+            ("tty0", "tty0", "console=tty0"),
+            ("ttyS0", "ttyS0,9600n8", "console=ttyS0,9600n8"),
+            ("ttyS1,115200", "ttyS1,115200n8", "console=ttyS1,115200n8"),
+            ("ttyS0,115200e", "ttyS0,115200e8", "console=ttyS0,115200e8"),
+            ("ttyS0,57600o7", "ttyS0,57600o7", "console=ttyS0,57600o7"),
+            ("ttyAMA0", "ttyAMA0", "console=ttyAMA0"),
+            ("ttyAMA0,115200", "ttyAMA0,115200", "console=ttyAMA0,115200"),
+            ("hvc0", "hvc0", "console=hvc0"),
+            ("ttysclp0", "ttysclp0", "console=ttysclp0"),
+            ("ttySAC0", "ttySAC0", "console=ttySAC0"),
+            (
+                "uart8250,io,0x3f8,115200",
+                "uart8250,io,0x3f8,115200",
+                "console=uart8250,io,0x3f8,115200",
+            ),
+        ] {
+            let console = Console::from_str(input).unwrap();
+            assert_eq!(console.to_string(), output, "{}", input);
+            assert_eq!(console.karg(), karg, "{}", input);
+        }
+        for input in [
+            "",
+            "tty0,115200",
+            "ttyS0,",
+            "ttyS0,115200x",
+            "ttyS0,115200n9",
+            "ttyS0,115200n8r",
+            "hvc0,",
+            "0",
+            "tty-0",
+            "hvc0,a b",
+        ] {
+            Console::from_str(input).expect_err(input);
+        }
+
+        let consoles = |s: &[&str]| {
+            s.iter()
+                .map(|c| Console::from_str(c).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert!(Console::grub_commands(&[]).unwrap().is_empty());
+        assert_eq!(
+            Console::grub_commands(&consoles(&["tty0", "tty1"])).unwrap(),
+            vec!["terminal_input console", "terminal_output console"]
+        );
+        assert_eq!(
+            Console::grub_commands(&consoles(&["tty0", "ttyS1,115200o7"])).unwrap(),
+            vec![
+                "serial --unit=1 --speed=115200 --word=7 --parity=odd",
+                "terminal_input console serial",
+                "terminal_output console serial"
+            ]
+        );
+        assert_eq!(
+            Console::grub_commands(&consoles(&["ttyAMA0,115200", "hvc0"])).unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(
+            Console::grub_commands(&consoles(&["tty0", "hvc0"])).unwrap(),
+            vec!["terminal_input console", "terminal_output console"]
+        );
+        Console::grub_commands(&consoles(&["ttyS0", "ttyS1"])).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("1K").unwrap(), 1024);
+        assert_eq!(parse_size("1k").unwrap(), 1024);
+        assert_eq!(parse_size("1KiB").unwrap(), 1024);
+        assert_eq!(parse_size("1KB").unwrap(), 1000);
+        assert_eq!(parse_size("100G").unwrap(), 100 << 30);
+        assert_eq!(parse_size("1.5T").unwrap(), 3 << 39);
+        assert_eq!(parse_size("2TB").unwrap(), 2_000_000_000_000);
+        parse_size("").unwrap_err();
+        parse_size("G").unwrap_err();
+        parse_size("1X").unwrap_err();
+        parse_size("1GiBB").unwrap_err();
+    }
+
+    #[test]
+    fn test_device_match() {
+        let m = DeviceMatch::from_str(
+            "size=100G-2T, rotational=false,transport=nvme|sata,model=Samsung*,serial=S4*,wwn=eui.*,removable=no,select=smallest",
+        )
+        .unwrap();
+        assert_eq!(
+            m.terms,
+            vec![
+                DeviceMatchTerm::Size(Some(100 << 30), Some(2 << 40)),
+                DeviceMatchTerm::Rotational(false),
+                DeviceMatchTerm::Transport(vec!["nvme".into(), "sata".into()]),
+                DeviceMatchTerm::Model(glob::Pattern::new("Samsung*").unwrap()),
+                DeviceMatchTerm::Serial(glob::Pattern::new("S4*").unwrap()),
+                DeviceMatchTerm::Wwn(glob::Pattern::new("eui.*").unwrap()),
+                DeviceMatchTerm::Removable(false),
+                DeviceMatchTerm::Select(DeviceSelect::Smallest),
+            ]
+        );
+        // round trip
+        assert_eq!(DeviceMatch::from_str(&m.to_string()).unwrap(), m);
+
+        assert_eq!(
+            DeviceMatch::from_str("size=-1T").unwrap().terms,
+            vec![DeviceMatchTerm::Size(None, Some(1 << 40))]
+        );
+        assert_eq!(
+            DeviceMatch::from_str("size=1T-").unwrap().terms,
+            vec![DeviceMatchTerm::Size(Some(1 << 40), None)]
+        );
+
+        for bad in [
+            "",
+            " , ",
+            "size",
+            "size=1T",
+            "size=x-",
+            "rotational=maybe",
+            "model=[",
+            "select=middle",
+            "color=blue",
+        ] {
+            DeviceMatch::from_str(bad).unwrap_err();
+        }
+    }
+}
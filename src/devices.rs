@@ -0,0 +1,1105 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reporting of disks available for installation, and of CoreOS installs
+//! on them
+
+use anyhow::{Context, Result};
+use openssl::sha;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::read_to_string;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::blkinfo::{BlkInfo, BlockDevice};
+use crate::blockdev::*;
+use crate::cmdline::*;
+use crate::io::read_bls_entry;
+use crate::util::{format_key_values, set_die_on_sigpipe};
+
+/// Mountpoints which indicate that a disk holds the running system or the
+/// live media it was booted from.
+const BOOT_MOUNTPOINTS: [&str; 4] = ["/", "/sysroot", "/boot", "/run/media/iso"];
+
+/// Filesystem types which might contain an OS or its boot loader.
+const OS_FILESYSTEMS: [&str; 6] = ["ext2", "ext3", "ext4", "xfs", "btrfs", "vfat"];
+
+/// Paths in the boot filesystem written by install.
+const IGNITION_CONFIG_PATH: &str = "ignition/config.ign";
+const FIRSTBOOT_PATH: &str = "ignition.firstboot";
+const FIRSTBOOT_NETWORK_DIR: &str = "coreos-firstboot-network";
+
+/// Path in the root filesystem of the record of the installed image.
+const ALEPH_PATH: &str = ".coreos-aleph-version.json";
+
+#[derive(Serialize)]
+struct DeviceList {
+    disks: Vec<DiskReport>,
+}
+
+#[derive(Serialize)]
+struct DiskReport {
+    path: String,
+    size: u64,
+    model: Option<String>,
+    serial: Option<String>,
+    transport: Option<String>,
+    sector_size: Option<u64>,
+    dasd: bool,
+    removable: bool,
+    read_only: bool,
+    /// None if we couldn't tell
+    busy: Option<bool>,
+    holders: Vec<String>,
+    /// None if we couldn't tell
+    coreos_install: Option<bool>,
+    boot_device: bool,
+    partitions: Vec<PartitionReport>,
+}
+
+#[derive(Serialize)]
+struct PartitionReport {
+    path: String,
+    size: u64,
+    fstype: Option<String>,
+    label: Option<String>,
+    partlabel: Option<String>,
+    uuid: Option<String>,
+    mountpoints: Vec<String>,
+    busy: bool,
+}
+
+#[derive(Serialize)]
+struct InstallInspection {
+    device: String,
+    /// Title of the default boot entry
+    deployment: String,
+    /// None if the title doesn't include a version
+    os_version: Option<String>,
+    kargs: String,
+    /// None if the kargs don't set a platform ID
+    platform: Option<String>,
+    /// None if no config is staged
+    ignition: Option<IgnitionInspection>,
+    /// Whether Ignition will run on the next boot
+    firstboot: bool,
+    network_keyfiles: Vec<String>,
+    /// None if the root filesystem couldn't be read
+    install_record: Option<InstallRecord>,
+    partitions: Vec<PartitionReport>,
+}
+
+/// The image originally written to the disk, as recorded in the aleph
+/// version file by the image build.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct InstallRecord {
+    build: Option<String>,
+    #[serde(rename = "ref")]
+    ostree_ref: Option<String>,
+    #[serde(rename(deserialize = "ostree-commit"))]
+    ostree_commit: Option<String>,
+    #[serde(rename(deserialize = "imgid"))]
+    image: Option<String>,
+}
+
+#[derive(Serialize)]
+struct IgnitionInspection {
+    digest: String,
+    /// None if the config can't be parsed
+    version: Option<String>,
+}
+
+pub fn list_devices(config: ListDevicesConfig) -> Result<()> {
+    let info = BlkInfo::default();
+    let mut disks = Vec::new();
+    for dev in info.devices()? {
+        if dev.size == 0 {
+            continue;
+        }
+        if let Some(props) = whole_disk_properties(&info, &dev)? {
+            let mut report = DiskReport::new(&info, &dev, &props)?;
+            report.check_in_use(&dev);
+            report.check_coreos_install();
+            disks.push(report);
+        }
+    }
+    let list = DeviceList { disks };
+
+    set_die_on_sigpipe()?;
+    let out = if config.json {
+        list.to_json()?
+    } else {
+        list.to_text().into_bytes()
+    };
+    io::stdout()
+        .lock()
+        .write_all(&out)
+        .context("writing output")
+}
+
+pub fn inspect(config: InspectConfig) -> Result<()> {
+    let info = BlkInfo::default();
+    let disk = info.device(Path::new(&config.device))?;
+    let partitions = info
+        .tree(&disk)?
+        .into_iter()
+        .filter(|d| d.kind == "part")
+        .map(|d| PartitionReport::new(&info.with_fs_info(d)))
+        .collect::<Result<Vec<_>>>()?;
+    let disk = Disk::new(&config.device)?;
+    let mount = disk
+        .mount_partition_by_label_readonly("boot")
+        .with_context(|| format!("mounting boot filesystem of {}", config.device))?;
+    let mut inspection = InstallInspection::new(&config.device, mount.mountpoint(), partitions)
+        .with_context(|| format!("inspecting CoreOS install on {}", config.device))?;
+    // the root filesystem may be encrypted or otherwise unreadable;
+    // report what we can
+    inspection.install_record = match disk
+        .mount_partition_by_label_readonly("root")
+        .and_then(|root| read_install_record(root.mountpoint()))
+    {
+        Ok(record) => record,
+        Err(e) => {
+            eprintln!(
+                "Couldn't read install record from {}: {:#}",
+                config.device, e
+            );
+            None
+        }
+    };
+
+    set_die_on_sigpipe()?;
+    let out = if config.json {
+        inspection.to_json()?
+    } else {
+        inspection.to_text().into_bytes()
+    };
+    io::stdout()
+        .lock()
+        .write_all(&out)
+        .context("writing output")
+}
+
+impl DiskReport {
+    /// Build a report from the device tree.  `props` is the device whose
+    /// hardware properties describe the disk; see `whole_disk_properties()`.
+    fn new(info: &BlkInfo, dev: &BlockDevice, props: &BlockDevice) -> Result<Self> {
+        let tree = info.tree(dev)?;
+        let partitions = tree
+            .iter()
+            .filter(|d| d.kind == "part")
+            .map(|d| PartitionReport::new(&info.with_fs_info(d.clone())))
+            .collect::<Result<Vec<_>>>()?;
+        let boot_device = tree.iter().any(|d| {
+            d.mountpoints
+                .iter()
+                .any(|m| BOOT_MOUNTPOINTS.contains(&m.as_str()))
+        });
+        Ok(Self {
+            path: dev.path.clone(),
+            size: dev.size,
+            model: props.model.clone(),
+            serial: props.serial.clone(),
+            transport: props.transport.clone(),
+            sector_size: dev.sector_size,
+            dasd: dev.name.starts_with("dasd"),
+            removable: props.removable,
+            read_only: dev.read_only,
+            busy: None,
+            holders: dev
+                .holders()?
+                .iter()
+                .map(|h| Ok(info.device_by_name(h)?.path))
+                .collect::<Result<_>>()?,
+            coreos_install: None,
+            boot_device,
+            partitions,
+        })
+    }
+
+    /// Check whether the disk or any of its partitions are in use, and
+    /// whether it's a DASD.  Both require access to the device node.
+    fn check_in_use(&mut self, dev: &BlockDevice) {
+        // Virtio DASDs can only be recognized by their label
+        if !self.dasd && dev.name.starts_with("vd") {
+            match is_dasd(&self.path, None) {
+                Ok(dasd) => self.dasd = dasd,
+                Err(e) => eprintln!("Couldn't check whether {} is a DASD: {:#}", self.path, e),
+            }
+        }
+        if !self.holders.is_empty() {
+            self.busy = Some(true);
+            return;
+        }
+        self.busy = match Disk::new(&self.path).and_then(|d| d.get_busy_partitions()) {
+            Ok(busy) => Some(!busy.is_empty()),
+            Err(e) => {
+                eprintln!("Couldn't check whether {} is in use: {:#}", self.path, e);
+                None
+            }
+        };
+    }
+
+    /// Check whether the disk has a CoreOS install: a boot filesystem
+    /// containing BLS entries.
+    fn check_coreos_install(&mut self) {
+        let boot = match self
+            .partitions
+            .iter()
+            .find(|p| p.label.as_deref() == Some("boot"))
+        {
+            Some(boot) => boot,
+            None => {
+                self.coreos_install = Some(false);
+                return;
+            }
+        };
+        let result = match (boot.mountpoints.first(), &boot.fstype) {
+            (Some(mountpoint), _) => bls_entry_titles(Path::new(mountpoint)),
+            (None, Some(fstype)) => Mount::try_mount_readonly(&boot.path, fstype)
+                .and_then(|mount| bls_entry_titles(mount.mountpoint())),
+            (None, None) => Ok(Vec::new()),
+        };
+        self.coreos_install = match result {
+            Ok(titles) => Some(!titles.is_empty()),
+            Err(e) => {
+                eprintln!("Couldn't check {} for a CoreOS install: {:#}", boot.path, e);
+                None
+            }
+        };
+    }
+}
+
+impl PartitionReport {
+    fn new(dev: &BlockDevice) -> Result<Self> {
+        Ok(Self {
+            path: dev.path.clone(),
+            size: dev.size,
+            fstype: dev.fstype.clone(),
+            label: dev.label.clone(),
+            partlabel: dev.partlabel.clone(),
+            uuid: dev.uuid.clone(),
+            mountpoints: dev.mountpoints.clone(),
+            busy: !dev.mountpoints.is_empty() || dev.swap || !dev.holders()?.is_empty(),
+        })
+    }
+}
+
+/// Look for an existing OS on the disk: boot loader entries, an OS root
+/// filesystem, or a vendor's EFI boot loader.  Returns a description of
+/// each finding.  Filesystems that can't be mounted, or that need journal
+/// recovery, are skipped with a warning.
+pub fn find_installed_os(device: &str) -> Result<Vec<String>> {
+    let info = BlkInfo::default();
+    let disk = info.device(Path::new(device))?;
+    let mut found = Vec::new();
+    for dev in info.tree(&disk)? {
+        if dev.kind != "part" && dev.path != disk.path {
+            continue;
+        }
+        let dev = info.with_fs_info(dev);
+        let fstype = match dev.fstype.as_deref() {
+            Some(fstype) if OS_FILESYSTEMS.contains(&fstype) => fstype,
+            _ => continue,
+        };
+        let result = match dev.mountpoints.first() {
+            Some(mountpoint) => probe_os(Path::new(mountpoint)),
+            None => Mount::try_mount_readonly(&dev.path, fstype)
+                .and_then(|mount| probe_os(mount.mountpoint())),
+        };
+        match result {
+            Ok(findings) => {
+                found.extend(findings.into_iter().map(|f| format!("{}: {}", dev.path, f)))
+            }
+            Err(e) => eprintln!("Couldn't check {} for an OS: {:#}", dev.path, e),
+        }
+    }
+    Ok(found)
+}
+
+/// Look for signs of an OS in the filesystem mounted at `mountpoint`.
+fn probe_os(mountpoint: &Path) -> Result<Vec<String>> {
+    let mut found = Vec::new();
+    // boot filesystem, or root filesystem with /boot
+    for dir in &["", "boot"] {
+        for title in bls_entry_titles(&mountpoint.join(dir))? {
+            found.push(format!("boot entry \"{}\"", title));
+        }
+    }
+    // root filesystem
+    for path in &["usr/lib/os-release", "etc/os-release"] {
+        if let Some(name) = os_release_name(&mountpoint.join(path))? {
+            found.push(format!("root filesystem of {}", name));
+            break;
+        }
+    }
+    // OSTree root filesystem
+    let pattern = format!(
+        "{}/ostree/deploy/*/deploy/*/usr/lib/os-release",
+        glob::Pattern::escape(&mountpoint.to_string_lossy())
+    );
+    let mut deployments: Vec<PathBuf> = glob::glob(&pattern)
+        .context("building deployment glob")?
+        .filter_map(|p| p.ok())
+        .collect();
+    deployments.sort();
+    for path in deployments {
+        if let Some(name) = os_release_name(&path)? {
+            found.push(format!("OSTree deployment of {}", name));
+        }
+    }
+    // EFI System Partition
+    let efi = mountpoint.join("EFI");
+    if efi.is_dir() {
+        let mut vendors = Vec::new();
+        for ent in efi
+            .read_dir()
+            .with_context(|| format!("reading {}", efi.display()))?
+        {
+            let ent = ent.with_context(|| format!("reading {} entry", efi.display()))?;
+            let name = ent.file_name().to_string_lossy().into_owned();
+            if !name.eq_ignore_ascii_case("boot") && ent.path().is_dir() {
+                vendors.push(name);
+            }
+        }
+        vendors.sort();
+        for vendor in vendors {
+            found.push(format!("EFI boot loader for {}", vendor));
+        }
+    }
+    Ok(found)
+}
+
+/// Return the titles of the BLS entries in the boot filesystem mounted at
+/// `mountpoint`, falling back to the file name for entries without one.
+fn bls_entry_titles(mountpoint: &Path) -> Result<Vec<String>> {
+    let entries = mountpoint.join("loader/entries");
+    if !entries.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut titles = Vec::new();
+    for ent in entries
+        .read_dir()
+        .with_context(|| format!("reading {}", entries.display()))?
+    {
+        let ent = ent.with_context(|| format!("reading {} entry", entries.display()))?;
+        let name = ent.file_name().to_string_lossy().into_owned();
+        if !name.ends_with(".conf") {
+            continue;
+        }
+        let contents = read_to_string(ent.path())
+            .with_context(|| format!("reading {}", ent.path().display()))?;
+        titles.push(
+            contents
+                .lines()
+                .find_map(|l| l.strip_prefix("title "))
+                .map(|t| t.trim().to_string())
+                .unwrap_or(name),
+        );
+    }
+    titles.sort();
+    Ok(titles)
+}
+
+/// Return the pretty name of the OS from an os-release file, if present.
+fn os_release_name(path: &Path) -> Result<Option<String>> {
+    let contents = match read_to_string(path) {
+        Ok(c) => c,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let get = |key: &str| {
+        contents.lines().find_map(|line| {
+            line.strip_prefix(key)?
+                .strip_prefix('=')
+                .map(|v| v.trim_matches(|c| c == '"' || c == '\'').to_string())
+        })
+    };
+    Ok(get("PRETTY_NAME").or_else(|| get("NAME")))
+}
+
+impl DeviceList {
+    fn to_json(&self) -> Result<Vec<u8>> {
+        let mut ret = serde_json::to_vec_pretty(self).context("serializing device list")?;
+        ret.push(b'\n');
+        Ok(ret)
+    }
+
+    fn to_text(&self) -> String {
+        fn unknown(value: Option<bool>, flag: &str) -> Option<String> {
+            match value {
+                Some(true) => Some(flag.into()),
+                Some(false) => None,
+                None => Some(format!("{}?", flag)),
+            }
+        }
+
+        if self.disks.is_empty() {
+            return "No disks found.\n".into();
+        }
+
+        // one row per disk, followed by an indented row per partition
+        let mut rows: Vec<[String; 7]> = vec![[
+            "DEVICE".into(),
+            "SIZE".into(),
+            "TRANSPORT".into(),
+            "SECTOR".into(),
+            "MODEL".into(),
+            "SERIAL".into(),
+            "FLAGS".into(),
+        ]];
+        let mut partitions: Vec<Vec<(String, String, String)>> = vec![Vec::new()];
+        for disk in &self.disks {
+            let flags: Vec<String> = [
+                Some("dasd".to_string()).filter(|_| disk.dasd),
+                Some("removable".to_string()).filter(|_| disk.removable),
+                Some("ro".to_string()).filter(|_| disk.read_only),
+                unknown(disk.busy, "busy"),
+                unknown(disk.coreos_install, "coreos"),
+                Some("boot".to_string()).filter(|_| disk.boot_device),
+            ]
+            .into_iter()
+            .flatten()
+            .collect();
+            rows.push([
+                disk.path.clone(),
+                format_size(disk.size),
+                disk.transport.clone().unwrap_or_default(),
+                disk.sector_size.map(|s| s.to_string()).unwrap_or_default(),
+                disk.model.clone().unwrap_or_default(),
+                disk.serial.clone().unwrap_or_default(),
+                flags.join(","),
+            ]);
+            partitions.push(
+                disk.partitions
+                    .iter()
+                    .map(|p| {
+                        let mut details: Vec<String> = p.fstype.iter().cloned().collect();
+                        if let Some(label) = &p.label {
+                            details.push(format!("\"{}\"", label));
+                        }
+                        if p.busy {
+                            details.push("busy".into());
+                        }
+                        (
+                            format!("  {}", p.path),
+                            format_size(p.size),
+                            details.join(" "),
+                        )
+                    })
+                    .collect(),
+            );
+        }
+
+        let mut widths = [0; 7];
+        for (row, parts) in rows.iter().zip(&partitions) {
+            for (width, field) in widths.iter_mut().zip(row) {
+                *width = (*width).max(field.len());
+            }
+            for (path, size, _) in parts {
+                widths[0] = widths[0].max(path.len());
+                widths[1] = widths[1].max(size.len());
+            }
+        }
+
+        let mut out = String::new();
+        for (row, parts) in rows.iter().zip(&partitions) {
+            let line = row
+                .iter()
+                .zip(widths)
+                .enumerate()
+                .map(|(i, (field, width))| match i {
+                    1 => format!("{:>1$}", field, width),
+                    _ => format!("{:1$}", field, width),
+                })
+                .collect::<Vec<_>>()
+                .join("  ");
+            out.push_str(line.trim_end());
+            out.push('\n');
+            for (path, size, details) in parts {
+                let line = format!(
+                    "{:3$}  {:>4$}  {}",
+                    path, size, details, widths[0], widths[1]
+                );
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// Get the OS version from a BLS entry title, such as
+/// "Fedora CoreOS 36.20220618.3.1 (ostree:0)" or
+/// "Red Hat Enterprise Linux CoreOS 410.84.202205191234-0 (Ootpa) (ostree:0)".
+fn parse_os_version(title: &str) -> Option<String> {
+    title
+        .split_whitespace()
+        .rev()
+        .find(|w| w.starts_with(|c: char| c.is_ascii_digit()))
+        .map(|w| w.to_string())
+}
+
+impl InstallInspection {
+    /// Inspect the boot filesystem mounted at `mountpoint`.
+    fn new(device: &str, mountpoint: &Path, partitions: Vec<PartitionReport>) -> Result<Self> {
+        let entry = read_bls_entry(mountpoint)?;
+        let field = |key: &str| {
+            entry.lines().find_map(|l| {
+                l.strip_prefix(key)
+                    .filter(|v| v.starts_with(' '))
+                    .map(|v| v.trim().to_string())
+            })
+        };
+        let kargs = field("options").unwrap_or_default();
+        let platform = kargs
+            .split_whitespace()
+            .find_map(|a| a.strip_prefix("ignition.platform.id="))
+            .map(|p| p.to_string());
+
+        let ignition = match std::fs::read(mountpoint.join(IGNITION_CONFIG_PATH)) {
+            Ok(data) => {
+                // inspect the config even if it's not a valid Ignition config
+                let config: Option<Value> = serde_json::from_slice(&data).ok();
+                Some(IgnitionInspection {
+                    digest: format!("sha256-{}", hex::encode(sha::sha256(&data))),
+                    version: config
+                        .as_ref()
+                        .and_then(|c| c.pointer("/ignition/version"))
+                        .and_then(|v| v.as_str())
+                        .map(|v| v.to_string()),
+                })
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(e).context("reading Ignition config"),
+        };
+
+        let network_dir = mountpoint.join(FIRSTBOOT_NETWORK_DIR);
+        let mut network_keyfiles = Vec::new();
+        if network_dir.is_dir() {
+            for ent in network_dir
+                .read_dir()
+                .with_context(|| format!("reading {}", network_dir.display()))?
+            {
+                let ent =
+                    ent.with_context(|| format!("reading {} entry", network_dir.display()))?;
+                network_keyfiles.push(ent.file_name().to_string_lossy().into_owned());
+            }
+            network_keyfiles.sort();
+        }
+
+        let deployment = field("title").unwrap_or_default();
+        Ok(Self {
+            device: device.to_string(),
+            os_version: parse_os_version(&deployment),
+            deployment,
+            kargs,
+            platform,
+            ignition,
+            firstboot: mountpoint.join(FIRSTBOOT_PATH).exists(),
+            network_keyfiles,
+            install_record: None,
+            partitions,
+        })
+    }
+
+    fn to_json(&self) -> Result<Vec<u8>> {
+        let mut ret = serde_json::to_vec_pretty(self).context("serializing inspection")?;
+        ret.push(b'\n');
+        Ok(ret)
+    }
+
+    fn to_text(&self) -> String {
+        fn list(items: &[String]) -> String {
+            if items.is_empty() {
+                "none".into()
+            } else {
+                items.join(", ")
+            }
+        }
+
+        let lines: Vec<(&str, String)> = vec![
+            ("Device", self.device.clone()),
+            ("Deployment", self.deployment.clone()),
+            (
+                "OS version",
+                self.os_version.clone().unwrap_or_else(|| "unknown".into()),
+            ),
+            ("Kernel arguments", self.kargs.clone()),
+            (
+                "Platform",
+                self.platform.clone().unwrap_or_else(|| "unknown".into()),
+            ),
+            (
+                "Ignition config",
+                match &self.ignition {
+                    Some(ign) => format!(
+                        "{} ({})",
+                        ign.version
+                            .as_ref()
+                            .map(|v| format!("spec {}", v))
+                            .unwrap_or_else(|| "unparseable".into()),
+                        ign.digest
+                    ),
+                    None => "none".into(),
+                },
+            ),
+            (
+                "First boot",
+                if self.firstboot { "pending" } else { "done" }.into(),
+            ),
+            ("Network keyfiles", list(&self.network_keyfiles)),
+            (
+                "Installed image",
+                match &self.install_record {
+                    Some(InstallRecord {
+                        image: Some(image), ..
+                    }) => image.clone(),
+                    Some(InstallRecord {
+                        build: Some(build), ..
+                    }) => build.clone(),
+                    _ => "unknown".into(),
+                },
+            ),
+        ];
+        let mut out = format_key_values(&lines);
+
+        out.push_str("Partitions:\n");
+        let rows: Vec<(String, String, String)> = self
+            .partitions
+            .iter()
+            .map(|p| {
+                let mut details: Vec<String> = p.fstype.iter().cloned().collect();
+                if let Some(label) = &p.label {
+                    details.push(format!("\"{}\"", label));
+                }
+                if let Some(partlabel) = &p.partlabel {
+                    details.push(format!("partlabel \"{}\"", partlabel));
+                }
+                (p.path.clone(), format_size(p.size), details.join(" "))
+            })
+            .collect();
+        let path_width = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+        let size_width = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+        for (path, size, details) in rows {
+            let line = format!(
+                "  {:3$}  {:>4$}  {}",
+                path, size, details, path_width, size_width
+            );
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Read the install record from the root filesystem mounted at
+/// `mountpoint`.  Returns None if there isn't one.
+fn read_install_record(mountpoint: &Path) -> Result<Option<InstallRecord>> {
+    let path = mountpoint.join(ALEPH_PATH);
+    match std::fs::read(&path) {
+        Ok(data) => Ok(Some(
+            serde_json::from_slice(&data).with_context(|| format!("parsing {}", path.display()))?,
+        )),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+/// Format a size in bytes for humans, using binary units.
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 || size >= 100.0 || size.fract() == 0.0 {
+        format!("{:.0} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> BlkInfo {
+        BlkInfo::with_root(&PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/sysfs/root"))
+    }
+
+    fn reports(info: &BlkInfo) -> Vec<DiskReport> {
+        let mut reports = Vec::new();
+        for dev in info.devices().unwrap() {
+            if dev.size == 0 {
+                continue;
+            }
+            if let Some(props) = whole_disk_properties(info, &dev).unwrap() {
+                reports.push(DiskReport::new(info, &dev, &props).unwrap());
+            }
+        }
+        reports
+    }
+
+    #[test]
+    fn test_disk_reports() {
+        let info = fixture();
+        let root = info.device_by_name("sda").unwrap().path;
+        let root = Path::new(&root).parent().unwrap();
+        let disks = reports(&info);
+        let paths: Vec<&str> = disks
+            .iter()
+            .map(|d| d.path.strip_prefix(root.to_str().unwrap()).unwrap())
+            .collect();
+        assert_eq!(paths, vec!["/sda", "/sdd", "/mapper/mpatha", "/nvme0n1"]);
+
+        let sda = &disks[0];
+        assert_eq!(sda.size, 16 * 1024 * 1024 * 1024);
+        assert_eq!(sda.model.as_deref(), Some("QEMU HARDDISK"));
+        assert_eq!(sda.sector_size, Some(512));
+        assert!(sda.boot_device);
+        assert!(sda.holders.is_empty());
+        assert_eq!(sda.partitions.len(), 4);
+        assert!(!sda.partitions[1].busy);
+        assert!(sda.partitions[2].busy);
+        assert_eq!(sda.partitions[2].mountpoints, vec!["/boot"]);
+
+        let usb = &disks[1];
+        assert!(usb.removable);
+        assert!(!usb.boot_device);
+        assert_eq!(usb.transport.as_deref(), Some("usb"));
+
+        // multipath device takes its properties from the first path
+        let mpath = &disks[2];
+        assert_eq!(mpath.size, 32 * 1024 * 1024 * 1024);
+        assert_eq!(mpath.serial.as_deref(), Some("mpath-lun"));
+        assert_eq!(mpath.transport.as_deref(), Some("scsi"));
+        assert_eq!(mpath.partitions.len(), 1);
+        assert_eq!(mpath.partitions[0].label.as_deref(), Some("boot"));
+
+        let nvme = &disks[3];
+        assert_eq!(nvme.sector_size, Some(4096));
+        assert!(!nvme.boot_device);
+        // LUKS, two RAID members, and swap
+        assert!(nvme.partitions.iter().all(|p| p.busy));
+    }
+
+    #[test]
+    fn test_to_text() {
+        let list = DeviceList {
+            disks: vec![
+                DiskReport {
+                    path: "/dev/sda".into(),
+                    size: 16 * 1024 * 1024 * 1024,
+                    model: Some("QEMU HARDDISK".into()),
+                    serial: Some("drive-scsi0".into()),
+                    transport: Some("scsi".into()),
+                    sector_size: Some(512),
+                    dasd: false,
+                    removable: false,
+                    read_only: false,
+                    busy: Some(true),
+                    holders: Vec::new(),
+                    coreos_install: Some(true),
+                    boot_device: true,
+                    partitions: vec![
+                        PartitionReport {
+                            path: "/dev/sda1".into(),
+                            size: 1024 * 1024,
+                            fstype: None,
+                            label: None,
+                            partlabel: Some("BIOS-BOOT".into()),
+                            uuid: None,
+                            mountpoints: Vec::new(),
+                            busy: false,
+                        },
+                        PartitionReport {
+                            path: "/dev/sda3".into(),
+                            size: 384 * 1024 * 1024,
+                            fstype: Some("ext4".into()),
+                            label: Some("boot".into()),
+                            partlabel: Some("boot".into()),
+                            uuid: None,
+                            mountpoints: vec!["/boot".into()],
+                            busy: true,
+                        },
+                    ],
+                },
+                DiskReport {
+                    path: "/dev/vdb".into(),
+                    size: 1536 * 1024 * 1024,
+                    model: None,
+                    serial: None,
+                    transport: Some("virtio".into()),
+                    sector_size: Some(4096),
+                    dasd: true,
+                    removable: false,
+                    read_only: false,
+                    busy: None,
+                    holders: Vec::new(),
+                    coreos_install: Some(false),
+                    boot_device: false,
+                    partitions: Vec::new(),
+                },
+            ],
+        };
+        assert_eq!(
+            list.to_text(),
+            "DEVICE          SIZE  TRANSPORT  SECTOR  MODEL          SERIAL       FLAGS
+/dev/sda      16 GiB  scsi       512     QEMU HARDDISK  drive-scsi0  busy,coreos,boot
+  /dev/sda1    1 MiB
+  /dev/sda3  384 MiB  ext4 \"boot\" busy
+/dev/vdb     1.5 GiB  virtio     4096                                dasd,busy?
+"
+        );
+        assert_eq!(
+            DeviceList { disks: Vec::new() }.to_text(),
+            "No disks found.\n"
+        );
+    }
+
+    #[test]
+    fn test_probe_os() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |dir: &tempfile::TempDir, path: &str, contents: &str| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        assert!(probe_os(dir.path()).unwrap().is_empty());
+
+        // CoreOS boot filesystem
+        write(
+            &dir,
+            "loader/entries/ostree-1-fedora-coreos.conf",
+            "title Fedora CoreOS 36.20220618.3.1 (ostree:0)\nversion 1\n",
+        );
+        write(&dir, "loader/entries/untitled.conf", "version 2\n");
+        write(&dir, "loader/entries/README", "title ignored\n");
+        assert_eq!(
+            probe_os(dir.path()).unwrap(),
+            vec![
+                "boot entry \"Fedora CoreOS 36.20220618.3.1 (ostree:0)\"",
+                "boot entry \"untitled.conf\""
+            ]
+        );
+
+        // CoreOS root filesystem
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            &dir,
+            "ostree/deploy/fedora-coreos/deploy/abc.0/usr/lib/os-release",
+            "NAME=\"Fedora Linux\"\nPRETTY_NAME=\"Fedora CoreOS 36.20220618.3.1\"\n",
+        );
+        assert_eq!(
+            probe_os(dir.path()).unwrap(),
+            vec!["OSTree deployment of Fedora CoreOS 36.20220618.3.1"]
+        );
+
+        // traditional root filesystem with /boot
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir, "usr/lib/os-release", "NAME=Debian\n");
+        write(&dir, "boot/loader/entries/debian.conf", "title Debian\n");
+        assert_eq!(
+            probe_os(dir.path()).unwrap(),
+            vec!["boot entry \"Debian\"", "root filesystem of Debian"]
+        );
+
+        // ESP
+        let dir = tempfile::tempdir().unwrap();
+        write(&dir, "EFI/BOOT/BOOTX64.EFI", "");
+        write(&dir, "EFI/Microsoft/Boot/bootmgfw.efi", "");
+        write(&dir, "EFI/fedora/shimx64.efi", "");
+        assert_eq!(
+            probe_os(dir.path()).unwrap(),
+            vec![
+                "EFI boot loader for Microsoft",
+                "EFI boot loader for fedora"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_install_inspection() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, contents: &str| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        };
+        let partitions = || {
+            vec![
+                PartitionReport {
+                    path: "/dev/vda1".into(),
+                    size: 1024 * 1024,
+                    fstype: None,
+                    label: None,
+                    partlabel: Some("BIOS-BOOT".into()),
+                    uuid: None,
+                    mountpoints: Vec::new(),
+                    busy: false,
+                },
+                PartitionReport {
+                    path: "/dev/vda3".into(),
+                    size: 384 * 1024 * 1024,
+                    fstype: Some("ext4".into()),
+                    label: Some("boot".into()),
+                    partlabel: Some("boot".into()),
+                    uuid: None,
+                    mountpoints: Vec::new(),
+                    busy: false,
+                },
+            ]
+        };
+
+        // freshly installed, no customizations; the latest entry wins
+        write(
+            "loader/entries/ostree-1-fedora-coreos.conf",
+            "title Fedora CoreOS 35.20220410.3.1 (ostree:1)\nversion 1\noptions old\n",
+        );
+        write(
+            "loader/entries/ostree-2-fedora-coreos.conf",
+            "title Fedora CoreOS 36.20220618.3.1 (ostree:0)\nversion 2\noptions mitigations=auto,nosmt ignition.platform.id=metal $ignition_firstboot\n",
+        );
+        write("ignition.firstboot", "");
+        let inspection = InstallInspection::new("/dev/vda", dir.path(), partitions()).unwrap();
+        assert_eq!(
+            inspection.deployment,
+            "Fedora CoreOS 36.20220618.3.1 (ostree:0)"
+        );
+        assert_eq!(inspection.os_version.as_deref(), Some("36.20220618.3.1"));
+        assert_eq!(
+            inspection.kargs,
+            "mitigations=auto,nosmt ignition.platform.id=metal $ignition_firstboot"
+        );
+        assert_eq!(inspection.platform.as_deref(), Some("metal"));
+        assert!(inspection.ignition.is_none());
+        assert!(inspection.firstboot);
+        assert!(inspection.network_keyfiles.is_empty());
+
+        // customized, already booted
+        write(
+            IGNITION_CONFIG_PATH,
+            r#"{"ignition": {"version": "3.3.0"}}"#,
+        );
+        write("coreos-firstboot-network/b.nmconnection", "");
+        write("coreos-firstboot-network/a.nmconnection", "");
+        std::fs::remove_file(dir.path().join(FIRSTBOOT_PATH)).unwrap();
+        let mut inspection = InstallInspection::new("/dev/vda", dir.path(), partitions()).unwrap();
+        assert!(inspection.install_record.is_none());
+        inspection.install_record = Some(InstallRecord {
+            build: Some("36.20220618.3.1".into()),
+            ostree_ref: None,
+            ostree_commit: None,
+            image: Some("fedora-coreos-36.20220618.3.1-metal.x86_64.raw".into()),
+        });
+        let ignition = inspection.ignition.as_ref().unwrap();
+        assert_eq!(ignition.version.as_deref(), Some("3.3.0"));
+        assert!(ignition.digest.starts_with("sha256-"));
+        assert!(!inspection.firstboot);
+        assert_eq!(
+            inspection.network_keyfiles,
+            vec!["a.nmconnection", "b.nmconnection"]
+        );
+        assert_eq!(
+            inspection.to_text(),
+            format!(
+                "Device:           /dev/vda
+Deployment:       Fedora CoreOS 36.20220618.3.1 (ostree:0)
+OS version:       36.20220618.3.1
+Kernel arguments: mitigations=auto,nosmt ignition.platform.id=metal $ignition_firstboot
+Platform:         metal
+Ignition config:  spec 3.3.0 ({})
+First boot:       done
+Network keyfiles: a.nmconnection, b.nmconnection
+Installed image:  fedora-coreos-36.20220618.3.1-metal.x86_64.raw
+Partitions:
+  /dev/vda1    1 MiB  partlabel \"BIOS-BOOT\"
+  /dev/vda3  384 MiB  ext4 \"boot\" partlabel \"boot\"
+",
+                ignition.digest
+            )
+        );
+
+        // no BLS entries
+        std::fs::remove_dir_all(dir.path().join("loader")).unwrap();
+        assert!(InstallInspection::new("/dev/vda", dir.path(), partitions()).is_err());
+    }
+
+    #[test]
+    fn test_read_install_record() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(read_install_record(dir.path()).unwrap(), None);
+
+        std::fs::write(
+            dir.path().join(ALEPH_PATH),
+            r#"{"build": "36.20220618.3.1", "imgid": "fedora-coreos-36.20220618.3.1-metal.x86_64.raw", "ostree-commit": "0123abcd", "ref": "fedora/x86_64/coreos/stable"}"#,
+        )
+        .unwrap();
+        let record = read_install_record(dir.path()).unwrap().unwrap();
+        assert_eq!(
+            record,
+            InstallRecord {
+                build: Some("36.20220618.3.1".into()),
+                ostree_ref: Some("fedora/x86_64/coreos/stable".into()),
+                ostree_commit: Some("0123abcd".into()),
+                image: Some("fedora-coreos-36.20220618.3.1-metal.x86_64.raw".into()),
+            }
+        );
+        assert_eq!(
+            serde_json::to_value(&record).unwrap(),
+            serde_json::json!({
+                "build": "36.20220618.3.1",
+                "ref": "fedora/x86_64/coreos/stable",
+                "ostree_commit": "0123abcd",
+                "image": "fedora-coreos-36.20220618.3.1-metal.x86_64.raw",
+            })
+        );
+
+        std::fs::write(dir.path().join(ALEPH_PATH), "{").unwrap();
+        read_install_record(dir.path()).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_os_version() {
+        assert_eq!(
+            parse_os_version("Fedora CoreOS 36.20220618.3.1 (ostree:0)").as_deref(),
+            Some("36.20220618.3.1")
+        );
+        assert_eq!(
+            parse_os_version(
+                "Red Hat Enterprise Linux CoreOS 410.84.202205191234-0 (Ootpa) (ostree:1)"
+            )
+            .as_deref(),
+            Some("410.84.202205191234-0")
+        );
+        assert_eq!(parse_os_version("Fedora CoreOS (ostree:0)"), None);
+        assert_eq!(parse_os_version(""), None);
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1024), "1 KiB");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(500107862016), "466 GiB");
+        assert_eq!(format_size(10200547328), "9.5 GiB");
+        assert_eq!(format_size(8 * 1024 * 1024 * 1024 * 1024), "8 TiB");
+        assert_eq!(format_size(200 * 1024 * 1024 + 1), "200 MiB");
+    }
+}
@@ -273,6 +273,9 @@ where
         reader = Box::new(LimitReader::new(reader, limit, conflict));
     }
 
+    // Wrap again to stop if we're interrupted by a signal.
+    reader = Box::new(InterruptReader::new(reader));
+
     // Read the first MiB of input and, if requested, check it against the
     // image's formatted sector size.
     let mut first_mb = [0u8; 1024 * 1024];
@@ -642,7 +645,7 @@ mod tests {
         let saved = SavedPartitions::new_from_file(
             &mut dest,
             512,
-            &vec![PartitionFilter::Label(glob::Pattern::new("*").unwrap())],
+            &[PartitionFilter::Label(glob::Pattern::new("*").unwrap())],
         )
         .unwrap();
         assert!(saved.is_saved());
@@ -684,8 +687,8 @@ mod tests {
         let mb: usize = 1024 * 1024;
 
         let mut data = vec![0u8; len];
-        for i in 0..data.len() {
-            data[i] = (i % 256) as u8;
+        for (i, v) in data.iter_mut().enumerate() {
+            *v = (i % 256) as u8;
         }
 
         // no saved partitions
@@ -706,7 +709,7 @@ mod tests {
         // gptman requires a fixed disk length
         dest.set_len(len as u64).unwrap();
         // create saved
-        let saved = SavedPartitions::new_from_file(&mut dest, 512, &vec![]).unwrap();
+        let saved = SavedPartitions::new_from_file(&mut dest, 512, &[]).unwrap();
         assert!(!saved.is_saved());
         // copy
         source.seek(SeekFrom::Start(mb as u64)).unwrap();
@@ -739,7 +742,7 @@ mod tests {
         let saved = SavedPartitions::new_from_file(
             &mut dest,
             512,
-            &vec![PartitionFilter::Label(glob::Pattern::new("bovik").unwrap())],
+            &[PartitionFilter::Label(glob::Pattern::new("bovik").unwrap())],
         )
         .unwrap();
         assert!(saved.is_saved());
@@ -22,22 +22,50 @@ use std::io::{self, Seek, SeekFrom, Write};
 use std::num::NonZeroU32;
 use std::os::unix::fs::{FileTypeExt, PermissionsExt};
 use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
 use crate::blockdev::*;
 use crate::cmdline::*;
+use crate::devices::find_installed_os;
 use crate::download::*;
 use crate::io::*;
+use crate::preflight::{preflight, Target};
 #[cfg(target_arch = "s390x")]
 use crate::s390x;
 use crate::source::*;
+use crate::util::{catch_interrupts, check_interrupted, interrupted};
 
 // Match the grub.cfg console settings commands in
 // https://github.com/coreos/coreos-assembler/blob/main/src/grub.cfg
 const GRUB_CFG_CONSOLE_SETTINGS_RE: &str = r"(?P<prefix>\n# CONSOLE-SETTINGS-START\n)(?P<commands>([^\n]*\n)*)(?P<suffix># CONSOLE-SETTINGS-END\n)";
 
+/// Kernel arguments needed to boot from a multipath root.
+const MULTIPATH_KARGS: [&str; 3] = [
+    "rd.multipath=default",
+    "root=/dev/disk/by-label/dm-mpath-root",
+    "rw",
+];
+
 pub fn install(config: InstallConfig) -> Result<()> {
     // evaluate config files
-    let config = config.expand_config_files()?;
+    let mut config = config.expand_config_files()?;
+
+    // wait for the destination to show up, if requested; otherwise find
+    // the device matching the criteria, if any
+    if let Some(timeout) = config.wait_for_device {
+        let device = wait_for_device(&config, Duration::from_secs(timeout))?;
+        if config.dest_device_match.is_some() {
+            eprintln!("Selected destination device {}", device);
+        }
+        config.dest_device = Some(device);
+    } else if let Some(criteria) = &config.dest_device_match {
+        let device = find_matching_disk(criteria)
+            .context("selecting destination device with --dest-device-match")?
+            .with_context(|| format!("no disk matches '{}'", criteria))?;
+        eprintln!("Selected destination device {}", device);
+        config.dest_device = Some(device);
+    }
 
     // make sure we have a device path
     let device = config
@@ -45,6 +73,57 @@ pub fn install(config: InstallConfig) -> Result<()> {
         .as_deref()
         .context("destination device must be specified")?;
 
+    // writing to one path of a multipath device would corrupt it
+    if let Some(mpath) = find_multipath_holder(device)
+        .with_context(|| format!("checking whether {} is a multipath path", device))?
+    {
+        bail!(
+            "{} is a path of multipath device {}; install to {} instead",
+            device,
+            mpath,
+            mpath
+        );
+    }
+
+    // make sure a multipath root will be assembled at boot
+    if is_multipath(device)
+        .with_context(|| format!("checking whether {} is a multipath device", device))?
+        && !config
+            .append_karg
+            .iter()
+            .any(|a| a.starts_with("rd.multipath="))
+    {
+        if config.multipath_kargs {
+            eprintln!(
+                "Appending multipath kernel arguments: {}",
+                MULTIPATH_KARGS.join(" ")
+            );
+            config
+                .append_karg
+                .extend(MULTIPATH_KARGS.iter().map(|a| a.to_string()));
+        } else {
+            eprintln!(
+                "Warning: installing to multipath device {} without rd.multipath=default kernel argument",
+                device
+            );
+            eprintln!("Use --multipath-kargs to append the arguments needed to boot from it.");
+        }
+    }
+
+    // look for an existing OS; preflight will refuse to overwrite it
+    // unless requested
+    let installed = find_installed_os(device)
+        .with_context(|| format!("checking for an existing OS on {}", device))?;
+    if !installed.is_empty() {
+        eprintln!("Found existing OS on {}:", device);
+        for finding in &installed {
+            eprintln!("  {}", finding);
+        }
+        if config.force_overwrite_os {
+            eprintln!("Overwriting as requested with --force-overwrite-os");
+        }
+    }
+
     // find Ignition config
     let ignition = if let Some(file) = &config.ignition_file {
         Some(
@@ -116,44 +195,67 @@ pub fn install(config: InstallConfig) -> Result<()> {
         Box::new(FileLocation::new(image_file))
     } else if let Some(image_url) = &config.image_url {
         Box::new(UrlLocation::new(image_url, config.fetch_retries))
-    } else if config.offline {
-        match OsmetLocation::new(config.architecture.as_str(), sector_size)? {
+    } else if config.offline || config.offline_version.is_some() {
+        let version = config
+            .offline_version
+            .as_deref()
+            .map(|v| {
+                glob::Pattern::new(v)
+                    .with_context(|| format!("couldn't parse version glob '{}'", v))
+            })
+            .transpose()?;
+        match OsmetLocation::new(config.architecture.as_str(), sector_size, version.as_ref())? {
             Some(osmet) => Box::new(osmet),
-            None => bail!("cannot perform offline install; metadata missing"),
+            None => match &config.offline_version {
+                Some(v) => bail!(
+                    "cannot perform offline install; metadata for version '{}' missing",
+                    v
+                ),
+                None => bail!("cannot perform offline install; metadata missing"),
+            },
         }
     } else {
-        // For now, using --stream automatically will cause a download. In the future, we could
-        // opportunistically use osmet if the version and stream match an osmet file/the live ISO.
-
+        let format = match sector_size {
+            4096 => "4k.raw.xz",
+            // could bail on non-512, but let's be optimistic and try
+            // the regular 512b image; preflight will warn
+            _ => "raw.xz",
+        };
+        let stream_location = StreamLocation::new(
+            config.stream.as_deref().unwrap_or("stable"),
+            config.architecture.as_str(),
+            "metal",
+            format,
+            config.stream_base_url.as_ref(),
+            config.fetch_retries,
+        )?;
+
+        // Without --stream, use any matching osmet file.  With --stream,
+        // only use an osmet file if it matches the version currently in
+        // the stream; otherwise download.  The stream metadata is cached,
+        // so it's only fetched once.
         let maybe_osmet = match config.stream {
-            Some(_) => None,
-            None => OsmetLocation::new(config.architecture.as_str(), sector_size)?,
+            Some(_) => match OsmetLocation::new(config.architecture.as_str(), sector_size, None)? {
+                Some(_) => match stream_location.release()? {
+                    Some(release) => {
+                        let version = glob::Pattern::new(&glob::Pattern::escape(&release))
+                            .context("building version glob")?;
+                        OsmetLocation::new(
+                            config.architecture.as_str(),
+                            sector_size,
+                            Some(&version),
+                        )?
+                    }
+                    None => None,
+                },
+                None => None,
+            },
+            None => OsmetLocation::new(config.architecture.as_str(), sector_size, None)?,
         };
 
-        if let Some(osmet) = maybe_osmet {
-            Box::new(osmet)
-        } else {
-            let format = match sector_size {
-                4096 => "4k.raw.xz",
-                512 => "raw.xz",
-                n => {
-                    // could bail on non-512, but let's be optimistic and just warn but try the regular
-                    // 512b image
-                    eprintln!(
-                        "Found non-standard sector size {} for {}, assuming 512b-compatible",
-                        n, device
-                    );
-                    "raw.xz"
-                }
-            };
-            Box::new(StreamLocation::new(
-                config.stream.as_deref().unwrap_or("stable"),
-                config.architecture.as_str(),
-                "metal",
-                format,
-                config.stream_base_url.as_ref(),
-                config.fetch_retries,
-            )?)
+        match maybe_osmet {
+            Some(osmet) => Box::new(osmet),
+            None => Box::new(stream_location),
         }
     };
     // report it to the user
@@ -172,6 +274,30 @@ pub fn install(config: InstallConfig) -> Result<()> {
         }
     }
 
+    // find the image size, if the source doesn't know it
+    if source.image_size.is_none() {
+        source.image_size = source.peek_image_size().context("finding size of image")?;
+    }
+
+    // check for problems before touching the destination
+    preflight(&Target {
+        device,
+        architecture: config.architecture.as_str(),
+        sector_size,
+        image_size: source.image_size,
+        existing_os: !installed.is_empty() && !config.force_overwrite_os,
+    })?;
+    if config.preflight_only {
+        eprintln!("Preflight checks passed; not installing as requested");
+        return Ok(());
+    }
+
+    // release devices stacked on the destination, if requested
+    if config.deactivate_holders {
+        deactivate_holders(device)
+            .with_context(|| format!("deactivating holders of {}", device))?;
+    }
+
     // set up DASD
     #[cfg(target_arch = "s390x")]
     {
@@ -200,7 +326,7 @@ pub fn install(config: InstallConfig) -> Result<()> {
     {
         bail!("{} is not a block device", device);
     }
-    ensure_exclusive_access(device)
+    ensure_exclusive_access(device, "use --deactivate-holders to release them")
         .with_context(|| format!("checking for exclusive access to {}", device))?;
 
     // save partitions that we plan to keep
@@ -215,8 +341,9 @@ pub fn install(config: InstallConfig) -> Result<()> {
         .with_context(|| format!("getting partition table for {}", device))?;
 
     // copy and postprocess disk image
-    // On failure, clear and reread the partition table to prevent the disk
-    // from accidentally being used.
+    // On failure, or if we're interrupted by a signal, clear and reread
+    // the partition table to prevent the disk from accidentally being used.
+    catch_interrupts()?;
     dest.seek(SeekFrom::Start(0))
         .with_context(|| format!("seeking {}", device))?;
     if let Err(err) = write_disk(
@@ -227,10 +354,15 @@ pub fn install(config: InstallConfig) -> Result<()> {
         &saved,
         ignition,
         network_config,
-    ) {
+    )
+    .and_then(|_| check_interrupted())
+    {
         // log the error so the details aren't dropped if we encounter
         // another error during cleanup
         eprintln!("\nError: {:?}\n", err);
+        if interrupted().is_some() {
+            eprintln!("Cleaning up; interrupt again to exit immediately");
+        }
 
         // clean up
         if config.preserve_on_error {
@@ -249,6 +381,9 @@ pub fn install(config: InstallConfig) -> Result<()> {
         }
 
         // return a generic error so our exit status is right
+        if let Some(interrupted) = interrupted() {
+            return Err(interrupted.into());
+        }
         bail!("install failed");
     }
 
@@ -283,6 +418,81 @@ wiping them with `wipefs -a`.\n"
     Ok(())
 }
 
+/// Apply install-time customizations to an existing install, without
+/// rewriting the image.
+pub fn reconfigure(config: ReconfigureConfig) -> Result<()> {
+    let device = config.device.as_str();
+    let ignition = config
+        .ignition_file
+        .as_ref()
+        .map(|file| {
+            OpenOptions::new()
+                .read(true)
+                .open(file)
+                .with_context(|| format!("opening source Ignition config {}", file))
+        })
+        .transpose()?;
+    let changes = BootChanges {
+        ignition: ignition.as_ref(),
+        ignition_hash: config.ignition_hash.as_ref(),
+        platform: config.platform.as_deref(),
+        console: &config.console,
+        append_karg: &config.append_karg,
+        delete_karg: &config.delete_karg,
+        network_config: if config.copy_network {
+            Some(config.network_dir.as_str())
+        } else {
+            None
+        },
+        ..Default::default()
+    };
+    if changes.is_empty() && !config.rearm_firstboot {
+        bail!("no changes requested");
+    }
+
+    // the installed system mustn't be running
+    ensure_exclusive_access(
+        device,
+        "reconfigure the disk from another system, or unmount them",
+    )
+    .with_context(|| format!("checking for exclusive access to {}", device))?;
+
+    let mount = Disk::new(device)?.mount_partition_by_label("boot", mount::MsFlags::empty())?;
+    let mountpoint = mount.mountpoint();
+    if ignition.is_some() && mountpoint.join("ignition/config.ign").exists() {
+        // the new config is validated and written before the old one is
+        // replaced
+        eprintln!("Replacing existing Ignition config");
+    }
+    changes.apply(mountpoint)?;
+
+    if config.rearm_firstboot {
+        rearm_firstboot(mountpoint)?;
+    } else if ignition.is_some() && !mountpoint.join("ignition.firstboot").exists() {
+        eprintln!("Warning: first-boot provisioning has already run, so the new Ignition config");
+        eprintln!("won't be applied unless it's re-armed with --rearm-firstboot.");
+    }
+
+    #[cfg(target_arch = "s390x")]
+    s390x::zipl(mountpoint, None, None, None, s390x::ZiplSecexMode::Disable)?;
+
+    eprintln!("Reconfiguration complete.");
+    Ok(())
+}
+
+/// Create the stamp file that makes Ignition run on the next boot.  Keep
+/// an existing one, which may contain first-boot kernel arguments.
+fn rearm_firstboot(mountpoint: &Path) -> Result<()> {
+    let path = mountpoint.join("ignition.firstboot");
+    if path.exists() {
+        eprintln!("First-boot provisioning is already armed");
+    } else {
+        eprintln!("Re-arming first-boot provisioning");
+        File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+    }
+    Ok(())
+}
+
 fn parse_partition_filters(labels: &[&str], indexes: &[&str]) -> Result<Vec<PartitionFilter>> {
     use PartitionFilter::*;
     let mut filters: Vec<PartitionFilter> = Vec::new();
@@ -331,7 +541,55 @@ fn parse_partition_filters(labels: &[&str], indexes: &[&str]) -> Result<Vec<Part
     Ok(filters)
 }
 
-fn ensure_exclusive_access(device: &str) -> Result<()> {
+/// Wait for the destination device, or a disk matching the destination
+/// criteria, to appear and settle, and return its path.
+fn wait_for_device(config: &InstallConfig, timeout: Duration) -> Result<String> {
+    let description = match (&config.dest_device_match, &config.dest_device) {
+        (Some(criteria), _) => format!("a disk matching '{}'", criteria),
+        (None, Some(device)) => device.clone(),
+        (None, None) => bail!("destination device must be specified"),
+    };
+    eprintln!(
+        "Waiting up to {} seconds for {}",
+        timeout.as_secs(),
+        description
+    );
+    // a timeout too large to represent is as good as none
+    let deadline = Instant::now().checked_add(timeout);
+    let mut previous = None;
+    loop {
+        let path = match &config.dest_device_match {
+            Some(criteria) => find_matching_disk(criteria)
+                .context("selecting destination device with --dest-device-match")?,
+            None => config.dest_device.clone(),
+        };
+        let current = match path {
+            Some(path) => get_settled_device(&path)
+                .with_context(|| format!("checking {}", path))?
+                .map(|state| (path, state)),
+            None => None,
+        };
+        // require the same result twice in a row, so we don't pick up a
+        // device that's still being set up
+        if let Some((path, _)) = current.as_ref().filter(|_| current == previous) {
+            eprintln!("Found {}", path);
+            return Ok(path.clone());
+        }
+        if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+            bail!(
+                "timed out after {} seconds waiting for {}",
+                timeout.as_secs(),
+                description
+            );
+        }
+        previous = current;
+        sleep(Duration::from_secs(1));
+    }
+}
+
+/// Fail if any partitions on the device are in use, reporting their users
+/// and suggesting `hint` to release them.
+fn ensure_exclusive_access(device: &str, hint: &str) -> Result<()> {
     let mut parts = Disk::new(device)?.get_busy_partitions()?;
     if parts.is_empty() {
         return Ok(());
@@ -339,17 +597,11 @@ fn ensure_exclusive_access(device: &str) -> Result<()> {
     parts.sort_unstable_by_key(|p| p.path.to_string());
     eprintln!("Partitions in use on {}:", device);
     for part in parts {
-        if let Some(mountpoint) = part.mountpoint.as_ref() {
-            eprintln!("    {} mounted on {}", part.path, mountpoint);
-        }
-        if part.swap {
-            eprintln!("    {} is swap device", part.path);
-        }
-        for holder in part.get_holders()? {
-            eprintln!("    {} in use by {}", part.path, holder);
+        for user in describe_device_users(&part.path)? {
+            eprintln!("    {}", user);
         }
     }
-    bail!("found busy partitions");
+    bail!("found busy partitions; {}", hint);
 }
 
 /// Copy the image source to the target disk and do all post-processing.
@@ -389,40 +641,19 @@ fn write_disk(
     table.reread()?;
 
     // postprocess
-    if ignition.is_some()
-        || config.firstboot_args.is_some()
-        || !config.append_karg.is_empty()
-        || !config.delete_karg.is_empty()
-        || config.platform.is_some()
-        || network_config.is_some()
-        || cfg!(target_arch = "s390x")
-    {
+    let changes = BootChanges {
+        ignition: ignition.as_ref(),
+        ignition_hash: config.ignition_hash.as_ref(),
+        platform: config.platform.as_deref(),
+        console: &[],
+        firstboot_args: config.firstboot_args.as_deref(),
+        append_karg: &config.append_karg,
+        delete_karg: &config.delete_karg,
+        network_config,
+    };
+    if !changes.is_empty() || cfg!(target_arch = "s390x") {
         let mount = Disk::new(device)?.mount_partition_by_label("boot", mount::MsFlags::empty())?;
-        if let Some(ignition) = ignition.as_ref() {
-            write_ignition(mount.mountpoint(), &config.ignition_hash, ignition)
-                .context("writing Ignition configuration")?;
-        }
-        if let Some(platform) = config.platform.as_ref() {
-            write_platform(mount.mountpoint(), platform).context("writing platform ID")?;
-        }
-        if let Some(firstboot_args) = config.firstboot_args.as_ref() {
-            write_firstboot_kargs(mount.mountpoint(), firstboot_args)
-                .context("writing firstboot kargs")?;
-        }
-        if !config.append_karg.is_empty() || !config.delete_karg.is_empty() {
-            eprintln!("Modifying kernel arguments");
-
-            visit_bls_entry_options(mount.mountpoint(), |orig_options: &str| {
-                KargsEditor::new()
-                    .append(config.append_karg.as_slice())
-                    .delete(config.delete_karg.as_slice())
-                    .maybe_apply_to(orig_options)
-            })
-            .context("deleting and appending kargs")?;
-        }
-        if let Some(network_config) = network_config.as_ref() {
-            copy_network_config(mount.mountpoint(), network_config)?;
-        }
+        changes.apply(mount.mountpoint())?;
         #[cfg(target_arch = "s390x")]
         {
             s390x::zipl(
@@ -442,16 +673,74 @@ fn write_disk(
     Ok(())
 }
 
+/// Changes to the boot filesystem of an installed system.
+#[derive(Default)]
+struct BootChanges<'a> {
+    ignition: Option<&'a File>,
+    ignition_hash: Option<&'a IgnitionHash>,
+    platform: Option<&'a str>,
+    console: &'a [Console],
+    firstboot_args: Option<&'a str>,
+    append_karg: &'a [String],
+    delete_karg: &'a [String],
+    network_config: Option<&'a str>,
+}
+
+impl BootChanges<'_> {
+    fn is_empty(&self) -> bool {
+        self.ignition.is_none()
+            && self.firstboot_args.is_none()
+            && self.append_karg.is_empty()
+            && self.delete_karg.is_empty()
+            && self.platform.is_none()
+            && self.console.is_empty()
+            && self.network_config.is_none()
+    }
+
+    /// Apply the changes to the boot filesystem mounted at `mountpoint`.
+    /// Doesn't run `zipl` on s390x.
+    fn apply(&self, mountpoint: &Path) -> Result<()> {
+        if let Some(ignition) = self.ignition {
+            write_ignition(mountpoint, self.ignition_hash, ignition)
+                .context("writing Ignition configuration")?;
+        }
+        if let Some(platform) = self.platform {
+            write_platform(mountpoint, platform).context("writing platform ID")?;
+        }
+        if !self.console.is_empty() {
+            write_console(mountpoint, self.console).context("writing console settings")?;
+        }
+        if let Some(firstboot_args) = self.firstboot_args {
+            write_firstboot_kargs(mountpoint, firstboot_args).context("writing firstboot kargs")?;
+        }
+        if !self.append_karg.is_empty() || !self.delete_karg.is_empty() {
+            eprintln!("Modifying kernel arguments");
+
+            visit_bls_entry_options(mountpoint, |orig_options: &str| {
+                KargsEditor::new()
+                    .append(self.append_karg)
+                    .delete(self.delete_karg)
+                    .maybe_apply_to(orig_options)
+            })
+            .context("deleting and appending kargs")?;
+        }
+        if let Some(network_config) = self.network_config {
+            copy_network_config(mountpoint, network_config)?;
+        }
+        Ok(())
+    }
+}
+
 /// Write the Ignition config.
 fn write_ignition(
     mountpoint: &Path,
-    digest_in: &Option<IgnitionHash>,
+    digest_in: Option<&IgnitionHash>,
     mut config_in: &File,
 ) -> Result<()> {
     eprintln!("Writing Ignition config");
 
     // Verify configuration digest, if any.
-    if let Some(digest) = &digest_in {
+    if let Some(digest) = digest_in {
         digest
             .validate(&mut config_in)
             .context("failed to validate Ignition configuration digest")?;
@@ -480,25 +769,28 @@ fn write_ignition(
     }
 
     // do the copy
-    config_dest.push("config.ign");
-    let mut config_out = OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&config_dest)
+    // Write to a temporary file and rename it into place, so a failure
+    // doesn't leave behind a truncated config or lose an existing one.
+    // The temporary file is only accessible by root, since the config
+    // may contain secrets.
+    let mut config_out = tempfile::Builder::new()
+        .prefix(".config.ign.")
+        .tempfile_in(&config_dest)
         .with_context(|| {
             format!(
-                "opening destination Ignition config {}",
+                "creating temporary Ignition config in {}",
                 config_dest.display()
             )
         })?;
-    // Ignition config may contain secrets; restrict to root
-    fs::set_permissions(&config_dest, Permissions::from_mode(0o600)).with_context(|| {
-        format!(
-            "setting file mode for destination Ignition config {}",
-            config_dest.display()
-        )
-    })?;
     io::copy(&mut config_in, &mut config_out).context("writing Ignition config")?;
+    config_out
+        .as_file()
+        .sync_all()
+        .context("syncing Ignition config")?;
+    config_dest.push("config.ign");
+    config_out
+        .persist(&config_dest)
+        .with_context(|| format!("renaming Ignition config to {}", config_dest.display()))?;
 
     Ok(())
 }
@@ -533,23 +825,32 @@ struct PlatformSpec {
 }
 
 /// Override the platform ID.  Add any kernel arguments and grub.cfg
-/// directives specified for this platform in platforms.json.
+/// directives specified for this platform in platforms.json, replacing
+/// those of the current platform.
 fn write_platform(mountpoint: &Path, platform: &str) -> Result<()> {
-    // early return if setting the platform to the default value, since
-    // otherwise we'll think we failed to set it
-    if platform == "metal" {
+    // early return if the platform is already set, since otherwise we'll
+    // think we failed to set it.  Freshly installed images are always
+    // "metal", so treat a missing platform ID as that.
+    let current = read_bls_entry(mountpoint)?
+        .lines()
+        .filter_map(|l| l.strip_prefix("options "))
+        .flat_map(|o| o.split_whitespace())
+        .find_map(|a| a.strip_prefix("ignition.platform.id="))
+        .unwrap_or("metal")
+        .to_string();
+    if platform == current {
         return Ok(());
     }
 
     // read platforms table
-    let (spec, metal_spec) = match fs::read_to_string(mountpoint.join("coreos/platforms.json")) {
+    let (spec, current_spec) = match fs::read_to_string(mountpoint.join("coreos/platforms.json")) {
         Ok(json) => {
             let mut table: HashMap<String, PlatformSpec> =
                 serde_json::from_str(&json).context("parsing platform table")?;
-            // no spec for this platform, or for metal?
+            // no spec for this platform, or for the current one?
             (
                 table.remove(platform).unwrap_or_default(),
-                table.remove("metal").unwrap_or_default(),
+                table.remove(&current).unwrap_or_default(),
             )
         }
         // no table for this image?
@@ -557,42 +858,74 @@ fn write_platform(mountpoint: &Path, platform: &str) -> Result<()> {
         Err(e) => return Err(e).context("reading platform table"),
     };
 
-    // set kargs, removing any metal-specific ones
+    // set kargs, removing any specific to the current platform
     eprintln!("Setting platform to {}", platform);
     visit_bls_entry_options(mountpoint, |orig_options: &str| {
         bls_entry_options_write_platform(
             orig_options,
+            &current,
             platform,
             &spec.kernel_arguments,
-            &metal_spec.kernel_arguments,
+            &current_spec.kernel_arguments,
         )
     })?;
 
     // set grub commands
-    if spec.grub_commands != metal_spec.grub_commands {
-        let path = mountpoint.join("grub2/grub.cfg");
-        let grub_cfg = fs::read_to_string(&path).context("reading grub.cfg")?;
-        let new_grub_cfg = update_grub_cfg_console_settings(&grub_cfg, &spec.grub_commands)
-            .context("updating grub.cfg")?;
-        fs::write(&path, new_grub_cfg).context("writing grub.cfg")?;
+    if spec.grub_commands != current_spec.grub_commands {
+        write_grub_console_settings(mountpoint, &spec.grub_commands)?;
     }
     Ok(())
 }
 
+/// Configure the kernel and GRUB to use the specified consoles, replacing
+/// any console settings from the platform.
+fn write_console(mountpoint: &Path, consoles: &[Console]) -> Result<()> {
+    let grub_commands = Console::grub_commands(consoles)?;
+    let kargs: Vec<String> = consoles.iter().map(Console::karg).collect();
+    eprintln!("Setting console to {}", kargs.join(" "));
+    visit_bls_entry_options(mountpoint, |orig_options: &str| {
+        let existing: Vec<String> = orig_options
+            .split_whitespace()
+            .filter(|a| a.starts_with("console="))
+            .map(|a| a.to_string())
+            .collect();
+        KargsEditor::new()
+            .delete(&existing)
+            .append(&kargs)
+            .maybe_apply_to(orig_options)
+    })
+    .context("setting console kernel arguments")?;
+    write_grub_console_settings(mountpoint, &grub_commands)
+}
+
+/// Replace the console settings in grub.cfg, if there is one.  s390x
+/// boots via zipl and has none.
+fn write_grub_console_settings(mountpoint: &Path, commands: &[String]) -> Result<()> {
+    let path = mountpoint.join("grub2/grub.cfg");
+    let grub_cfg = match fs::read_to_string(&path) {
+        Ok(grub_cfg) => grub_cfg,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).context("reading grub.cfg"),
+    };
+    let new_grub_cfg =
+        update_grub_cfg_console_settings(&grub_cfg, commands).context("updating grub.cfg")?;
+    fs::write(&path, new_grub_cfg).context("writing grub.cfg")
+}
+
 /// To be used with `visit_bls_entry_options()`.  Modifies the BLS config,
-/// changing the `ignition.platform.id` and then appending/deleting any
-/// specified kargs.  This assumes that we will only install from metal
-/// images and that the bootloader configs will always set
-/// ignition.platform.id.  Fail if those assumptions change.  This is
-/// deliberately simplistic.
+/// changing the `ignition.platform.id` from `current` to `platform` and
+/// then appending/deleting any specified kargs.  This assumes that the
+/// bootloader configs will always set ignition.platform.id.  Fail if that
+/// assumption changes.  This is deliberately simplistic.
 fn bls_entry_options_write_platform(
     orig_options: &str,
+    current: &str,
     platform: &str,
     append_kargs: &[String],
     delete_kargs: &[String],
 ) -> Result<Option<String>> {
     let new_options = KargsEditor::new()
-        .replace(&[format!("ignition.platform.id=metal={}", platform)])
+        .replace(&[format!("ignition.platform.id={}={}", current, platform)])
         .apply_to(orig_options)
         .context("updating platform ID")?;
     if orig_options == new_options {
@@ -646,7 +979,7 @@ fn copy_network_config(mountpoint: &Path, net_config_src: &str) -> Result<()> {
     })?;
 
     // copy files from source to destination directories
-    for entry in fs::read_dir(&net_config_src)
+    for entry in fs::read_dir(net_config_src)
         .with_context(|| format!("reading directory {}", net_config_src))?
     {
         let entry = entry.with_context(|| format!("reading directory {}", net_config_src))?;
@@ -778,13 +1111,177 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_write_ignition() {
+        use std::str::FromStr;
+
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("ignition/config.ign");
+        let source = |data: &[u8]| {
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(data).unwrap();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file
+        };
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        write_ignition(dir.path(), None, &source(b"old")).unwrap();
+        assert_eq!(fs::read(&config_path).unwrap(), b"old");
+        assert_eq!(mode(&config_path), 0o600);
+        assert_eq!(mode(&dir.path().join("ignition")), 0o700);
+
+        // a config failing validation leaves the existing one alone
+        let hash = IgnitionHash::from_str(
+            "sha256-ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+        )
+        .unwrap();
+        write_ignition(dir.path(), Some(&hash), &source(b"new")).unwrap_err();
+        assert_eq!(fs::read(&config_path).unwrap(), b"old");
+
+        // a valid one replaces it, without leaving temporary files
+        write_ignition(dir.path(), Some(&hash), &source(b"abc")).unwrap();
+        assert_eq!(fs::read(&config_path).unwrap(), b"abc");
+        assert_eq!(mode(&config_path), 0o600);
+        assert_eq!(
+            fs::read_dir(dir.path().join("ignition")).unwrap().count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_write_console() {
+        use std::str::FromStr;
+
+        let dir = tempfile::tempdir().unwrap();
+        let bls_path = dir
+            .path()
+            .join("loader/entries/ostree-1-fedora-coreos.conf");
+        let grub_path = dir.path().join("grub2/grub.cfg");
+        fs::create_dir_all(bls_path.parent().unwrap()).unwrap();
+        fs::create_dir_all(grub_path.parent().unwrap()).unwrap();
+        fs::write(
+            &bls_path,
+            "title Fedora CoreOS\noptions root=UUID=x console=tty0 console=ttyS0,115200n8 ignition.platform.id=qemu\n",
+        )
+        .unwrap();
+        fs::write(
+            &grub_path,
+            "a\n# CONSOLE-SETTINGS-START\nserial --speed=115200\nterminal_input serial console\nterminal_output serial console\n# CONSOLE-SETTINGS-END\nb\n",
+        )
+        .unwrap();
+
+        write_console(
+            dir.path(),
+            &[
+                Console::from_str("tty0").unwrap(),
+                Console::from_str("ttyS1,9600").unwrap(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&bls_path).unwrap(),
+            "title Fedora CoreOS\noptions root=UUID=x ignition.platform.id=qemu console=tty0 console=ttyS1,9600n8\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&grub_path).unwrap(),
+            "a\n# CONSOLE-SETTINGS-START\nserial --unit=1 --speed=9600 --word=8 --parity=no\nterminal_input console serial\nterminal_output console serial\n# CONSOLE-SETTINGS-END\nb\n"
+        );
+
+        // no grub.cfg, as on s390x
+        fs::remove_file(&grub_path).unwrap();
+        write_console(dir.path(), &[Console::from_str("ttyS0").unwrap()]).unwrap();
+        assert_eq!(
+            fs::read_to_string(&bls_path).unwrap(),
+            "title Fedora CoreOS\noptions root=UUID=x ignition.platform.id=qemu console=ttyS0,9600n8\n"
+        );
+    }
+
+    #[test]
+    fn test_write_platform() {
+        let dir = tempfile::tempdir().unwrap();
+        let bls_path = dir
+            .path()
+            .join("loader/entries/ostree-1-fedora-coreos.conf");
+        let grub_path = dir.path().join("grub2/grub.cfg");
+        fs::create_dir_all(bls_path.parent().unwrap()).unwrap();
+        fs::create_dir_all(grub_path.parent().unwrap()).unwrap();
+        fs::create_dir_all(dir.path().join("coreos")).unwrap();
+        fs::write(
+            dir.path().join("coreos/platforms.json"),
+            r#"{
+                "qemu": {
+                    "grub_commands": ["serial --speed=115200", "terminal_input serial console", "terminal_output serial console"],
+                    "kernel_arguments": ["console=tty0", "console=ttyS0,115200n8"]
+                },
+                "vmware": {
+                    "kernel_arguments": ["console=ttyS0,115200n8"]
+                }
+            }"#,
+        )
+        .unwrap();
+        // an installed system previously switched to qemu
+        fs::write(
+            &bls_path,
+            "title Fedora CoreOS\noptions root=UUID=x ignition.platform.id=qemu console=tty0 console=ttyS0,115200n8\n",
+        )
+        .unwrap();
+        fs::write(
+            &grub_path,
+            "a\n# CONSOLE-SETTINGS-START\nserial --speed=115200\nterminal_input serial console\nterminal_output serial console\n# CONSOLE-SETTINGS-END\nb\n",
+        )
+        .unwrap();
+        let bls = || fs::read_to_string(&bls_path).unwrap();
+        let grub = || fs::read_to_string(&grub_path).unwrap();
+
+        // the qemu settings are replaced, not the metal ones
+        write_platform(dir.path(), "vmware").unwrap();
+        assert_eq!(
+            bls(),
+            "title Fedora CoreOS\noptions root=UUID=x ignition.platform.id=vmware console=ttyS0,115200n8\n"
+        );
+        assert_eq!(
+            grub(),
+            "a\n# CONSOLE-SETTINGS-START\n# CONSOLE-SETTINGS-END\nb\n"
+        );
+
+        // no change
+        write_platform(dir.path(), "vmware").unwrap();
+        assert_eq!(
+            bls(),
+            "title Fedora CoreOS\noptions root=UUID=x ignition.platform.id=vmware console=ttyS0,115200n8\n"
+        );
+
+        // back to metal, which has no spec
+        write_platform(dir.path(), "metal").unwrap();
+        assert_eq!(
+            bls(),
+            "title Fedora CoreOS\noptions root=UUID=x ignition.platform.id=metal\n"
+        );
+    }
+
+    #[test]
+    fn test_rearm_firstboot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ignition.firstboot");
+
+        rearm_firstboot(dir.path()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        // existing first-boot kargs are kept
+        let contents = "set ignition_network_kcmdline=\"ip=dhcp\"\n";
+        fs::write(&path, contents).unwrap();
+        rearm_firstboot(dir.path()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), contents);
+    }
+
     #[test]
     fn test_platform_id() {
         let orig_content = "ignition.platform.id=metal foo bar";
         let new_content = bls_entry_options_write_platform(
             orig_content,
+            "metal",
             "openstack",
-            &vec!["baz".to_string(), "blah".to_string()],
+            &["baz".to_string(), "blah".to_string()],
             &[],
         )
         .unwrap();
@@ -796,9 +1293,10 @@ mod tests {
         let orig_content = "foo ignition.platform.id=metal bar";
         let new_content = bls_entry_options_write_platform(
             orig_content,
+            "metal",
             "openstack",
-            &vec!["baz".to_string(), "blah".to_string()],
-            &vec!["foo".to_string()],
+            &["baz".to_string(), "blah".to_string()],
+            &["foo".to_string()],
         )
         .unwrap();
         assert_eq!(
@@ -809,8 +1307,9 @@ mod tests {
         let orig_content = "foo bar ignition.platform.id=metal";
         let new_content = bls_entry_options_write_platform(
             orig_content,
+            "metal",
             "openstack",
-            &vec!["baz".to_string(), "blah".to_string()],
+            &["baz".to_string(), "blah".to_string()],
             &[],
         )
         .unwrap();
@@ -821,11 +1320,29 @@ mod tests {
 
         let orig_content = "foo bar ignition.platform.id=metal";
         let new_content =
-            bls_entry_options_write_platform(orig_content, "openstack", &[], &[]).unwrap();
+            bls_entry_options_write_platform(orig_content, "metal", "openstack", &[], &[]).unwrap();
         assert_eq!(
             new_content.unwrap(),
             "foo bar ignition.platform.id=openstack"
         );
+
+        // reconfiguring from a non-default platform
+        let orig_content = "foo ignition.platform.id=qemu console=ttyS0 bar";
+        let new_content = bls_entry_options_write_platform(
+            orig_content,
+            "qemu",
+            "vmware",
+            &["baz".to_string()],
+            &["console=ttyS0".to_string()],
+        )
+        .unwrap();
+        assert_eq!(
+            new_content.unwrap(),
+            "foo ignition.platform.id=vmware bar baz"
+        );
+
+        // platform ID not found
+        bls_entry_options_write_platform("foo bar", "metal", "openstack", &[], &[]).unwrap_err();
     }
 
     #[test]
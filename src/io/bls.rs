@@ -17,7 +17,7 @@
 use anyhow::{bail, Context, Result};
 use lazy_static::lazy_static;
 use regex::Regex;
-use std::fs::{read_dir, OpenOptions};
+use std::fs::{read_dir, read_to_string, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 
@@ -32,16 +32,58 @@ pub fn visit_bls_entry(
     mountpoint: &Path,
     mut f: impl FnMut(&str) -> Result<Option<String>>,
 ) -> Result<bool> {
+    let path = latest_bls_entry(mountpoint)?;
+
+    // slurp in the file
+    let mut config = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("opening bootloader config {}", path.display()))?;
+    let orig_contents = {
+        let mut s = String::new();
+        config
+            .read_to_string(&mut s)
+            .with_context(|| format!("reading {}", path.display()))?;
+        s
+    };
+
+    let r = f(&orig_contents).with_context(|| format!("visiting {}", path.display()))?;
+
+    if let Some(new_contents) = r {
+        // write out the modified data
+        config
+            .seek(SeekFrom::Start(0))
+            .with_context(|| format!("seeking {}", path.display()))?;
+        config
+            .set_len(0)
+            .with_context(|| format!("truncating {}", path.display()))?;
+        config
+            .write(new_contents.as_bytes())
+            .with_context(|| format!("writing {}", path.display()))?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+/// Returns the contents of the latest (default) BLS entry, without
+/// requiring write access.  Errors out if no BLS entry was found.
+pub fn read_bls_entry(mountpoint: &Path) -> Result<String> {
+    let path = latest_bls_entry(mountpoint)?;
+    read_to_string(&path).with_context(|| format!("reading {}", path.display()))
+}
+
+/// Returns the path of the latest (default) BLS entry.  This confusingly is
+/// the *last* BLS config in the directory because they are sorted by
+/// reverse order: https://github.com/ostreedev/ostree/pull/1654
+fn latest_bls_entry(mountpoint: &Path) -> Result<PathBuf> {
     // walk /boot/loader/entries/*.conf
     let mut config_path = mountpoint.to_path_buf();
     config_path.push("loader/entries");
 
-    // We only want to affect the latest BLS entry (i.e. the default one). This confusingly is the
-    // *last* BLS config in the directory because they are sorted by reverse order:
-    // https://github.com/ostreedev/ostree/pull/1654
-    //
-    // Because `read_dir` doesn't guarantee any ordering, we gather all the filenames up front and
-    // sort them before picking the last one.
+    // Because `read_dir` doesn't guarantee any ordering, we gather all the
+    // filenames up front and sort them before picking the last one.
     let mut entries: Vec<PathBuf> = Vec::new();
     for entry in read_dir(&config_path)
         .with_context(|| format!("reading directory {}", config_path.display()))?
@@ -55,43 +97,9 @@ pub fn visit_bls_entry(
         entries.push(path);
     }
     entries.sort();
-
-    let mut changed = false;
-    if let Some(path) = entries.pop() {
-        // slurp in the file
-        let mut config = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(&path)
-            .with_context(|| format!("opening bootloader config {}", path.display()))?;
-        let orig_contents = {
-            let mut s = String::new();
-            config
-                .read_to_string(&mut s)
-                .with_context(|| format!("reading {}", path.display()))?;
-            s
-        };
-
-        let r = f(&orig_contents).with_context(|| format!("visiting {}", path.display()))?;
-
-        if let Some(new_contents) = r {
-            // write out the modified data
-            config
-                .seek(SeekFrom::Start(0))
-                .with_context(|| format!("seeking {}", path.display()))?;
-            config
-                .set_len(0)
-                .with_context(|| format!("truncating {}", path.display()))?;
-            config
-                .write(new_contents.as_bytes())
-                .with_context(|| format!("writing {}", path.display()))?;
-            changed = true;
-        }
-    } else {
-        bail!("Found no BLS entries in {}", config_path.display());
-    }
-
-    Ok(changed)
+    entries
+        .pop()
+        .with_context(|| format!("Found no BLS entries in {}", config_path.display()))
 }
 
 /// Wrapper around `visit_bls_entry` to specifically visit just the BLS entry's `options` line and
@@ -303,7 +311,7 @@ mod tests {
             .append(&append_kargs)
             .replace(&replace_kargs)
             .delete(&delete_kargs)
-            .apply_to(&orig_kargs)
+            .apply_to(orig_kargs)
             .unwrap();
         assert_eq!(
             new_kargs,
@@ -17,6 +17,7 @@ use flate2::read::GzEncoder;
 use flate2::Compression;
 use ignition_config as ign_multi;
 use ignition_config::v3_3 as ign;
+use serde_json::Value;
 use std::io::Read;
 
 #[derive(Debug, Default)]
@@ -113,6 +114,84 @@ impl Ignition {
     }
 }
 
+/// Describe the changes made by merging the `child` Ignition config into
+/// `parent`, one line per change.  Keys present only in the parent are
+/// retained by the merge and aren't reported.
+pub fn diff_ignition_merge(parent: &Value, child: &Value) -> Vec<String> {
+    let mut changes = Vec::new();
+    diff_value("", "", Some(parent), child, &mut changes);
+    changes
+}
+
+/// The fields identifying entries in a list of objects, in order of
+/// preference, for each list Ignition merges by key.  Entries in the
+/// child replace fields of the parent entry with the same key.  Other
+/// lists, such as file append lists, are concatenated.
+fn merge_keys(list: &str) -> &'static [&'static str] {
+    match list {
+        "files" | "directories" | "links" => &["path"],
+        "units" | "dropins" | "users" | "groups" | "raid" | "luks" => &["name"],
+        "disks" | "filesystems" => &["device"],
+        "merge" | "certificateAuthorities" => &["source"],
+        "partitions" => &["number", "label"],
+        _ => &[],
+    }
+}
+
+/// Return the merge key of a list entry, formatted for display.
+fn merge_key(keys: &[&str], entry: &Value) -> Option<String> {
+    keys.iter().find_map(|&key| match entry.get(key)? {
+        Value::Null => None,
+        Value::String(value) => Some(format!("{}={}", key, value)),
+        value => Some(format!("{}={}", key, value)),
+    })
+}
+
+fn diff_value(
+    path: &str,
+    name: &str,
+    parent: Option<&Value>,
+    child: &Value,
+    changes: &mut Vec<String>,
+) {
+    match (parent, child) {
+        (Some(Value::Object(parent)), Value::Object(child)) => {
+            for (key, value) in child {
+                diff_value(
+                    &format!("{}/{}", path, key),
+                    key,
+                    parent.get(key),
+                    value,
+                    changes,
+                );
+            }
+        }
+        (Some(Value::Array(parent)), Value::Array(child)) => {
+            let keys = merge_keys(name);
+            for value in child {
+                match merge_key(keys, value) {
+                    Some(key) => diff_value(
+                        &format!("{}[{}]", path, key),
+                        "",
+                        parent
+                            .iter()
+                            .find(|p| merge_key(keys, p).as_ref() == Some(&key)),
+                        value,
+                        changes,
+                    ),
+                    None if !parent.contains(value) => {
+                        changes.push(format!("+ {}[]: {}", path, value))
+                    }
+                    None => {}
+                }
+            }
+        }
+        (Some(parent), child) if parent == child => {}
+        (Some(parent), child) => changes.push(format!("~ {}: {} -> {}", path, parent, child)),
+        (None, child) => changes.push(format!("+ {}: {}", path, child)),
+    }
+}
+
 fn make_resource(data: &[u8]) -> Result<ign::Resource> {
     let mut compressed = Vec::new();
     GzEncoder::new(data, Compression::best()).read_to_end(&mut compressed)?;
@@ -133,4 +212,59 @@ mod test {
         ignition.add_file("/a/b".into(), &[], 0o755).unwrap();
         ignition.add_file("/a/b".into(), &[], 0o755).unwrap_err();
     }
+
+    #[test]
+    fn merge_diff() {
+        let parent = serde_json::json!({
+            "ignition": {"version": "3.0.0"},
+            "passwd": {"users": [{"name": "core"}]},
+            "storage": {"files": [{"path": "/a"}]}
+        });
+        let child = serde_json::json!({
+            "ignition": {"version": "3.3.0"},
+            "passwd": {"users": [{"name": "core"}, {"name": "admin"}]},
+            "systemd": {"units": [{"name": "a.service"}]}
+        });
+        assert_eq!(
+            diff_ignition_merge(&parent, &child),
+            vec![
+                r#"~ /ignition/version: "3.0.0" -> "3.3.0""#,
+                r#"+ /passwd/users[name=admin]: {"name":"admin"}"#,
+                r#"+ /systemd: {"units":[{"name":"a.service"}]}"#,
+            ]
+        );
+        assert!(diff_ignition_merge(&parent, &parent).is_empty());
+
+        // keyed entries are merged field by field
+        let parent = serde_json::json!({
+            "passwd": {"users": [{"name": "core", "sshAuthorizedKeys": ["key1"]}]},
+            "storage": {
+                "files": [
+                    {"path": "/a", "mode": 420, "contents": {"source": "data:,a"}},
+                    {"path": "/b", "append": [{"source": "data:,b"}]}
+                ]
+            },
+            "systemd": {"units": [{"name": "a.service", "enabled": true}]}
+        });
+        let child = serde_json::json!({
+            "passwd": {"users": [{"name": "core", "sshAuthorizedKeys": ["key1", "key2"]}]},
+            "storage": {
+                "files": [
+                    {"path": "/a", "contents": {"source": "data:,A"}},
+                    {"path": "/b", "append": [{"source": "data:,b"}, {"source": "data:,c"}]},
+                    {"path": "/c", "mode": 384}
+                ]
+            },
+            "systemd": {"units": [{"name": "a.service", "enabled": true}]}
+        });
+        assert_eq!(
+            diff_ignition_merge(&parent, &child),
+            vec![
+                r#"+ /passwd/users[name=core]/sshAuthorizedKeys[]: "key2""#,
+                r#"~ /storage/files[path=/a]/contents/source: "data:,a" -> "data:,A""#,
+                r#"+ /storage/files[path=/b]/append[]: {"source":"data:,c"}"#,
+                r#"+ /storage/files[path=/c]: {"mode":384,"path":"/c"}"#,
+            ]
+        );
+    }
 }
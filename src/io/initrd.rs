@@ -28,7 +28,15 @@ lazy_static! {
 
 #[derive(Default, Debug)]
 pub struct Initrd {
-    members: BTreeMap<String, Vec<u8>>,
+    members: BTreeMap<String, Member>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Member {
+    contents: Vec<u8>,
+    /// Full st_mode, including the file type bits
+    mode: u32,
+    mtime: u32,
 }
 
 impl Initrd {
@@ -43,7 +51,7 @@ impl Initrd {
         // current directory, and notionally "mkdir" and "chdir" our way
         // around the filesystem.
         let mut cwd: Vec<&str> = Vec::new();
-        for (path, contents) in &self.members {
+        for (path, member) in &self.members {
             // chdir to common ancestor of cwd and file
             let mut parent: Vec<&str> = path.split('/').collect();
             parent.pop();
@@ -57,16 +65,19 @@ impl Initrd {
             // chdir into them
             for component in parent.iter().skip(cwd.len()) {
                 cwd.push(component);
-                // S_IFDIR | 0755
+                // S_IFDIR | 0755, timestamped like the member that
+                // caused it to be created
                 members.push((
-                    NewcBuilder::new(&cwd.join("/")).mode(0o40_755),
+                    NewcBuilder::new(&cwd.join("/"))
+                        .mode(0o40_755)
+                        .mtime(member.mtime),
                     Cursor::new(&[][..]),
                 ));
             }
-            // create file, S_IFREG | 0600
+            // create file
             members.push((
-                NewcBuilder::new(path).mode(0o100_600),
-                Cursor::new(contents),
+                NewcBuilder::new(path).mode(member.mode).mtime(member.mtime),
+                Cursor::new(&member.contents[..]),
             ));
         }
         // kernel requires CRC32: https://www.kernel.org/doc/Documentation/xz.txt
@@ -84,7 +95,10 @@ impl Initrd {
     }
 
     /// Read an initrd containing compressed and/or uncompressed archives,
-    /// ignoring paths not matching the specified glob patterns.
+    /// ignoring paths not matching the specified glob patterns.  Files
+    /// are read back as if added with `add()`: members such as Ignition
+    /// configs may contain secrets, so they're always written out with
+    /// mode 0600 regardless of the metadata in the source archive.
     pub fn from_reader_filtered<R: Read>(source: R, filter: &GlobMatcher) -> Result<Self> {
         let mut source = BufReader::with_capacity(BUFFER_SIZE, source);
         let mut result = Self::default();
@@ -110,7 +124,7 @@ impl Initrd {
                     reader
                         .read_to_end(&mut buf)
                         .context("reading CPIO entry contents")?;
-                    result.members.insert(name, buf);
+                    result.add(&name, buf);
                 }
                 decompressor = reader.finish().context("finishing reading CPIO entry")?;
             }
@@ -153,19 +167,33 @@ impl Initrd {
     }
 
     pub fn get(&self, path: &str) -> Option<&[u8]> {
-        self.members.get(path).map(|v| v.as_slice())
+        self.members.get(path).map(|m| m.contents.as_slice())
     }
 
     pub fn find(&self, filter: &GlobMatcher) -> BTreeMap<&str, &[u8]> {
         self.members
             .iter()
             .filter(|(p, _)| filter.matches(p))
-            .map(|(p, c)| (p.as_str(), c.as_slice()))
+            .map(|(p, m)| (p.as_str(), m.contents.as_slice()))
             .collect()
     }
 
+    /// Add a regular file with mode 0600 and a zero timestamp.
     pub fn add(&mut self, path: &str, contents: Vec<u8>) {
-        self.members.insert(path.into(), contents);
+        self.add_with_metadata(path, contents, 0o100_600, 0);
+    }
+
+    /// Add a member with the specified st_mode (including file type bits)
+    /// and modification time.  For symlinks, contents is the link target.
+    pub fn add_with_metadata(&mut self, path: &str, contents: Vec<u8>, mode: u32, mtime: u32) {
+        self.members.insert(
+            path.into(),
+            Member {
+                contents,
+                mode,
+                mtime,
+            },
+        );
     }
 
     pub fn remove(&mut self, path: &str) {
@@ -186,12 +214,12 @@ impl GlobMatcher {
         Ok(Self {
             patterns: globs
                 .iter()
-                .map(|p| glob::Pattern::new(*p).map_err(|e| anyhow!(e)))
+                .map(|p| glob::Pattern::new(p).map_err(|e| anyhow!(e)))
                 .collect::<Result<_>>()?,
         })
     }
 
-    fn matches(&self, path: &str) -> bool {
+    pub fn matches(&self, path: &str) -> bool {
         self.patterns.iter().any(|p| p.matches(path))
     }
 }
@@ -202,6 +230,14 @@ mod tests {
     use maplit::btreemap;
     use xz2::read::XzDecoder;
 
+    fn contents(initrd: &Initrd) -> BTreeMap<String, Vec<u8>> {
+        initrd
+            .members
+            .iter()
+            .map(|(p, m)| (p.clone(), m.contents.clone()))
+            .collect()
+    }
+
     #[test]
     fn roundtrip() {
         let input = r#"{}"#;
@@ -224,7 +260,7 @@ mod tests {
             .unwrap();
         let initrd = Initrd::from_reader(&*archive).unwrap();
         assert_eq!(
-            initrd.members,
+            contents(&initrd),
             btreemap! {
                 "uncompressed-1/hello".into() => b"HELLO\n".to_vec(),
                 "uncompressed-1/world".into() => b"WORLD\n".to_vec(),
@@ -335,6 +371,56 @@ mod tests {
         );
     }
 
+    #[test]
+    fn metadata() {
+        let mut initrd = Initrd::default();
+        initrd.add("a/default", b"default".to_vec());
+        initrd.add_with_metadata("a/script", b"#!/bin/sh\n".to_vec(), 0o100_755, 1234);
+        let archive = initrd.to_bytes().unwrap();
+
+        // metadata is written to the archive
+        let mut cpio = Vec::new();
+        XzDecoder::new(&*archive).read_to_end(&mut cpio).unwrap();
+        let mut source = &*cpio;
+        let mut entries = Vec::new();
+        loop {
+            let reader = NewcReader::new(source).unwrap();
+            let entry = reader.entry();
+            if entry.is_trailer() {
+                break;
+            }
+            entries.push((entry.name().to_string(), entry.mode(), entry.mtime()));
+            source = reader.finish().unwrap();
+        }
+        assert_eq!(
+            entries,
+            vec![
+                ("a".into(), 0o40_755, 0),
+                ("a/default".into(), 0o100_600, 0),
+                ("a/script".into(), 0o100_755, 1234),
+            ]
+        );
+
+        // but not read back, so rewriting an archive can't loosen the
+        // permissions of its members
+        let initrd = Initrd::from_reader(&*archive).unwrap();
+        assert_eq!(
+            initrd.members,
+            btreemap! {
+                "a/default".into() => Member {
+                    contents: b"default".to_vec(),
+                    mode: 0o100_600,
+                    mtime: 0,
+                },
+                "a/script".into() => Member {
+                    contents: b"#!/bin/sh\n".to_vec(),
+                    mode: 0o100_600,
+                    mtime: 0,
+                },
+            }
+        );
+    }
+
     /// Check that we correctly decode an archive generated by dracut-cpio
     /// with padded filenames.
     // https://github.com/dracutdevs/dracut/commit/a9c67046
@@ -345,7 +431,7 @@ mod tests {
             .read_to_end(&mut archive)
             .unwrap();
         assert_eq!(
-            Initrd::from_reader(&*archive).unwrap().members,
+            contents(&Initrd::from_reader(&*archive).unwrap()),
             btreemap! {
                 "dir/hello".into() => std::iter::repeat(b'z').take(5000).collect(),
                 "dir/world".into() => std::iter::repeat(b'q').take(4500).collect(),
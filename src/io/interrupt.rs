@@ -0,0 +1,69 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, Read};
+use std::sync::atomic::AtomicI32;
+
+use crate::util::{Interrupted, INTERRUPTED};
+
+/// Reader which fails once we've caught SIGINT or SIGTERM, so a copy loop
+/// stops between reads rather than in the middle of a write.
+pub struct InterruptReader<R: Read> {
+    source: R,
+    flag: &'static AtomicI32,
+}
+
+impl<R: Read> InterruptReader<R> {
+    pub fn new(source: R) -> Self {
+        Self::with_flag(source, &INTERRUPTED)
+    }
+
+    fn with_flag(source: R, flag: &'static AtomicI32) -> Self {
+        Self { source, flag }
+    }
+}
+
+impl<R: Read> Read for InterruptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // not ErrorKind::Interrupted, which callers would retry
+        if let Some(interrupted) = Interrupted::from_flag(self.flag) {
+            return Err(io::Error::new(io::ErrorKind::Other, interrupted));
+        }
+        self.source.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::signal::Signal;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_interrupt_reader() {
+        static FLAG: AtomicI32 = AtomicI32::new(0);
+        let mut reader = InterruptReader::with_flag(&b"hello world"[..], &FLAG);
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        FLAG.store(libc::SIGINT, Ordering::SeqCst);
+        let err = reader.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            err.get_ref().unwrap().downcast_ref::<Interrupted>(),
+            Some(&Interrupted(Signal::SIGINT))
+        );
+    }
+}
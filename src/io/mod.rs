@@ -21,6 +21,7 @@ mod compress;
 mod hash;
 mod ignition;
 mod initrd;
+mod interrupt;
 mod limit;
 mod tee;
 mod verify;
@@ -31,6 +32,7 @@ pub use self::compress::*;
 pub use self::hash::*;
 pub use self::ignition::*;
 pub use self::initrd::*;
+pub use self::interrupt::*;
 pub use self::limit::*;
 pub use self::tee::*;
 pub use self::verify::*;
@@ -13,11 +13,14 @@
 // limitations under the License.
 
 //! Minimal ISO 9660 parser. This is not a comprehensive parser; we only
-//! parse out the fields we care about. Extensions such as Rock Ridge
-//! and Joliet are not supported.
+//! parse out the fields we care about. Rock Ridge names, modes, and
+//! symlinks and Joliet names are supported, and are preferred over the
+//! ISO 9660 names when present.
 //!
 //! The official specification is not free. The primary reference used
-//! for this module is https://wiki.osdev.org/ISO_9660.
+//! for this module is https://wiki.osdev.org/ISO_9660. Rock Ridge is
+//! specified by IEEE P1281 (SUSP) and P1282 (RRIP), and Joliet by the
+//! Microsoft Joliet Specification.
 
 // An initial version of this module used the zerocopy crate to try to deserialize directly from
 // the on-disk ISO file in with zero copying. It works, but it's non-trivial and the performance
@@ -28,6 +31,7 @@
 
 use std::fs;
 use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
 use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, bail, Context, Result};
@@ -39,17 +43,74 @@ use crate::io::*;
 // technically the standard supports others, but this is the only one we support
 const ISO9660_SECTOR_SIZE: usize = 2048;
 
+/// Upper bound on the number of SUSP continuation areas we'll follow for
+/// a single directory record, to avoid looping on corrupt images.
+const SUSP_MAX_CONTINUATIONS: usize = 32;
+
 #[derive(Debug, Serialize)]
 pub struct IsoFs {
     descriptors: Vec<VolumeDescriptor>,
+    /// Extension providing file names, if any
+    extension: Option<Extension>,
     #[serde(skip_serializing)]
     file: fs::File,
 }
 
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Extension {
+    RockRidge,
+    Joliet,
+}
+
+/// How to decode the directory records of a directory tree.
+#[derive(Debug, Clone, Copy, Default)]
+struct RecordFormat {
+    /// Names are UCS-2 (Joliet)
+    joliet: bool,
+    /// If the tree has Rock Ridge entries, the number of bytes to skip at
+    /// the start of each System Use area
+    rock_ridge_skip: Option<usize>,
+}
+
 impl IsoFs {
     pub fn from_file(mut file: fs::File) -> Result<Self> {
-        let descriptors = get_volume_descriptors(&mut file)?;
-        Ok(Self { descriptors, file })
+        let mut descriptors = get_volume_descriptors(&mut file)?;
+
+        // prefer Rock Ridge, which also gives us modes and symlinks, then
+        // Joliet
+        let mut extension = None;
+        for d in &mut descriptors {
+            if let VolumeDescriptor::Primary(p) = d {
+                p.root.format.rock_ridge_skip =
+                    get_susp_skip(&file, &p.root).context("checking for Rock Ridge")?;
+                if p.root.format.rock_ridge_skip.is_some() {
+                    extension = Some(Extension::RockRidge);
+                }
+            }
+        }
+        if extension.is_none()
+            && descriptors
+                .iter()
+                .any(|d| matches!(d, VolumeDescriptor::Joliet(_)))
+        {
+            extension = Some(Extension::Joliet);
+        }
+
+        Ok(Self {
+            descriptors,
+            extension,
+            file,
+        })
+    }
+
+    /// Returns the extension used for file names, if any.
+    pub fn extension(&self) -> Option<Extension> {
+        self.extension
+    }
+
+    pub fn volume_id(&self) -> Result<&str> {
+        Ok(&self.get_primary_volume_descriptor()?.volume_id)
     }
 
     pub fn as_file(&mut self) -> Result<&mut fs::File> {
@@ -59,14 +120,22 @@ impl IsoFs {
         Ok(&mut self.file)
     }
 
+    /// Returns the root of the preferred directory tree.
     pub fn get_root_directory(&self) -> Result<Directory> {
+        if self.extension == Some(Extension::Joliet) {
+            for d in &self.descriptors {
+                if let VolumeDescriptor::Joliet(j) = d {
+                    return Ok(j.root.clone());
+                }
+            }
+        }
         let primary = self
             .get_primary_volume_descriptor()
             .context("getting root directory")?;
         Ok(primary.root.clone())
     }
 
-    pub fn walk(&mut self) -> Result<IsoFsWalkIterator> {
+    pub fn walk(&mut self) -> Result<IsoFsWalkIterator<'_>> {
         let root_dir = self.get_root_directory()?;
         let buf = self.list_dir(&root_dir)?;
         Ok(IsoFsWalkIterator {
@@ -82,9 +151,28 @@ impl IsoFs {
         IsoFsIterator::new(&mut self.file, dir)
     }
 
-    /// Returns the record for a specific path.
+    /// Returns the record for a specific path.  Each path component is
+    /// matched against the Rock Ridge or Joliet name if available,
+    /// falling back to a case-insensitive match against the ISO 9660
+    /// name.
     pub fn get_path(&mut self, path: &str) -> Result<DirectoryRecord> {
-        let mut dir = self.get_root_directory()?;
+        let root = self.get_root_directory()?;
+        match self.get_path_from(root, path) {
+            // Joliet names live in a separate directory tree
+            Err(e) if e.is::<NotFound>() && self.extension == Some(Extension::Joliet) => {
+                let root = self
+                    .get_primary_volume_descriptor()
+                    .context("getting root directory")?
+                    .root
+                    .clone();
+                self.get_path_from(root, path)
+            }
+            r => r,
+        }
+    }
+
+    fn get_path_from(&mut self, root: Directory, path: &str) -> Result<DirectoryRecord> {
+        let mut dir = root;
         let mut components = path_components(path);
         let filename = match components.pop() {
             Some(f) => f,
@@ -115,18 +203,23 @@ impl IsoFs {
 
     /// Returns the record for a specific name in a directory if it exists.
     fn get_dir_record(&mut self, dir: &Directory, name: &str) -> Result<Option<DirectoryRecord>> {
+        let mut fallback = None;
         for record in self
             .list_dir(dir)
             .with_context(|| format!("listing directory {}", dir.name))?
         {
             let record = record?;
-            match &record {
-                DirectoryRecord::Directory(d) if d.name == name => return Ok(Some(record)),
-                DirectoryRecord::File(f) if f.name == name => return Ok(Some(record)),
-                _ => continue,
+            if record.name() == name {
+                return Ok(Some(record));
+            }
+            if fallback.is_none()
+                && !dir.format.joliet
+                && record.iso_name().eq_ignore_ascii_case(name)
+            {
+                fallback = Some(record);
             }
         }
-        Ok(None)
+        Ok(fallback)
     }
 
     /// Returns a reader for a file record.
@@ -152,6 +245,64 @@ impl IsoFs {
         ))
     }
 
+    /// Returns the El Torito boot catalog, if any.
+    pub fn get_boot_catalog(&self) -> Result<Option<BootCatalog>> {
+        let address = match self.descriptors.iter().find_map(|d| match d {
+            VolumeDescriptor::Boot(b) => b.catalog,
+            _ => None,
+        }) {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+        // the catalog can span multiple sectors, so read them as needed
+        let mut offset = address.as_offset();
+        let mut buf = Vec::new();
+        let mut pos = 0;
+        let entries = std::iter::from_fn(|| {
+            if pos == buf.len() {
+                buf = vec![0; ISO9660_SECTOR_SIZE];
+                pos = 0;
+                if let Err(e) = self.file.read_exact_at(&mut buf, offset) {
+                    return Some(Err(e).context("reading boot catalog"));
+                }
+                offset += ISO9660_SECTOR_SIZE as u64;
+            }
+            pos += BootCatalog::ENTRY_SIZE;
+            Some(Ok(buf[pos - BootCatalog::ENTRY_SIZE..pos].to_vec()))
+        });
+        Ok(Some(
+            BootCatalog::parse(entries).context("parsing boot catalog")?,
+        ))
+    }
+
+    /// Returns the file containing the image loaded by a boot catalog
+    /// entry.  The sector count in the entry is often truncated or
+    /// unset for EFI images, so we look for a file starting at the
+    /// entry's address, and only fall back to the sector count if there
+    /// isn't one.
+    pub fn get_boot_image(&mut self, entry: &BootEntry) -> Result<File> {
+        for record in self.walk()? {
+            if let (_, DirectoryRecord::File(f)) = record? {
+                if f.address == entry.address && f.length > 0 {
+                    return Ok(f);
+                }
+            }
+        }
+        if entry.sector_count == 0 {
+            bail!(
+                "couldn't find boot image at sector {}",
+                entry.address.as_sector()
+            );
+        }
+        Ok(File {
+            name: format!("boot image at sector {}", entry.address.as_sector()),
+            iso_name: String::new(),
+            address: entry.address,
+            length: entry.sector_count as u32 * 512,
+            mode: None,
+        })
+    }
+
     fn get_primary_volume_descriptor(&self) -> Result<&PrimaryVolumeDescriptor> {
         for d in &self.descriptors {
             if let VolumeDescriptor::Primary(p) = d {
@@ -167,6 +318,7 @@ impl IsoFs {
 enum VolumeDescriptor {
     Boot(BootVolumeDescriptor),
     Primary(PrimaryVolumeDescriptor),
+    Joliet(JolietVolumeDescriptor),
     Supplementary,
     Unknown { type_id: u8 },
 }
@@ -175,6 +327,9 @@ enum VolumeDescriptor {
 struct BootVolumeDescriptor {
     boot_system_id: String,
     boot_id: String,
+    /// Location of the El Torito boot catalog
+    #[serde(skip_serializing_if = "Option::is_none")]
+    catalog: Option<Address>,
 }
 
 #[derive(Debug, Serialize)]
@@ -184,11 +339,19 @@ struct PrimaryVolumeDescriptor {
     root: Directory,
 }
 
+/// Supplementary volume descriptor with Joliet escape sequences.
+#[derive(Debug, Serialize)]
+struct JolietVolumeDescriptor {
+    volume_id: String,
+    root: Directory,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum DirectoryRecord {
     Directory(Directory),
     File(File),
+    Symlink(Symlink),
 }
 
 impl DirectoryRecord {
@@ -196,6 +359,7 @@ impl DirectoryRecord {
         match self {
             Self::Directory(d) => Ok(d),
             Self::File(f) => Err(anyhow!("entry {} is a file", f.name)),
+            Self::Symlink(s) => Err(anyhow!("entry {} is a symlink", s.name)),
         }
     }
 
@@ -203,6 +367,24 @@ impl DirectoryRecord {
         match self {
             Self::Directory(f) => Err(anyhow!("entry {} is a directory", f.name)),
             Self::File(f) => Ok(f),
+            Self::Symlink(s) => Err(anyhow!("entry {} is a symlink", s.name)),
+        }
+    }
+
+    /// Returns the preferred name of the record.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Directory(d) => &d.name,
+            Self::File(f) => &f.name,
+            Self::Symlink(s) => &s.name,
+        }
+    }
+
+    fn iso_name(&self) -> &str {
+        match self {
+            Self::Directory(d) => &d.iso_name,
+            Self::File(f) => &f.iso_name,
+            Self::Symlink(s) => &s.iso_name,
         }
     }
 }
@@ -210,15 +392,39 @@ impl DirectoryRecord {
 #[derive(Debug, Serialize, Clone)]
 pub struct Directory {
     pub name: String,
+    #[serde(skip_serializing)]
+    iso_name: String,
     pub address: Address,
     pub length: u32,
+    /// POSIX mode from Rock Ridge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    #[serde(skip_serializing)]
+    format: RecordFormat,
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct File {
     pub name: String,
+    #[serde(skip_serializing)]
+    iso_name: String,
     pub address: Address,
     pub length: u32,
+    /// POSIX mode from Rock Ridge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+/// A Rock Ridge symbolic link.
+#[derive(Debug, Serialize, Clone)]
+pub struct Symlink {
+    pub name: String,
+    #[serde(skip_serializing)]
+    iso_name: String,
+    pub target: String,
+    /// POSIX mode from Rock Ridge
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
@@ -270,9 +476,12 @@ fn get_next_volume_descriptor(f: &mut fs::File) -> Result<Option<VolumeDescripto
     Ok(match buf.get_u8() {
         TYPE_BOOT => Some(VolumeDescriptor::Boot(BootVolumeDescriptor::parse(buf)?)),
         TYPE_PRIMARY => Some(VolumeDescriptor::Primary(PrimaryVolumeDescriptor::parse(
-            buf,
+            buf, f,
         )?)),
-        TYPE_SUPPLEMENTARY => Some(VolumeDescriptor::Supplementary),
+        TYPE_SUPPLEMENTARY => match JolietVolumeDescriptor::parse(buf, f)? {
+            Some(d) => Some(VolumeDescriptor::Joliet(d)),
+            None => Some(VolumeDescriptor::Supplementary),
+        },
         TYPE_TERMINATOR => None,
         t => Some(VolumeDescriptor::Unknown { type_id: t }),
     })
@@ -281,24 +490,193 @@ fn get_next_volume_descriptor(f: &mut fs::File) -> Result<Option<VolumeDescripto
 impl BootVolumeDescriptor {
     /// Parses boot descriptor at cursor after type field.
     fn parse(buf: &mut Bytes) -> Result<Self> {
+        const EL_TORITO_ID: &str = "EL TORITO SPECIFICATION";
+
         verify_descriptor_header(buf).context("parsing boot descriptor")?;
+        let boot_system_id =
+            parse_iso9660_string(buf, 32, IsoString::StrA).context("parsing boot system ID")?;
+        let boot_id = parse_iso9660_string(buf, 32, IsoString::StrA).context("parsing boot ID")?;
+        let catalog = if boot_system_id == EL_TORITO_ID {
+            Some(Address(buf.get_u32_le()))
+        } else {
+            None
+        };
+        Ok(Self {
+            boot_system_id,
+            boot_id,
+            catalog,
+        })
+    }
+}
+
+/// El Torito boot catalog.
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct BootCatalog {
+    pub entries: Vec<BootEntry>,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq)]
+pub struct BootEntry {
+    pub platform: BootPlatform,
+    pub bootable: bool,
+    pub emulation: BootEmulation,
+    pub load_segment: u16,
+    /// Number of 512-byte virtual sectors loaded by the firmware
+    pub sector_count: u16,
+    /// Location of the boot image
+    pub address: Address,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BootPlatform {
+    X86,
+    PowerPc,
+    Mac,
+    Efi,
+    Unknown(u8),
+}
+
+impl From<u8> for BootPlatform {
+    fn from(id: u8) -> Self {
+        match id {
+            0 => Self::X86,
+            1 => Self::PowerPc,
+            2 => Self::Mac,
+            0xef => Self::Efi,
+            id => Self::Unknown(id),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootEmulation {
+    None,
+    #[serde(rename = "floppy-1.2m")]
+    Floppy1_2M,
+    #[serde(rename = "floppy-1.44m")]
+    Floppy1_44M,
+    #[serde(rename = "floppy-2.88m")]
+    Floppy2_88M,
+    HardDisk,
+    Unknown(u8),
+}
+
+impl From<u8> for BootEmulation {
+    fn from(media: u8) -> Self {
+        match media {
+            0 => Self::None,
+            1 => Self::Floppy1_2M,
+            2 => Self::Floppy1_44M,
+            3 => Self::Floppy2_88M,
+            4 => Self::HardDisk,
+            media => Self::Unknown(media),
+        }
+    }
+}
+
+impl BootCatalog {
+    const ENTRY_SIZE: usize = 32;
+
+    /// Parses the boot catalog from its successive 32-byte entries,
+    /// stopping after the final section.
+    fn parse(mut entries: impl Iterator<Item = Result<Vec<u8>>>) -> Result<Self> {
+        const HEADER_VALIDATION: u8 = 0x01;
+        const HEADER_SECTION: u8 = 0x90;
+        const HEADER_FINAL_SECTION: u8 = 0x91;
+        const EXTENSION: u8 = 0x44;
+
+        let mut next = |what: &str| -> Result<Vec<u8>> {
+            entries
+                .next()
+                .with_context(|| format!("missing {}", what))?
+        };
+
+        // validation entry
+        let validation = next("validation entry")?;
+        if validation[0] != HEADER_VALIDATION || validation[30..32] != [0x55, 0xaa] {
+            bail!("invalid validation entry");
+        }
+        let checksum = validation.chunks_exact(2).fold(0u16, |sum, w| {
+            sum.wrapping_add(u16::from_le_bytes([w[0], w[1]]))
+        });
+        if checksum != 0 {
+            bail!("invalid validation entry checksum");
+        }
+
+        // initial/default entry
+        let mut result = Vec::new();
+        let initial = next("initial entry")?;
+        result.push(BootEntry::parse(validation[1].into(), &initial).context("initial entry")?);
+
+        // sections
+        loop {
+            let header = next("final section header")?;
+            if header[0] == EXTENSION {
+                // extension of the last entry of the previous section
+                continue;
+            }
+            if header[0] != HEADER_SECTION && header[0] != HEADER_FINAL_SECTION {
+                break;
+            }
+            let platform = header[1].into();
+            let count = u16::from_le_bytes([header[2], header[3]]);
+            for i in 0..count {
+                let what = format!("section entry {}", i);
+                let mut entry = next(&what)?;
+                // skip extension entries of the previous section entry
+                while entry[0] == EXTENSION {
+                    entry = next(&what)?;
+                }
+                result.push(BootEntry::parse(platform, &entry).context(what)?);
+            }
+            if header[0] == HEADER_FINAL_SECTION {
+                break;
+            }
+        }
+
+        Ok(Self { entries: result })
+    }
+}
+
+impl BootEntry {
+    fn parse(platform: BootPlatform, buf: &[u8]) -> Result<Self> {
+        const BOOTABLE: u8 = 0x88;
+        const NOT_BOOTABLE: u8 = 0x00;
+
+        let bootable = match buf[0] {
+            BOOTABLE => true,
+            NOT_BOOTABLE => false,
+            v => bail!("invalid boot indicator {:#x}", v),
+        };
         Ok(Self {
-            boot_system_id: parse_iso9660_string(buf, 32, IsoString::StrA)
-                .context("parsing boot system ID")?,
-            boot_id: parse_iso9660_string(buf, 32, IsoString::StrA).context("parsing boot ID")?,
+            platform,
+            bootable,
+            // high bits are flags in section entries
+            emulation: (buf[1] & 0x0f).into(),
+            load_segment: u16::from_le_bytes([buf[2], buf[3]]),
+            sector_count: u16::from_le_bytes([buf[6], buf[7]]),
+            address: Address(u32::from_le_bytes(buf[8..12].try_into().unwrap())),
         })
     }
 }
 
 impl PrimaryVolumeDescriptor {
     /// Parses primary descriptor at cursor after type field.
-    fn parse(buf: &mut Bytes) -> Result<Self> {
+    fn parse(buf: &mut Bytes, iso: &fs::File) -> Result<Self> {
         verify_descriptor_header(buf).context("parsing primary descriptor")?;
         let system_id =
             parse_iso9660_string(eat(buf, 1), 32, IsoString::StrA).context("parsing system id")?;
         let volume_id = // technically should be StrD, but non-compliance is common
             parse_iso9660_string(buf, 32, IsoString::StrA).context("parsing volume id")?;
-        let root = match get_next_directory_record(eat(buf, 156 - 72), 34, true)? {
+        let root = match get_next_directory_record(
+            eat(buf, 156 - 72),
+            34,
+            true,
+            RecordFormat::default(),
+            iso,
+        )? {
             Some(DirectoryRecord::Directory(d)) => d,
             _ => bail!("failed to parse root directory record from primary descriptor"),
         };
@@ -310,6 +688,52 @@ impl PrimaryVolumeDescriptor {
     }
 }
 
+impl JolietVolumeDescriptor {
+    /// Parses supplementary descriptor at cursor after type field.
+    /// Returns None if the descriptor isn't a Joliet descriptor.
+    fn parse(buf: &mut Bytes, iso: &fs::File) -> Result<Option<Self>> {
+        // UCS-2 levels 1, 2, and 3
+        const JOLIET_ESCAPES: [&[u8]; 3] = [b"%/@", b"%/C", b"%/E"];
+
+        verify_descriptor_header(buf).context("parsing supplementary descriptor")?;
+        let volume_id =
+            parse_joliet_string(eat(buf, 33), 32, false).context("parsing volume id")?;
+        let escapes = eat(buf, 88 - 72).copy_to_bytes(32);
+        if !JOLIET_ESCAPES.iter().any(|e| escapes.starts_with(e)) {
+            return Ok(None);
+        }
+        let format = RecordFormat {
+            joliet: true,
+            rock_ridge_skip: None,
+        };
+        let root = match get_next_directory_record(eat(buf, 156 - 120), 34, true, format, iso)? {
+            Some(DirectoryRecord::Directory(d)) => d,
+            _ => bail!("failed to parse root directory record from Joliet descriptor"),
+        };
+        Ok(Some(Self { volume_id, root }))
+    }
+}
+
+/// Checks the first record of the root directory for a SUSP "SP" entry,
+/// and returns the SUSP skip length if present.
+fn get_susp_skip(iso: &fs::File, root: &Directory) -> Result<Option<usize>> {
+    // the "." record: 33 bytes of header, a one-byte name, and the SP
+    // entry at the start of the System Use area
+    let mut buf = [0; 41];
+    iso.read_exact_at(&mut buf, root.address.as_offset())
+        .context("reading root directory record")?;
+    if buf[0] as usize >= buf.len()
+        && buf[32] == 1
+        && &buf[34..36] == b"SP"
+        && buf[36] == 7
+        && buf[38..40] == [0xbe, 0xef]
+    {
+        Ok(Some(buf[40] as usize))
+    } else {
+        Ok(None)
+    }
+}
+
 /// Verifies descriptor header at cursor.
 fn verify_descriptor_header(buf: &mut Bytes) -> Result<()> {
     const VOLUME_DESCRIPTOR_ID: &[u8] = b"CD001";
@@ -329,8 +753,11 @@ fn verify_descriptor_header(buf: &mut Bytes) -> Result<()> {
 }
 
 pub struct IsoFsIterator {
+    // for reading SUSP continuation areas
+    iso: fs::File,
     dir: Bytes,
     length: u32,
+    format: RecordFormat,
 }
 
 impl IsoFsIterator {
@@ -343,8 +770,10 @@ impl IsoFsIterator {
             .with_context(|| format!("reading directory {}", dir.name))?;
 
         Ok(Self {
+            iso: iso.try_clone().context("cloning file")?,
             dir: Bytes::from(buf),
             length: dir.length,
+            format: dir.format,
         })
     }
 }
@@ -352,7 +781,7 @@ impl IsoFsIterator {
 impl Iterator for IsoFsIterator {
     type Item = Result<DirectoryRecord>;
     fn next(&mut self) -> Option<Self::Item> {
-        get_next_directory_record(&mut self.dir, self.length, false)
+        get_next_directory_record(&mut self.dir, self.length, false, self.format, &self.iso)
             .context("reading next record")
             .transpose()
     }
@@ -388,7 +817,7 @@ impl<'a> IsoFsWalkIterator<'a> {
                             self.current_dir = Some(IsoFsIterator::new(self.iso, d)?);
                             path.push(&d.name);
                         }
-                        DirectoryRecord::File(f) => path.push(&f.name),
+                        _ => path.push(r.name()),
                     };
                     // paths are all UTF-8
                     return Ok(Some((path.into_os_string().into_string().unwrap(), r)));
@@ -409,6 +838,8 @@ fn get_next_directory_record(
     buf: &mut Bytes,
     length: u32,
     is_root: bool,
+    format: RecordFormat,
+    iso: &fs::File,
 ) -> Result<Option<DirectoryRecord>> {
     loop {
         if !buf.has_remaining() {
@@ -438,6 +869,9 @@ fn get_next_directory_record(
         let length = eat(buf, 4).get_u32_le();
         let flags = eat(buf, 25 - 14).get_u8();
         let name_length = eat(buf, 32 - 26).get_u8() as usize;
+        if len < 33 + name_length {
+            bail!("directory record too short for name; corrupt ISO?");
+        }
         let name = if name_length == 1 && (buf[0] == 0 || buf[0] == 1) {
             let c = buf.get_u8();
             if is_root && c == 0 {
@@ -448,6 +882,8 @@ fn get_next_directory_record(
                 // "." or ".."
                 None
             }
+        } else if format.joliet {
+            Some(parse_joliet_string(buf, name_length, true).context("parsing record name")?)
         } else {
             Some(
                 parse_iso9660_string(buf, name_length, IsoString::File)
@@ -455,25 +891,201 @@ fn get_next_directory_record(
             )
         };
 
-        // advance to next record
-        eat(buf, len - (33 + name_length));
+        // take the System Use area and advance to next record
+        let mut system_use = buf.split_to(len - (33 + name_length));
 
-        if let Some(name) = name {
-            if flags & 2 > 0 {
-                return Ok(Some(DirectoryRecord::Directory(Directory {
-                    name,
-                    address,
-                    length,
-                })));
-            } else {
-                return Ok(Some(DirectoryRecord::File(File {
-                    name,
-                    address,
-                    length,
-                })));
+        let iso_name = match name {
+            Some(name) => name,
+            None => continue,
+        };
+        let rr = match format.rock_ridge_skip {
+            Some(skip) => {
+                // the System Use area starts at an even offset
+                let pad = 1 - name_length % 2;
+                if system_use.remaining() >= pad + skip {
+                    system_use.advance(pad + skip);
+                    parse_rock_ridge(system_use, iso)
+                        .with_context(|| format!("parsing Rock Ridge entries for {}", iso_name))?
+                } else {
+                    RockRidge::default()
+                }
+            }
+            None => RockRidge::default(),
+        };
+        if rr.relocated {
+            // reachable via the CL entry in its original parent
+            continue;
+        }
+        let name = rr.name.unwrap_or_else(|| iso_name.clone());
+
+        if let Some(target) = rr.symlink {
+            return Ok(Some(DirectoryRecord::Symlink(Symlink {
+                name,
+                iso_name,
+                target,
+                mode: rr.mode,
+            })));
+        } else if let Some(address) = rr.child_link {
+            let length = get_directory_length(iso, address)
+                .with_context(|| format!("reading relocated directory {}", name))?;
+            return Ok(Some(DirectoryRecord::Directory(Directory {
+                name,
+                iso_name,
+                address,
+                length,
+                mode: rr.mode,
+                format,
+            })));
+        } else if flags & 2 > 0 {
+            return Ok(Some(DirectoryRecord::Directory(Directory {
+                name,
+                iso_name,
+                address,
+                length,
+                mode: rr.mode,
+                format,
+            })));
+        } else {
+            return Ok(Some(DirectoryRecord::File(File {
+                name,
+                iso_name,
+                address,
+                length,
+                mode: rr.mode,
+            })));
+        }
+    }
+}
+
+/// Reads the length of a directory from its "." record.
+fn get_directory_length(iso: &fs::File, address: Address) -> Result<u32> {
+    let mut buf = [0; 14];
+    iso.read_exact_at(&mut buf, address.as_offset())
+        .context("reading directory record")?;
+    Ok(u32::from_le_bytes(buf[10..14].try_into().unwrap()))
+}
+
+/// Rock Ridge fields we care about.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct RockRidge {
+    name: Option<String>,
+    mode: Option<u32>,
+    symlink: Option<String>,
+    /// Location of a relocated directory (CL)
+    child_link: Option<Address>,
+    /// This is a relocated directory (RE)
+    relocated: bool,
+}
+
+/// Parses the SUSP entries in a System Use area, following continuation
+/// areas.
+fn parse_rock_ridge(mut area: Bytes, iso: &fs::File) -> Result<RockRidge> {
+    let mut rr = RockRidge::default();
+    let mut name: Option<Vec<u8>> = None;
+    let mut symlink: Option<Vec<String>> = None;
+    // last symlink component continues in the next component record
+    let mut symlink_continue = false;
+    let mut continuation: Option<(Address, u32, u32)> = None;
+    let mut continuations = 0;
+    loop {
+        if area.remaining() < 4 || area[0] == 0 {
+            // end of this area; follow the continuation, if any
+            match continuation.take() {
+                Some((address, offset, length)) => {
+                    continuations += 1;
+                    if continuations > SUSP_MAX_CONTINUATIONS {
+                        bail!("too many SUSP continuation areas");
+                    }
+                    let mut buf = vec![0; length as usize];
+                    iso.read_exact_at(&mut buf, address.as_offset() + offset as u64)
+                        .context("reading SUSP continuation area")?;
+                    area = Bytes::from(buf);
+                    continue;
+                }
+                None => break,
+            }
+        }
+        let signature = [area[0], area[1]];
+        let len = area[2] as usize;
+        if len < 4 || len > area.remaining() {
+            bail!("invalid length {} for SUSP entry {:?}", len, signature);
+        }
+        let mut entry = area.split_to(len);
+        entry.advance(4);
+        let check_len = |entry: &Bytes, min: usize| {
+            if entry.remaining() < min {
+                bail!("truncated SUSP entry {:?}", signature);
+            }
+            Ok(())
+        };
+        match &signature {
+            b"NM" => {
+                check_len(&entry, 1)?;
+                let flags = entry.get_u8();
+                // ignore "." and ".." names
+                if flags & 0x6 == 0 {
+                    name.get_or_insert_with(Vec::new).extend(entry);
+                }
+            }
+            b"PX" => {
+                check_len(&entry, 8)?;
+                rr.mode = Some(entry.get_u32_le());
+            }
+            b"SL" => {
+                check_len(&entry, 1)?;
+                let components = symlink.get_or_insert_with(Vec::new);
+                entry.advance(1);
+                while entry.remaining() >= 2 {
+                    let flags = entry.get_u8();
+                    let len = entry.get_u8() as usize;
+                    check_len(&entry, len)?;
+                    let content = entry.copy_to_bytes(len);
+                    let component = if flags & 0x2 != 0 {
+                        ".".into()
+                    } else if flags & 0x4 != 0 {
+                        "..".into()
+                    } else if flags & 0x8 != 0 {
+                        // root
+                        String::new()
+                    } else {
+                        String::from_utf8(content.to_vec())
+                            .context("symlink target is not UTF-8")?
+                    };
+                    match components.last_mut() {
+                        Some(last) if symlink_continue => last.push_str(&component),
+                        _ => components.push(component),
+                    }
+                    symlink_continue = flags & 0x1 != 0;
+                }
+            }
+            b"CE" => {
+                check_len(&entry, 24)?;
+                let address = Address(entry.get_u32_le());
+                let offset = eat(&mut entry, 4).get_u32_le();
+                let length = eat(&mut entry, 4).get_u32_le();
+                continuation = Some((address, offset, length));
+            }
+            b"CL" => {
+                check_len(&entry, 8)?;
+                rr.child_link = Some(Address(entry.get_u32_le()));
             }
+            b"RE" => rr.relocated = true,
+            // terminator
+            b"ST" => break,
+            _ => (),
         }
     }
+
+    if let Some(name) = name {
+        rr.name = Some(String::from_utf8(name).context("name is not UTF-8")?);
+    }
+    if let Some(components) = symlink {
+        rr.symlink = Some(match components.as_slice() {
+            [root] if root.is_empty() => "/".into(),
+            _ => components.join("/"),
+        });
+    }
+    Ok(rr)
 }
 
 #[allow(unused)]
@@ -525,6 +1137,29 @@ fn parse_iso9660_string(buf: &mut Bytes, len: usize, kind: IsoString) -> Result<
     Ok(s)
 }
 
+/// Reads a Joliet (UCS-2 big-endian) string.
+fn parse_joliet_string(buf: &mut Bytes, len: usize, is_file: bool) -> Result<String> {
+    if len > buf.remaining() {
+        bail!("incomplete string name; corrupt ISO?");
+    }
+    let bytes = buf.copy_to_bytes(len);
+    let mut s = char::decode_utf16(
+        bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]])),
+    )
+    .collect::<Result<String, _>>()
+    .with_context(|| format!("invalid UCS-2 string {:?}", bytes))?;
+    if is_file {
+        if s.ends_with(";1") {
+            s.truncate(s.len() - 2);
+        }
+    } else {
+        s.truncate(s.trim_end_matches(|c| c == ' ' || c == '\0').len());
+    }
+    Ok(s)
+}
+
 fn eat(buf: &mut Bytes, n: usize) -> &mut Bytes {
     buf.advance(n);
     buf
@@ -561,10 +1196,20 @@ mod tests {
 
     fn open_iso() -> IsoFs {
         let iso_bytes: &[u8] = include_bytes!("../fixtures/iso/synthetic.iso.xz");
+        IsoFs::from_file(decompress(iso_bytes)).unwrap()
+    }
+
+    // has Rock Ridge and Joliet
+    fn open_live_iso_file() -> fs::File {
+        let iso_bytes: &[u8] = include_bytes!("../fixtures/iso/embed-areas-2022-02.iso.xz");
+        decompress(iso_bytes)
+    }
+
+    fn decompress(iso_bytes: &[u8]) -> fs::File {
         let mut decoder = XzDecoder::new(iso_bytes);
         let mut iso_file = tempfile().unwrap();
         copy(&mut decoder, &mut iso_file).unwrap();
-        IsoFs::from_file(iso_file).unwrap()
+        iso_file
     }
 
     #[test]
@@ -624,6 +1269,7 @@ mod tests {
             .map(|e| match e {
                 Ok(DirectoryRecord::Directory(d)) => d.name,
                 Ok(DirectoryRecord::File(f)) => f.name,
+                Ok(DirectoryRecord::Symlink(s)) => s.name,
                 Err(e) => panic!("{}", e),
             })
             .collect::<Vec<String>>();
@@ -694,6 +1340,247 @@ mod tests {
         assert_eq!(names, expected);
     }
 
+    #[test]
+    fn test_no_extensions() {
+        let mut iso = open_iso();
+        assert_eq!(iso.extension(), None);
+        // case-insensitive match against ISO 9660 names
+        let file = iso
+            .get_path("content/dir/subfile.txt")
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        assert_eq!(file.name, "SUBFILE.TXT");
+        assert_eq!(file.mode, None);
+    }
+
+    #[test]
+    fn test_rock_ridge() {
+        let mut iso = IsoFs::from_file(open_live_iso_file()).unwrap();
+        assert_eq!(iso.extension(), Some(Extension::RockRidge));
+        let file = iso
+            .get_path("coreos/features.json")
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        assert_eq!(file.name, "features.json");
+        assert_eq!(file.mode.unwrap() & 0o170_000, 0o100_000);
+        // fall back to ISO 9660 names
+        let legacy = iso
+            .get_path("COREOS/FEATURES.JSO")
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        assert_eq!(legacy.name, "features.json");
+        assert_eq!(legacy.address, file.address);
+        let dir = iso.get_path("images").unwrap().try_into_dir().unwrap();
+        assert_eq!(dir.mode.unwrap() & 0o170_000, 0o040_000);
+
+        let names = iso
+            .walk()
+            .unwrap()
+            .map(|v| v.unwrap().0)
+            .collect::<Vec<String>>();
+        assert!(names.contains(&"images/pxeboot/rootfs.img".to_string()));
+        assert!(names.contains(&"EFI/fedora/grub.cfg".to_string()));
+    }
+
+    #[test]
+    fn test_joliet() {
+        let mut iso_file = open_live_iso_file();
+        // clobber the SUSP "SP" entry in the root directory to disable
+        // Rock Ridge
+        iso_file.seek(SeekFrom::Start(29 * 2048 + 34)).unwrap();
+        iso_file.write_all(b"XX").unwrap();
+        let mut iso = IsoFs::from_file(iso_file).unwrap();
+        assert_eq!(iso.extension(), Some(Extension::Joliet));
+        let file = iso
+            .get_path("coreos/features.json")
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        assert_eq!(file.name, "features.json");
+        assert_eq!(file.mode, None);
+        // fall back to the ISO 9660 directory tree
+        let legacy = iso
+            .get_path("COREOS/FEATURES.JSO")
+            .unwrap()
+            .try_into_file()
+            .unwrap();
+        assert_eq!(legacy.name, "FEATURES.JSO");
+        assert_eq!(legacy.address, file.address);
+        assert!(iso.get_path("coreos/MISSING").unwrap_err().is::<NotFound>());
+
+        let names = iso
+            .walk()
+            .unwrap()
+            .map(|v| v.unwrap().0)
+            .collect::<Vec<String>>();
+        assert!(names.contains(&"images/pxeboot/rootfs.img".to_string()));
+    }
+
+    #[test]
+    fn test_boot_catalog() {
+        let mut iso = IsoFs::from_file(open_live_iso_file()).unwrap();
+        let catalog = iso.get_boot_catalog().unwrap().unwrap();
+        assert_eq!(
+            catalog.entries,
+            vec![
+                BootEntry {
+                    platform: BootPlatform::X86,
+                    bootable: true,
+                    emulation: BootEmulation::None,
+                    load_segment: 0,
+                    sector_count: 4,
+                    address: Address(45),
+                },
+                BootEntry {
+                    platform: BootPlatform::Efi,
+                    bootable: true,
+                    emulation: BootEmulation::None,
+                    load_segment: 0,
+                    sector_count: 4,
+                    address: Address(64),
+                },
+            ]
+        );
+        let file = iso.get_boot_image(&catalog.entries[1]).unwrap();
+        assert_eq!(file.name, "efiboot.img");
+        assert_eq!(file.length, 12);
+
+        // no boot catalog
+        assert!(open_iso().get_boot_catalog().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_boot_catalog() {
+        let mut buf = vec![0; 2048];
+        // validation entry, with checksum
+        buf[0] = 1;
+        buf[30] = 0x55;
+        buf[31] = 0xaa;
+        buf[28..30].copy_from_slice(&0xaa56u16.wrapping_neg().to_le_bytes());
+        // initial entry: 1.44M floppy
+        buf[32] = 0x88;
+        buf[33] = 2;
+        buf[34..36].copy_from_slice(&0x7c0u16.to_le_bytes());
+        buf[38..40].copy_from_slice(&1u16.to_le_bytes());
+        buf[40..44].copy_from_slice(&20u32.to_le_bytes());
+        // section header: EFI, 1 entry
+        buf[64] = 0x90;
+        buf[65] = 0xef;
+        buf[66] = 1;
+        // non-bootable hard disk entry with flags, then an extension
+        buf[97] = 0x24;
+        buf[104..108].copy_from_slice(&30u32.to_le_bytes());
+        buf[128] = 0x44;
+        // final section header: EFI, 1 entry
+        buf[160] = 0x91;
+        buf[161] = 0xef;
+        buf[162] = 1;
+        // bootable entry
+        buf[192] = 0x88;
+        buf[200..204].copy_from_slice(&40u32.to_le_bytes());
+        // trailing garbage after final section
+        buf[224] = 0x88;
+
+        let parse = |buf: &[u8]| {
+            BootCatalog::parse(
+                buf.chunks_exact(BootCatalog::ENTRY_SIZE)
+                    .map(|e| Ok(e.to_vec())),
+            )
+        };
+        let catalog = parse(&buf).unwrap();
+        assert_eq!(
+            catalog
+                .entries
+                .iter()
+                .map(|e| (e.platform, e.bootable, e.emulation, e.address.as_sector()))
+                .collect::<Vec<_>>(),
+            vec![
+                (BootPlatform::X86, true, BootEmulation::Floppy1_44M, 20),
+                (BootPlatform::Efi, false, BootEmulation::HardDisk, 30),
+                (BootPlatform::Efi, true, BootEmulation::None, 40),
+            ]
+        );
+        assert_eq!(catalog.entries[0].load_segment, 0x7c0);
+
+        // final section extending past the first sector
+        let mut long = buf[..160].to_vec();
+        long.extend([0x91, 0xef, 70, 0]);
+        long.resize(192, 0);
+        for i in 0..70u32 {
+            let mut entry = [0; 32];
+            entry[0] = 0x88;
+            entry[8..12].copy_from_slice(&(100 + i).to_le_bytes());
+            long.extend(entry);
+        }
+        let catalog = parse(&long).unwrap();
+        assert_eq!(catalog.entries.len(), 72);
+        assert_eq!(catalog.entries[71].address.as_sector(), 169);
+
+        // truncated catalog
+        parse(&long[..2048]).unwrap_err();
+
+        // bad checksum
+        buf[29] ^= 1;
+        parse(&buf).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_rock_ridge() {
+        let iso = tempfile().unwrap();
+        let mut area = Vec::new();
+        // name split across two NM entries
+        area.extend(b"NM\x08\x01\x01lon");
+        area.extend(b"NM\x0b\x01\x00g.name");
+        // PX with mode 0o120777
+        area.extend(b"PX\x2c\x01");
+        area.extend(0o120_777u32.to_le_bytes());
+        area.extend(0o120_777u32.to_be_bytes());
+        area.extend([0; 32]);
+        // SL for /usr/li + b/../x.
+        area.extend(b"SL\x10\x01\x00");
+        area.extend(b"\x08\x00\x00\x03usr\x01\x02li");
+        area.extend(b"SL\x0e\x01\x00");
+        area.extend(b"\x00\x01b\x04\x00\x00\x02x.");
+        // unknown entry
+        area.extend(b"ZZ\x05\x01\x00");
+        // terminator, then garbage
+        area.extend(b"ST\x04\x01NM\x06\x01\x00z");
+
+        let rr = parse_rock_ridge(Bytes::from(area), &iso).unwrap();
+        assert_eq!(
+            rr,
+            RockRidge {
+                name: Some("long.name".into()),
+                mode: Some(0o120_777),
+                symlink: Some("/usr/lib/../x.".into()),
+                child_link: None,
+                relocated: false,
+            }
+        );
+
+        // root symlink
+        let rr = parse_rock_ridge(Bytes::from(&b"SL\x07\x01\x00\x08\x00"[..]), &iso).unwrap();
+        assert_eq!(rr.symlink.unwrap(), "/");
+
+        // bad lengths
+        parse_rock_ridge(Bytes::from(&b"NM\x10\x01\x00z"[..]), &iso).unwrap_err();
+        parse_rock_ridge(Bytes::from(&b"PX\x06\x01\x00\x00"[..]), &iso).unwrap_err();
+    }
+
+    #[test]
+    fn test_parse_joliet_string() {
+        let mut buf = Bytes::from(&b"\x00a\x00b\x00.\x00t\x00x\x00t\x00;\x001"[..]);
+        assert_eq!(parse_joliet_string(&mut buf, 16, true).unwrap(), "ab.txt");
+        let mut buf = Bytes::from(&b"\x00v\x00o\x00l\x00 \x00 "[..]);
+        assert_eq!(parse_joliet_string(&mut buf, 10, false).unwrap(), "vol");
+        // unpaired surrogate
+        let mut buf = Bytes::from(&b"\xd8\x00"[..]);
+        parse_joliet_string(&mut buf, 2, true).unwrap_err();
+    }
+
     #[test]
     fn test_path_components() {
         // basic
@@ -12,8 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+pub mod blkinfo;
 pub mod blockdev;
 pub mod cmdline;
+pub mod devices;
 pub mod download;
 pub mod install;
 pub mod io;
@@ -21,6 +23,7 @@ pub mod iso9660;
 pub mod live;
 pub mod miniso;
 pub mod osmet;
+pub mod preflight;
 #[cfg(target_arch = "s390x")]
 pub mod s390x;
 pub mod source;
@@ -0,0 +1,579 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Embedded ISO checksums, compatible with implantisomd5 and checkisomd5
+//! from isomd5sum.
+//!
+//! implantisomd5 stores an MD5 digest of the ISO image in the application
+//! use area of the primary volume descriptor, along with "fragment sums":
+//! truncated digests of successively longer prefixes of the image, which
+//! let checkisomd5 fail early.  The application use area itself is hashed
+//! as if it were filled with spaces, and the last few sectors of the image
+//! are skipped.
+
+use anyhow::{bail, Context, Result};
+use openssl::hash::{Hasher, MessageDigest};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const SECTOR_SIZE: u64 = 2048;
+const VOLUME_DESCRIPTOR_START: u64 = 16 * SECTOR_SIZE;
+const VOLUME_SPACE_SIZE_OFFSET: usize = 84; // big-endian copy
+const APPDATA_OFFSET: u64 = 883;
+const APPDATA_SIZE: usize = 512;
+const SKIP_SECTORS: u64 = 15;
+const FRAGMENT_COUNT: u64 = 20;
+const FRAGMENT_SUM_SIZE: u64 = 60;
+// isomd5sum only computes fragment sums between reads, so we need to read
+// in chunks of the same size
+const CHUNK_SIZE: u64 = 16 * SECTOR_SIZE;
+
+const KEY_MD5: &str = "ISO MD5SUM = ";
+const KEY_SKIP_SECTORS: &str = "SKIPSECTORS = ";
+const KEY_SUPPORTED: &str = "RHLISOSTATUS=";
+const KEY_FRAGMENT_SUMS: &str = "FRAGMENT SUMS = ";
+const KEY_FRAGMENT_COUNT: &str = "FRAGMENT COUNT = ";
+const TRAILER: &str = "THIS IS NOT THE SAME AS RUNNING MD5SUM ON THIS ISO!!";
+
+/// A byte range whose contents should be replaced before hashing.
+pub(super) type Substitution = (u64, Vec<u8>);
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum ChecksumStatus {
+    /// The image matches the implanted checksum.
+    Match,
+    /// The image matches the implanted checksum once some groups of
+    /// substitutions are applied.
+    MatchWithSubstitutions,
+    /// The image doesn't match.  If the fragment sums can localize the
+    /// problem, the 1-based index of the first bad fragment and the
+    /// fragment count.
+    Mismatch(Option<(u64, u64)>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct ImplantedChecksum {
+    md5: String,
+    skip_sectors: u64,
+    supported: bool,
+    fragment_sums: String,
+    fragment_count: u64,
+}
+
+impl ImplantedChecksum {
+    /// Parse the application use area.  Returns None if there's no
+    /// checksum.
+    fn parse(appdata: &[u8]) -> Result<Option<Self>> {
+        let appdata = String::from_utf8_lossy(appdata);
+        let md5 = match field(&appdata, KEY_MD5) {
+            Some(v) => v.to_string(),
+            None => return Ok(None),
+        };
+        if md5.len() != 32 || !md5.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("invalid implanted MD5 checksum: {}", md5);
+        }
+        let skip_sectors = match field(&appdata, KEY_SKIP_SECTORS) {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("invalid implanted skip sector count: {}", v))?,
+            None => 0,
+        };
+        let supported = field(&appdata, KEY_SUPPORTED) == Some("1");
+        let fragment_count = match field(&appdata, KEY_FRAGMENT_COUNT) {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("invalid implanted fragment count: {}", v))?,
+            None => 0,
+        };
+        let fragment_sums = match field(&appdata, KEY_FRAGMENT_SUMS) {
+            Some(v) => v.to_string(),
+            None if fragment_count == 0 => String::new(),
+            None => bail!("implanted checksum is missing fragment sums"),
+        };
+        Ok(Some(Self {
+            md5,
+            skip_sectors,
+            supported,
+            fragment_sums,
+            fragment_count,
+        }))
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut appdata = format!(
+            "{}{};{}{};{}{};{}{};{}{};{}",
+            KEY_MD5,
+            self.md5,
+            KEY_SKIP_SECTORS,
+            self.skip_sectors,
+            KEY_SUPPORTED,
+            if self.supported { 1 } else { 0 },
+            KEY_FRAGMENT_SUMS,
+            self.fragment_sums,
+            KEY_FRAGMENT_COUNT,
+            self.fragment_count,
+            TRAILER
+        )
+        .into_bytes();
+        if appdata.len() > APPDATA_SIZE {
+            bail!(
+                "implanted checksum too large: {} > {}",
+                appdata.len(),
+                APPDATA_SIZE
+            );
+        }
+        appdata.resize(APPDATA_SIZE, b' ');
+        Ok(appdata)
+    }
+
+    /// Return the 1-based index of the first fragment whose sum doesn't
+    /// match, if any.
+    fn first_bad_fragment(&self, sums: &Sums) -> Option<u64> {
+        let size = fragment_sum_size(self.fragment_count);
+        sums.fragments
+            .iter()
+            .filter(|(index, _)| *index <= self.fragment_count)
+            .find(|(index, sum)| {
+                let start = ((index - 1) * size) as usize;
+                self.fragment_sums.get(start..start + sum.len()) != Some(sum.as_str())
+            })
+            .map(|(index, _)| *index)
+    }
+}
+
+/// Return the value following `key`, up to the next semicolon.
+fn field<'a>(appdata: &'a str, key: &str) -> Option<&'a str> {
+    let start = appdata.find(key)? + key.len();
+    let value = &appdata[start..];
+    Some(value.split(';').next().unwrap_or(value).trim())
+}
+
+fn fragment_sum_size(fragment_count: u64) -> u64 {
+    // MD5 digest produces 16 bytes, each contributing one character
+    (FRAGMENT_SUM_SIZE / fragment_count).min(16)
+}
+
+/// Location of the primary volume descriptor and size of the image.
+struct Layout {
+    appdata_offset: u64,
+    iso_size: u64,
+}
+
+impl Layout {
+    fn for_file(file: &mut File) -> Result<Self> {
+        let mut sector = vec![0; SECTOR_SIZE as usize];
+        let mut offset = VOLUME_DESCRIPTOR_START;
+        loop {
+            file.seek(SeekFrom::Start(offset))
+                .with_context(|| format!("seeking to offset {}", offset))?;
+            file.read_exact(&mut sector)
+                .context("reading volume descriptor")?;
+            if &sector[1..6] != b"CD001" {
+                bail!("not an ISO9660 image");
+            }
+            match sector[0] {
+                1 => break,
+                255 => bail!("no primary volume descriptor found"),
+                _ => offset += SECTOR_SIZE,
+            }
+        }
+        let mut size = [0; 4];
+        size.copy_from_slice(&sector[VOLUME_SPACE_SIZE_OFFSET..VOLUME_SPACE_SIZE_OFFSET + 4]);
+        Ok(Self {
+            appdata_offset: offset + APPDATA_OFFSET,
+            iso_size: u32::from_be_bytes(size) as u64 * SECTOR_SIZE,
+        })
+    }
+
+    fn read_appdata(&self, file: &mut File) -> Result<Vec<u8>> {
+        let mut appdata = vec![0; APPDATA_SIZE];
+        file.seek(SeekFrom::Start(self.appdata_offset))
+            .with_context(|| format!("seeking to offset {}", self.appdata_offset))?;
+        file.read_exact(&mut appdata)
+            .context("reading application use area")?;
+        Ok(appdata)
+    }
+}
+
+/// Digest and fragment sums for one view of the image.
+struct Sums {
+    md5: String,
+    /// (1-based fragment index, fragment sum)
+    fragments: Vec<(u64, String)>,
+}
+
+struct Summer<'a> {
+    hasher: Hasher,
+    substitutions: &'a [Substitution],
+    fragments: Vec<(u64, String)>,
+}
+
+impl<'a> Summer<'a> {
+    fn new(substitutions: &'a [Substitution]) -> Result<Self> {
+        Ok(Self {
+            hasher: Hasher::new(MessageDigest::md5()).context("creating MD5 hasher")?,
+            substitutions,
+            fragments: Vec::new(),
+        })
+    }
+
+    fn update(&mut self, offset: u64, buf: &[u8], scratch: &mut Vec<u8>) -> Result<()> {
+        if self
+            .substitutions
+            .iter()
+            .any(|(start, contents)| overlaps(offset, buf.len(), *start, contents.len()))
+        {
+            scratch.clear();
+            scratch.extend_from_slice(buf);
+            for (start, contents) in self.substitutions {
+                overwrite(scratch, offset, *start, contents);
+            }
+            self.hasher.update(scratch)
+        } else {
+            self.hasher.update(buf)
+        }
+        .context("updating MD5 hash")
+    }
+
+    fn finish_fragment(&mut self, index: u64, size: u64) -> Result<()> {
+        let digest = self
+            .hasher
+            .clone()
+            .finish()
+            .context("finishing fragment hash")?;
+        // isomd5sum keeps only the first hex digit of each byte, without
+        // zero padding
+        let sum = digest
+            .iter()
+            .take(size as usize)
+            .map(|b| format!("{:x}", b).chars().next().unwrap())
+            .collect();
+        self.fragments.push((index, sum));
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Sums> {
+        Ok(Sums {
+            md5: hex::encode(self.hasher.finish().context("finishing MD5 hash")?),
+            fragments: self.fragments,
+        })
+    }
+}
+
+fn overlaps(offset: u64, len: usize, start: u64, length: usize) -> bool {
+    start < offset + len as u64 && offset < start + length as u64
+}
+
+/// Overwrite the part of `buf` (located at `offset` in the image) that
+/// overlaps `contents` (located at `start`).
+fn overwrite(buf: &mut [u8], offset: u64, start: u64, contents: &[u8]) {
+    if !overlaps(offset, buf.len(), start, contents.len()) {
+        return;
+    }
+    let buf_start = start.saturating_sub(offset) as usize;
+    let contents_start = offset.saturating_sub(start) as usize;
+    let len = (buf.len() - buf_start).min(contents.len() - contents_start);
+    buf[buf_start..buf_start + len]
+        .copy_from_slice(&contents[contents_start..contents_start + len]);
+}
+
+/// Hash the image once per entry in `views`, applying that entry's
+/// substitutions to the image contents.  The image is only read once.
+fn compute(
+    file: &mut File,
+    layout: &Layout,
+    skip_sectors: u64,
+    fragment_count: u64,
+    views: &[&[Substitution]],
+) -> Result<Vec<Sums>> {
+    let total = layout
+        .iso_size
+        .checked_sub(skip_sectors * SECTOR_SIZE)
+        .context("ISO image smaller than skipped sectors")?;
+    let fragment_size = total / (fragment_count + 1);
+    if fragment_count > 0 && fragment_size == 0 {
+        bail!("ISO image too small for {} fragments", fragment_count);
+    }
+    let fragment_sum_size = if fragment_count > 0 {
+        fragment_sum_size(fragment_count)
+    } else {
+        0
+    };
+
+    let appdata = [(layout.appdata_offset, vec![b' '; APPDATA_SIZE])];
+    let mut summers = views
+        .iter()
+        .map(|substitutions| Summer::new(substitutions))
+        .collect::<Result<Vec<_>>>()?;
+    let mut buf = vec![0; CHUNK_SIZE as usize];
+    let mut scratch = Vec::with_capacity(buf.len());
+    let mut previous_fragment = 0;
+    let mut offset = 0;
+    file.seek(SeekFrom::Start(0))
+        .context("seeking to start of ISO")?;
+    while offset < total {
+        let len = (total - offset).min(CHUNK_SIZE) as usize;
+        let buf = &mut buf[..len];
+        file.read_exact(buf)
+            .with_context(|| format!("reading {} bytes at {}", len, offset))?;
+        overwrite(buf, offset, appdata[0].0, &appdata[0].1);
+        for summer in &mut summers {
+            summer.update(offset, buf, &mut scratch)?;
+        }
+        if fragment_count > 0 {
+            let fragment = offset / fragment_size;
+            if fragment != previous_fragment {
+                for summer in &mut summers {
+                    summer.finish_fragment(fragment, fragment_sum_size)?;
+                }
+                previous_fragment = fragment;
+            }
+        }
+        offset += len as u64;
+    }
+    summers.into_iter().map(|s| s.finish()).collect()
+}
+
+/// Check the image against its implanted checksum.  If it doesn't match
+/// as-is, check it again with every combination of the substitution
+/// groups applied.  Returns None if the image has no implanted checksum.
+pub(super) fn verify_checksum(
+    file: &mut File,
+    groups: &[Vec<Substitution>],
+) -> Result<Option<ChecksumStatus>> {
+    let layout = Layout::for_file(file)?;
+    let implanted = match ImplantedChecksum::parse(&layout.read_appdata(file)?)? {
+        Some(c) => c,
+        None => return Ok(None),
+    };
+    if groups.len() > 4 {
+        bail!("too many substitution groups: {}", groups.len());
+    }
+    // the first view, with no substitutions, is the image as-is
+    let views: Vec<Vec<Substitution>> = (0..1usize << groups.len())
+        .map(|mask| {
+            groups
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .flat_map(|(_, group)| group.iter().cloned())
+                .collect()
+        })
+        .collect();
+    let views: Vec<&[Substitution]> = views.iter().map(|v| v.as_slice()).collect();
+    let sums = compute(
+        file,
+        &layout,
+        implanted.skip_sectors,
+        implanted.fragment_count,
+        &views,
+    )?;
+    match sums.iter().position(|s| s.md5 == implanted.md5) {
+        Some(0) => return Ok(Some(ChecksumStatus::Match)),
+        Some(_) => return Ok(Some(ChecksumStatus::MatchWithSubstitutions)),
+        None => (),
+    }
+    // Each view may fail early for legitimate reasons (e.g. a customized
+    // embed area), so report the latest failure.
+    let bad_fragment = sums
+        .iter()
+        .map(|s| implanted.first_bad_fragment(s))
+        .collect::<Option<Vec<_>>>()
+        .and_then(|v| v.into_iter().max())
+        .map(|f| (f, implanted.fragment_count));
+    Ok(Some(ChecksumStatus::Mismatch(bad_fragment)))
+}
+
+/// Compute a checksum for the image and implant it, replacing any existing
+/// checksum.
+pub(super) fn implant_checksum(file: &mut File) -> Result<()> {
+    let layout = Layout::for_file(file)?;
+    // preserve the support status set by the image builder
+    let supported = ImplantedChecksum::parse(&layout.read_appdata(file)?)
+        .ok()
+        .flatten()
+        .map(|c| c.supported)
+        .unwrap_or(false);
+    let sums = compute(file, &layout, SKIP_SECTORS, FRAGMENT_COUNT, &[&[]])?
+        .pop()
+        .unwrap();
+    let implanted = ImplantedChecksum {
+        md5: sums.md5,
+        skip_sectors: SKIP_SECTORS,
+        supported,
+        fragment_sums: sums.fragments.into_iter().map(|(_, s)| s).collect(),
+        fragment_count: FRAGMENT_COUNT,
+    };
+    file.seek(SeekFrom::Start(layout.appdata_offset))
+        .with_context(|| format!("seeking to offset {}", layout.appdata_offset))?;
+    file.write_all(&implanted.to_bytes()?)
+        .context("writing implanted checksum")?;
+    file.flush().context("flushing ISO")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::copy;
+
+    use tempfile::tempfile;
+    use xz2::read::XzDecoder;
+
+    use crate::live::embed::IsoConfig;
+
+    fn open_iso_file() -> File {
+        let iso_bytes: &[u8] = include_bytes!("../../fixtures/iso/embed-areas-2021-09.iso.xz");
+        let mut decoder = XzDecoder::new(iso_bytes);
+        let mut iso_file = tempfile().unwrap();
+        copy(&mut decoder, &mut iso_file).unwrap();
+        iso_file
+    }
+
+    fn corrupt(file: &mut File, offset: u64) {
+        let mut byte = [0];
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.read_exact(&mut byte).unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[!byte[0]]).unwrap();
+    }
+
+    #[test]
+    fn test_appdata_round_trip() {
+        let checksum = ImplantedChecksum {
+            md5: "0123456789abcdef0123456789abcdef".into(),
+            skip_sectors: 15,
+            supported: true,
+            fragment_sums: "a".repeat(60),
+            fragment_count: 20,
+        };
+        let appdata = checksum.to_bytes().unwrap();
+        assert_eq!(appdata.len(), APPDATA_SIZE);
+        assert!(appdata.starts_with(b"ISO MD5SUM = 0123456789abcdef0123456789abcdef;SKIPSECTORS = 15;RHLISOSTATUS=1;FRAGMENT SUMS = aaaa"));
+        assert_eq!(
+            ImplantedChecksum::parse(&appdata).unwrap().unwrap(),
+            checksum
+        );
+
+        // no checksum
+        assert!(ImplantedChecksum::parse(&[b' '; APPDATA_SIZE])
+            .unwrap()
+            .is_none());
+        // bad checksum
+        ImplantedChecksum::parse(b"ISO MD5SUM = 1234;").unwrap_err();
+        // no fragment sums
+        let parsed = ImplantedChecksum::parse(
+            b"ISO MD5SUM = 0123456789abcdef0123456789abcdef;SKIPSECTORS = 15;",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(parsed.fragment_count, 0);
+        assert_eq!(parsed.fragment_sums, "");
+        assert!(!parsed.supported);
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let mut buf = [0u8; 8];
+        overwrite(&mut buf, 100, 96, &[1; 6]);
+        assert_eq!(buf, [1, 1, 0, 0, 0, 0, 0, 0]);
+        overwrite(&mut buf, 100, 103, &[2; 2]);
+        assert_eq!(buf, [1, 1, 0, 2, 2, 0, 0, 0]);
+        overwrite(&mut buf, 100, 106, &[3; 6]);
+        assert_eq!(buf, [1, 1, 0, 2, 2, 0, 3, 3]);
+        overwrite(&mut buf, 100, 108, &[4; 6]);
+        assert_eq!(buf, [1, 1, 0, 2, 2, 0, 3, 3]);
+        overwrite(&mut buf, 100, 90, &[5; 20]);
+        assert_eq!(buf, [5; 8]);
+    }
+
+    #[test]
+    fn test_verify() {
+        let mut iso_file = open_iso_file();
+        let areas = IsoConfig::for_file(&mut iso_file)
+            .unwrap()
+            .pristine_areas()
+            .unwrap();
+
+        // no checksum
+        assert_eq!(verify_checksum(&mut iso_file, &areas).unwrap(), None);
+
+        // implanted
+        implant_checksum(&mut iso_file).unwrap();
+        assert_eq!(
+            verify_checksum(&mut iso_file, &areas).unwrap(),
+            Some(ChecksumStatus::Match)
+        );
+
+        // customized after implanting
+        let mut iso = IsoConfig::for_file(&mut iso_file).unwrap();
+        iso.initrd_mut().add("config.ign", b"{}".to_vec());
+        iso.set_kargs("console=ttyS0").unwrap();
+        iso.write(&mut iso_file).unwrap();
+        assert_eq!(
+            verify_checksum(&mut iso_file, &areas).unwrap(),
+            Some(ChecksumStatus::MatchWithSubstitutions)
+        );
+
+        // re-implanted
+        implant_checksum(&mut iso_file).unwrap();
+        assert_eq!(
+            verify_checksum(&mut iso_file, &areas).unwrap(),
+            Some(ChecksumStatus::Match)
+        );
+
+        // kargs customized after implanting a customized initrd
+        let mut iso = IsoConfig::for_file(&mut iso_file).unwrap();
+        #[allow(clippy::unnecessary_to_owned)]
+        iso.set_kargs(&iso.kargs_default().unwrap().to_string())
+            .unwrap();
+        iso.write(&mut iso_file).unwrap();
+        implant_checksum(&mut iso_file).unwrap();
+        iso.set_kargs("console=ttyS0").unwrap();
+        iso.write(&mut iso_file).unwrap();
+        assert_eq!(
+            verify_checksum(&mut iso_file, &areas).unwrap(),
+            Some(ChecksumStatus::MatchWithSubstitutions)
+        );
+        implant_checksum(&mut iso_file).unwrap();
+
+        // corrupted in the middle and at the end
+        let layout = Layout::for_file(&mut iso_file).unwrap();
+        let mut corrupt_file = iso_file.try_clone().unwrap();
+        corrupt(&mut corrupt_file, layout.iso_size / 2);
+        match verify_checksum(&mut iso_file, &areas).unwrap() {
+            Some(ChecksumStatus::Mismatch(Some((fragment, 20)))) => {
+                assert!((9..=11).contains(&fragment), "{}", fragment)
+            }
+            v => panic!("unexpected status {:?}", v),
+        }
+        corrupt(&mut corrupt_file, layout.iso_size / 2);
+        corrupt(&mut corrupt_file, layout.iso_size - 16 * SECTOR_SIZE);
+        match verify_checksum(&mut iso_file, &areas).unwrap() {
+            Some(ChecksumStatus::Mismatch(Some((fragment, 20)))) => {
+                assert!(fragment >= 19, "{}", fragment)
+            }
+            Some(ChecksumStatus::Mismatch(None)) => (),
+            v => panic!("unexpected status {:?}", v),
+        }
+        // skipped sectors aren't checked
+        corrupt(&mut corrupt_file, layout.iso_size - 16 * SECTOR_SIZE);
+        corrupt(&mut corrupt_file, layout.iso_size - SECTOR_SIZE);
+        assert_eq!(
+            verify_checksum(&mut iso_file, &areas).unwrap(),
+            Some(ChecksumStatus::Match)
+        );
+    }
+}
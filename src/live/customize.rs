@@ -15,7 +15,7 @@
 //! Infrastructure for high-level ISO/PXE customizations
 
 use anyhow::{bail, Context, Result};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fs::read;
 
 use crate::cmdline::*;
@@ -32,7 +32,7 @@ const COREOS_ISO_FEATURES_PATH: &str = "COREOS/FEATURES.JSO";
 /// CoreOS feature flags in /etc/coreos/features.json in the live initramfs
 /// and /coreos/features.json in the live ISO.  Written by
 /// cosa buildextend-live.
-#[derive(Default, Deserialize)]
+#[derive(Default, Deserialize, Serialize)]
 #[serde(rename_all = "kebab-case")]
 pub(super) struct OsFeatures {
     /// Installer reads config files from /etc/coreos/installer.d
@@ -53,6 +53,13 @@ impl OsFeatures {
             Err(e) => Err(e).context("looking up OS features"),
         }
     }
+
+    pub fn for_initrd(initrd: &Initrd) -> Result<Self> {
+        match initrd.get(INITRD_FEATURES_PATH) {
+            Some(json) => serde_json::from_slice(json).context("parsing OS features"),
+            None => Ok(Self::default()),
+        }
+    }
 }
 
 #[derive(Default)]
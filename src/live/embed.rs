@@ -25,6 +25,8 @@ use std::iter::repeat;
 use crate::io::*;
 use crate::iso9660::{self, IsoFs};
 
+use super::checksum::Substitution;
+
 pub(super) const INITRD_IGNITION_PATH: &str = "config.ign";
 pub(super) const INITRD_NETWORK_DIR: &str = "etc/coreos-firstboot-network";
 
@@ -110,6 +112,14 @@ impl IsoConfig {
         self.unwrap_kargs_mut()?.set_kargs(kargs)
     }
 
+    pub fn initrd_capacity(&self) -> usize {
+        self.initrd.region.length
+    }
+
+    pub fn kargs_capacity(&self) -> Option<usize> {
+        self.kargs.as_ref().map(|k| k.length)
+    }
+
     pub fn kargs_supported(&self) -> bool {
         self.kargs.is_some()
     }
@@ -142,6 +152,26 @@ impl IsoConfig {
         Ok(())
     }
 
+    /// Return the offset and unmodified contents of every embed area, as
+    /// they were when the ISO was built, grouped by kind of customization.
+    /// Used for checksumming an ISO whose embed areas may have been
+    /// customized since.
+    pub fn pristine_areas(&self) -> Result<Vec<Vec<Substitution>>> {
+        let initrd = &self.initrd.region;
+        let mut areas = vec![vec![(initrd.offset, vec![0; initrd.length])]];
+        if let Some(kargs) = &self.kargs {
+            let contents = kargs.format(&kargs.default)?;
+            areas.push(
+                kargs
+                    .regions
+                    .iter()
+                    .map(|r| (r.offset, contents.clone()))
+                    .collect(),
+            );
+        }
+        Ok(areas)
+    }
+
     pub fn stream(&self, input: &mut File, writer: &mut (impl Write + ?Sized)) -> Result<()> {
         let initrd_region = self.initrd.region()?;
         let mut regions = vec![&initrd_region];
@@ -350,7 +380,7 @@ impl KargEmbedAreas {
         let mut regions = Vec::new();
         for loc in info.files {
             let iso_file = iso
-                .get_path(&loc.path.to_uppercase())
+                .get_path(&loc.path)
                 .with_context(|| format!("looking up '{}'", loc.path))?
                 .try_into_file()?;
             // we rely on Region::read() to verify that the offset/length
@@ -467,8 +497,17 @@ impl KargEmbedAreas {
     }
 
     pub fn set_kargs(&mut self, kargs: &str) -> Result<()> {
-        let unformatted = kargs.trim();
-        let formatted = unformatted.to_string() + "\n";
+        let contents = self.format(kargs)?;
+        for region in &mut self.regions {
+            region.contents = contents.clone();
+            region.modified = true;
+        }
+        self.args = kargs.trim().to_string();
+        Ok(())
+    }
+
+    fn format(&self, kargs: &str) -> Result<Vec<u8>> {
+        let formatted = kargs.trim().to_string() + "\n";
         if formatted.len() > self.length {
             bail!(
                 "kargs too large for area: {} vs {}",
@@ -478,12 +517,7 @@ impl KargEmbedAreas {
         }
         let mut contents = vec![b'#'; self.length];
         contents[..formatted.len()].copy_from_slice(formatted.as_bytes());
-        for region in &mut self.regions {
-            region.contents = contents.clone();
-            region.modified = true;
-        }
-        self.args = unformatted.to_string();
-        Ok(())
+        Ok(contents)
     }
 
     pub fn write(&self, file: &mut File) -> Result<()> {
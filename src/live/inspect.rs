@@ -0,0 +1,430 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reporting of customizations in live ISO and PXE images
+
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use openssl::sha;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Read;
+
+use crate::io::*;
+use crate::iso9660::IsoFs;
+use crate::util::format_key_values;
+
+use super::customize::OsFeatures;
+use super::embed::{IsoConfig, INITRD_IGNITION_PATH, INITRD_NETWORK_DIR, INITRD_NETWORK_GLOB};
+
+pub(super) const INITRD_OS_RELEASE_PATH: &str = "usr/lib/os-release";
+
+const INSTALLER_CONFIG_DIR: &str = "/etc/coreos/installer.d/";
+const PRE_INSTALL_PREFIX: &str = "/usr/local/bin/pre-install-";
+const POST_INSTALL_PREFIX: &str = "/usr/local/bin/post-install-";
+
+#[derive(Serialize)]
+pub(super) struct Inspection {
+    /// ISO volume ID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    volume_id: Option<String>,
+    /// OS version from os-release in the initrd
+    #[serde(skip_serializing_if = "Option::is_none")]
+    os_version: Option<String>,
+    features: OsFeatures,
+    ignition: Option<IgnitionInspection>,
+    network_keyfiles: Vec<String>,
+    /// None if the image doesn't support kargs customization
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kargs: Option<KargsInspection>,
+    installer_config: Vec<InstallerConfigFile>,
+    pre_install: Vec<String>,
+    post_install: Vec<String>,
+    /// None for PXE images
+    #[serde(skip_serializing_if = "Option::is_none")]
+    embed_areas: Option<EmbedAreasInspection>,
+}
+
+#[derive(Serialize)]
+struct IgnitionInspection {
+    digest: String,
+    /// None if the config can't be parsed
+    version: Option<String>,
+}
+
+#[derive(Serialize)]
+struct KargsInspection {
+    current: String,
+    default: String,
+    appended: Vec<String>,
+    deleted: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct InstallerConfigFile {
+    path: String,
+    /// None if the contents can't be decoded
+    contents: Option<String>,
+}
+
+#[derive(Serialize)]
+struct EmbedAreasInspection {
+    initrd: EmbedAreaUsage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kargs: Option<EmbedAreaUsage>,
+}
+
+#[derive(Serialize)]
+struct EmbedAreaUsage {
+    capacity: usize,
+    used: usize,
+    free: usize,
+}
+
+impl EmbedAreaUsage {
+    fn new(capacity: usize, used: usize) -> Self {
+        Self {
+            capacity,
+            used,
+            free: capacity.saturating_sub(used),
+        }
+    }
+}
+
+impl Inspection {
+    pub fn for_iso(iso_fs: &mut IsoFs, iso: &IsoConfig) -> Result<Self> {
+        let mut inspection = Self::for_initrd(iso.initrd(), OsFeatures::for_iso(iso_fs)?)?;
+        inspection.volume_id = Some(iso_fs.volume_id()?.to_string());
+
+        let initrd_used = if iso.initrd().is_empty() {
+            0
+        } else {
+            iso.initrd().to_bytes()?.len()
+        };
+        let mut areas = EmbedAreasInspection {
+            initrd: EmbedAreaUsage::new(iso.initrd_capacity(), initrd_used),
+            kargs: None,
+        };
+        if let Some(capacity) = iso.kargs_capacity() {
+            let (current, default) = (iso.kargs()?, iso.kargs_default()?);
+            // kargs are followed by a newline
+            areas.kargs = Some(EmbedAreaUsage::new(capacity, current.len() + 1));
+            inspection.kargs = Some(KargsInspection::new(current, default));
+        }
+        inspection.embed_areas = Some(areas);
+        Ok(inspection)
+    }
+
+    pub fn for_pxe(initrd: &Initrd) -> Result<Self> {
+        let mut inspection = Self::for_initrd(initrd, OsFeatures::for_initrd(initrd)?)?;
+        inspection.os_version = initrd
+            .get(INITRD_OS_RELEASE_PATH)
+            .and_then(|data| os_release_version(&String::from_utf8_lossy(data)));
+        Ok(inspection)
+    }
+
+    fn for_initrd(initrd: &Initrd, features: OsFeatures) -> Result<Self> {
+        let mut inspection = Self {
+            volume_id: None,
+            os_version: None,
+            features,
+            ignition: None,
+            network_keyfiles: initrd
+                .find(&INITRD_NETWORK_GLOB)
+                .keys()
+                .map(|path| {
+                    path.trim_start_matches(INITRD_NETWORK_DIR)
+                        .trim_start_matches('/')
+                })
+                .map(|name| name.to_string())
+                .collect(),
+            kargs: None,
+            installer_config: Vec::new(),
+            pre_install: Vec::new(),
+            post_install: Vec::new(),
+            embed_areas: None,
+        };
+        if let Some(data) = initrd.get(INITRD_IGNITION_PATH) {
+            // inspect the config even if it's not a valid Ignition config
+            let config: Option<Value> = serde_json::from_slice(data).ok();
+            inspection.ignition = Some(IgnitionInspection {
+                digest: format!("sha256-{}", hex::encode(sha::sha256(data))),
+                version: config
+                    .as_ref()
+                    .and_then(|c| c.pointer("/ignition/version"))
+                    .and_then(|v| v.as_str())
+                    .map(|v| v.to_string()),
+            });
+            if let Some(config) = config {
+                inspection.add_ignition_files(&config);
+            }
+        }
+        Ok(inspection)
+    }
+
+    /// Find installer configs and install hooks written by the customize
+    /// subcommands.
+    fn add_ignition_files(&mut self, config: &Value) {
+        let files = match config.pointer("/storage/files").and_then(|f| f.as_array()) {
+            Some(files) => files,
+            None => return,
+        };
+        for file in files {
+            let path = match file.get("path").and_then(|p| p.as_str()) {
+                Some(path) => path,
+                None => continue,
+            };
+            if path.starts_with(INSTALLER_CONFIG_DIR) {
+                self.installer_config.push(InstallerConfigFile {
+                    path: path.to_string(),
+                    contents: decode_contents(file).and_then(|data| String::from_utf8(data).ok()),
+                });
+            } else if let Some(name) = path.strip_prefix(PRE_INSTALL_PREFIX) {
+                self.pre_install.push(name.to_string());
+            } else if let Some(name) = path.strip_prefix(POST_INSTALL_PREFIX) {
+                self.post_install.push(name.to_string());
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        let mut ret = serde_json::to_vec_pretty(self).context("serializing inspection")?;
+        ret.push(b'\n');
+        Ok(ret)
+    }
+
+    pub fn to_text(&self) -> String {
+        fn list(items: &[String]) -> String {
+            if items.is_empty() {
+                "none".into()
+            } else {
+                items.join(", ")
+            }
+        }
+
+        let mut lines: Vec<(&str, String)> = Vec::new();
+        if let Some(volume_id) = &self.volume_id {
+            lines.push(("Volume ID", volume_id.clone()));
+        }
+        if let Some(os_version) = &self.os_version {
+            lines.push(("OS version", os_version.clone()));
+        }
+        let mut features = Vec::new();
+        if self.features.installer_config {
+            features.push("installer-config".to_string());
+        }
+        if self.features.live_initrd_network {
+            features.push("live-initrd-network".to_string());
+        }
+        lines.push(("OS features", list(&features)));
+        lines.push((
+            "Ignition config",
+            match &self.ignition {
+                Some(ign) => format!(
+                    "{} ({})",
+                    ign.version
+                        .as_ref()
+                        .map(|v| format!("spec {}", v))
+                        .unwrap_or_else(|| "unparseable".into()),
+                    ign.digest
+                ),
+                None => "none".into(),
+            },
+        ));
+        lines.push(("Network keyfiles", list(&self.network_keyfiles)));
+        if let Some(kargs) = &self.kargs {
+            let mut changes = Vec::new();
+            if !kargs.appended.is_empty() {
+                changes.push(format!("appended {}", kargs.appended.join(" ")));
+            }
+            if !kargs.deleted.is_empty() {
+                changes.push(format!("deleted {}", kargs.deleted.join(" ")));
+            }
+            if changes.is_empty() {
+                changes.push("default".into());
+            }
+            lines.push(("Live kargs", changes.join("; ")));
+        }
+        let installer_config: Vec<String> = self
+            .installer_config
+            .iter()
+            .map(|f| f.path.clone())
+            .collect();
+        lines.push(("Installer configs", list(&installer_config)));
+        lines.push(("Pre-install hooks", list(&self.pre_install)));
+        lines.push(("Post-install hooks", list(&self.post_install)));
+        if let Some(areas) = &self.embed_areas {
+            let usage = |area: &EmbedAreaUsage| {
+                format!(
+                    "{} of {} bytes used, {} free",
+                    area.used, area.capacity, area.free
+                )
+            };
+            lines.push(("Initrd embed area", usage(&areas.initrd)));
+            if let Some(kargs) = &areas.kargs {
+                lines.push(("Kargs embed area", usage(kargs)));
+            }
+        }
+
+        format_key_values(&lines)
+    }
+}
+
+impl KargsInspection {
+    fn new(current: &str, default: &str) -> Self {
+        let current_args: Vec<&str> = current.split_whitespace().collect();
+        let default_args: Vec<&str> = default.split_whitespace().collect();
+        Self {
+            current: current.to_string(),
+            default: default.to_string(),
+            appended: current_args
+                .iter()
+                .filter(|a| !default_args.contains(a))
+                .map(|a| a.to_string())
+                .collect(),
+            deleted: default_args
+                .iter()
+                .filter(|a| !current_args.contains(a))
+                .map(|a| a.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Decode the contents of an Ignition file entry, if they're stored in a
+/// base64 data URL.
+fn decode_contents(file: &Value) -> Option<Vec<u8>> {
+    let contents = file.get("contents")?;
+    let source = contents.get("source")?.as_str()?;
+    let (_, data) = source.strip_prefix("data:")?.split_once(";base64,")?;
+    let data = base64::decode(data).ok()?;
+    match contents.get("compression").and_then(|c| c.as_str()) {
+        None | Some("") => Some(data),
+        Some("gzip") => {
+            let mut decompressed = Vec::new();
+            GzDecoder::new(&*data).read_to_end(&mut decompressed).ok()?;
+            Some(decompressed)
+        }
+        Some(_) => None,
+    }
+}
+
+/// Get the OS version from an os-release file.
+fn os_release_version(os_release: &str) -> Option<String> {
+    let get = |key: &str| {
+        os_release.lines().find_map(|line| {
+            line.strip_prefix(key)?
+                .strip_prefix('=')
+                .map(|v| v.trim_matches(|c| c == '"' || c == '\'').to_string())
+        })
+    };
+    get("OSTREE_VERSION").or_else(|| get("VERSION"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use clap::Parser;
+
+    use crate::cmdline::CommonCustomizeConfig;
+    use crate::live::customize::LiveInitrd;
+
+    #[test]
+    fn test_kargs_diff() {
+        let kargs = KargsInspection::new("a b=c d e", "a b=d e f");
+        assert_eq!(kargs.appended, vec!["b=c", "d"]);
+        assert_eq!(kargs.deleted, vec!["b=d", "f"]);
+    }
+
+    #[test]
+    fn test_os_release_version() {
+        assert_eq!(
+            os_release_version(
+                "NAME=\"Fedora Linux\"\nVERSION=\"36.20220618.3.1 (CoreOS)\"\nOSTREE_VERSION='36.20220618.3.1'\n"
+            )
+            .unwrap(),
+            "36.20220618.3.1"
+        );
+        assert_eq!(
+            os_release_version("NAME=Foo\nVERSION=\"1.2\"\n").unwrap(),
+            "1.2"
+        );
+        assert!(os_release_version("NAME=Foo\n").is_none());
+    }
+
+    #[test]
+    fn test_inspect_initrd() {
+        // uncustomized
+        let inspection = Inspection::for_pxe(&Initrd::default()).unwrap();
+        assert!(inspection.ignition.is_none());
+        assert!(inspection.installer_config.is_empty());
+
+        // customized
+        let dir = tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        let common = CommonCustomizeConfig::parse_from([
+            "customize",
+            "--dest-device",
+            "/dev/vda",
+            "--network-keyfile",
+            &write("eth0.nmconnection", "[connection]\n"),
+            "--pre-install",
+            &write("pre", "#!/bin/sh\n"),
+            "--post-install",
+            &write("post", "#!/bin/sh\n"),
+        ]);
+        let features = OsFeatures {
+            installer_config: true,
+            live_initrd_network: true,
+        };
+        let mut initrd = LiveInitrd::from_common(&common, features)
+            .unwrap()
+            .into_initrd()
+            .unwrap();
+        initrd.add(INITRD_OS_RELEASE_PATH, b"VERSION=\"1.2\"\n".to_vec());
+        initrd.add(
+            "etc/coreos/features.json",
+            br#"{"installer-config": true, "live-initrd-network": false}"#.to_vec(),
+        );
+        let inspection = Inspection::for_pxe(&initrd).unwrap();
+        assert_eq!(inspection.os_version.as_deref(), Some("1.2"));
+        assert!(inspection.features.installer_config);
+        assert!(!inspection.features.live_initrd_network);
+        let ignition = inspection.ignition.as_ref().unwrap();
+        assert_eq!(ignition.version.as_deref(), Some("3.3.0"));
+        assert!(ignition.digest.starts_with("sha256-"));
+        assert_eq!(inspection.network_keyfiles, vec!["eth0.nmconnection"]);
+        assert_eq!(inspection.pre_install, vec!["pre"]);
+        assert_eq!(inspection.post_install, vec!["post"]);
+        assert_eq!(inspection.installer_config.len(), 1);
+        let installer_config = &inspection.installer_config[0];
+        assert_eq!(
+            installer_config.path,
+            "/etc/coreos/installer.d/0000-customize.yaml"
+        );
+        assert!(installer_config
+            .contents
+            .as_ref()
+            .unwrap()
+            .contains("dest-device: /dev/vda"));
+        let text = inspection.to_text();
+        assert!(text.contains("OS version:         1.2\n"), "{}", text);
+        assert!(text.contains("Pre-install hooks:  pre\n"), "{}", text);
+    }
+}
@@ -16,9 +16,12 @@ use anyhow::{bail, Context, Result};
 use lazy_static::lazy_static;
 use serde::Serialize;
 use std::collections::HashMap;
-use std::fs::{create_dir_all, read, File, OpenOptions};
+use std::fs::{create_dir_all, read, read_link, File, OpenOptions};
 use std::io::{self, copy, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
 
 use crate::cmdline::*;
 use crate::io::*;
@@ -26,15 +29,22 @@ use crate::iso9660::{self, IsoFs};
 use crate::miniso;
 use crate::util::set_die_on_sigpipe;
 
+mod checksum;
 mod customize;
 mod embed;
+mod inspect;
+mod netboot;
 mod util;
 
+use self::checksum::*;
 use self::customize::*;
 use self::embed::*;
+use self::inspect::*;
+use self::netboot::*;
 use self::util::*;
 
 const INITRD_LIVE_STAMP_PATH: &str = "etc/coreos-live-initramfs";
+const INITRD_ROOTFS_STAMP_PATH: &str = "etc/coreos-live-rootfs";
 const COREOS_ISO_PXEBOOT_DIR: &str = "IMAGES/PXEBOOT";
 const COREOS_ISO_ROOTFS_IMG: &str = "IMAGES/PXEBOOT/ROOTFS.IMG";
 const COREOS_ISO_MINISO_FILE: &str = "COREOS/MINISO.DAT";
@@ -47,6 +57,8 @@ pub fn iso_embed(config: IsoEmbedConfig) -> Result<()> {
     eprintln!("`iso embed` is deprecated; use `iso ignition embed`.  Continuing.");
     iso_ignition_embed(IsoIgnitionEmbedConfig {
         force: config.force,
+        merge: false,
+        show_diff: false,
         ignition_file: config.config,
         output: config.output,
         input: config.input,
@@ -86,9 +98,18 @@ pub fn iso_ignition_embed(config: IsoIgnitionEmbedConfig) -> Result<()> {
     let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
     let mut iso = IsoConfig::for_file(&mut iso_file)?;
 
-    if !config.force && iso.have_ignition() {
-        bail!("This ISO image already has an embedded Ignition config; use -f to force.");
-    }
+    let ignition = if config.merge {
+        merge_ignition(
+            iso.initrd().get(INITRD_IGNITION_PATH),
+            &ignition,
+            config.show_diff,
+        )?
+    } else {
+        if !config.force && iso.have_ignition() {
+            bail!("This ISO image already has an embedded Ignition config; use -f to force.");
+        }
+        ignition
+    };
 
     iso.initrd_mut().add(INITRD_IGNITION_PATH, ignition);
 
@@ -176,12 +197,62 @@ pub fn pxe_ignition_wrap(config: PxeIgnitionWrapConfig) -> Result<()> {
         }
     };
 
+    let ignition = match &config.merge {
+        Some(path) => {
+            let existing = read_initrd(path, &[INITRD_IGNITION_PATH.into()])?;
+            merge_ignition(
+                existing.get(INITRD_IGNITION_PATH),
+                &ignition,
+                config.show_diff,
+            )?
+        }
+        None => ignition,
+    };
+
     let mut initrd = Initrd::default();
     initrd.add(INITRD_IGNITION_PATH, ignition);
 
     write_live_pxe(&initrd, config.output.as_ref())
 }
 
+/// Combine an existing Ignition config, if any, with a new one, and
+/// optionally print the changes to stderr.
+fn merge_ignition(existing: Option<&[u8]>, new: &[u8], show_diff: bool) -> Result<Vec<u8>> {
+    let (new_config, warnings) =
+        ignition_config::Config::parse_slice(new).context("parsing Ignition config")?;
+    for warning in warnings {
+        eprintln!("Warning parsing Ignition config: {}", warning);
+    }
+    let existing_value = match existing {
+        Some(existing) => {
+            serde_json::from_slice(existing).context("parsing existing Ignition config")?
+        }
+        None => serde_json::Value::Object(Default::default()),
+    };
+    if show_diff {
+        let new_value = serde_json::from_slice(new).context("parsing Ignition config")?;
+        let changes = diff_ignition_merge(&existing_value, &new_value);
+        if changes.is_empty() {
+            eprintln!("No changes to Ignition config.");
+        }
+        for change in changes {
+            eprintln!("{}", change);
+        }
+    }
+
+    let existing = match existing {
+        Some(existing) => existing,
+        // nothing to merge with
+        None => return Ok(new.to_vec()),
+    };
+    let (existing_config, _) = ignition_config::Config::parse_slice(existing)
+        .context("parsing existing Ignition config")?;
+    let mut merged = Ignition::default();
+    merged.merge_config(&existing_config)?;
+    merged.merge_config(&new_config)?;
+    merged.to_bytes()
+}
+
 pub fn pxe_ignition_unwrap(config: PxeIgnitionUnwrapConfig) -> Result<()> {
     set_die_on_sigpipe()?;
     let stdin = io::stdin();
@@ -255,7 +326,7 @@ fn initrd_network_extract(initrd: &Initrd, directory: Option<&String>) -> Result
         bail!("No embedded network settings.");
     }
     if let Some(dir) = directory {
-        create_dir_all(&dir)?;
+        create_dir_all(dir)?;
         for (path, contents) in files {
             let path = Path::new(dir).join(filename(path)?);
             OpenOptions::new()
@@ -321,6 +392,9 @@ pub fn iso_kargs_show(config: IsoKargsShowConfig) -> Result<()> {
 }
 
 pub fn iso_customize(config: IsoCustomizeConfig) -> Result<()> {
+    if config.implant_checksum && config.output.as_deref() == Some("-") {
+        bail!("Can't implant checksum when writing to stdout.");
+    }
     let mut iso_file = open_live_iso(&config.input, Some(config.output.as_ref()))?;
     let mut iso_fs = IsoFs::from_file(iso_file.try_clone().context("cloning file")?)
         .context("parsing ISO9660 image")?;
@@ -356,7 +430,18 @@ pub fn iso_customize(config: IsoCustomizeConfig) -> Result<()> {
         iso.set_kargs(&kargs)?;
     }
 
-    write_live_iso(&iso, &mut iso_file, config.output.as_ref())
+    write_live_iso(&iso, &mut iso_file, config.output.as_ref())?;
+
+    if config.implant_checksum {
+        let path = config.output.as_ref().unwrap_or(&config.input);
+        let mut output = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("opening {}", path))?;
+        implant_checksum(&mut output).context("implanting checksum")?;
+    }
+    Ok(())
 }
 
 pub fn iso_reset(config: IsoResetConfig) -> Result<()> {
@@ -372,8 +457,63 @@ pub fn iso_reset(config: IsoResetConfig) -> Result<()> {
     write_live_iso(&iso, &mut iso_file, config.output.as_ref())
 }
 
+pub fn iso_verify(config: IsoVerifyConfig) -> Result<()> {
+    let mut iso_file = open_live_iso(&config.input, None)?;
+    let iso = IsoConfig::for_file(&mut iso_file)?;
+
+    match verify_checksum(&mut iso_file, &iso.pristine_areas()?)? {
+        None => bail!("No embedded checksum found in ISO image."),
+        Some(ChecksumStatus::Match) => println!("Checksum OK"),
+        Some(ChecksumStatus::MatchWithSubstitutions) => {
+            println!("Checksum OK (ignoring customizations)")
+        }
+        Some(ChecksumStatus::Mismatch(Some((fragment, count)))) => bail!(
+            "Checksum mismatch; ISO image is corrupt (first bad fragment: {} of {}).",
+            fragment,
+            count
+        ),
+        Some(ChecksumStatus::Mismatch(None)) => {
+            bail!("Checksum mismatch; ISO image is corrupt.")
+        }
+    }
+    Ok(())
+}
+
+pub fn iso_inspect(config: IsoInspectConfig) -> Result<()> {
+    let iso_file = open_live_iso(&config.input, None)?;
+    let mut iso_fs = IsoFs::from_file(iso_file.try_clone().context("cloning file")?)
+        .context("parsing ISO9660 image")?;
+    let iso = IsoConfig::for_iso(&mut iso_fs)?;
+    write_inspection(&Inspection::for_iso(&mut iso_fs, &iso)?, config.json)
+}
+
+pub fn pxe_inspect(config: PxeInspectConfig) -> Result<()> {
+    let initrd = read_initrd(
+        &config.input,
+        &[
+            INITRD_FEATURES_PATH.into(),
+            INITRD_IGNITION_PATH.into(),
+            INITRD_OS_RELEASE_PATH.into(),
+            format!("{}/*", INITRD_NETWORK_DIR),
+        ],
+    )?;
+    write_inspection(&Inspection::for_pxe(&initrd)?, config.json)
+}
+
+fn write_inspection(inspection: &Inspection, json: bool) -> Result<()> {
+    let out = if json {
+        inspection.to_json()?
+    } else {
+        inspection.to_text().into_bytes()
+    };
+    io::stdout()
+        .lock()
+        .write_all(&out)
+        .context("writing output")
+}
+
 pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
-    // open input and set up output
+    // open inputs and set up output
     let mut input = BufReader::with_capacity(
         BUFFER_SIZE,
         OpenOptions::new()
@@ -381,6 +521,21 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
             .open(&config.input)
             .with_context(|| format!("opening {}", &config.input))?,
     );
+    let mut rootfs = match &config.embed_rootfs {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .read(true)
+                .open(path)
+                .with_context(|| format!("opening {}", path))?;
+            let size = file
+                .metadata()
+                .with_context(|| format!("getting metadata for {}", path))?
+                .len();
+            warn_rootfs_size(size);
+            Some(file)
+        }
+        None => None,
+    };
     let mut tempfile = match &*config.output {
         "-" => {
             verify_stdout_not_tty()?;
@@ -401,6 +556,7 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
     // copy and check base initrd
     let filter = GlobMatcher::new(&[
         INITRD_LIVE_STAMP_PATH,
+        INITRD_ROOTFS_STAMP_PATH,
         INITRD_FEATURES_PATH,
         INITRD_IGNITION_PATH,
         &format!("{}/*", INITRD_NETWORK_DIR),
@@ -425,17 +581,20 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
     {
         bail!("input is already customized");
     }
-    let features = match base_initrd.get(INITRD_FEATURES_PATH) {
-        Some(json) => serde_json::from_slice::<OsFeatures>(json).context("parsing OS features")?,
-        None => OsFeatures::default(),
-    };
+    if rootfs.is_some() && base_initrd.get(INITRD_ROOTFS_STAMP_PATH).is_some() {
+        bail!("input already contains a rootfs image");
+    }
+    let features = OsFeatures::for_initrd(&base_initrd)?;
 
     let live = LiveInitrd::from_common(&config.common, features)?;
     let initrd = live.into_initrd()?;
 
-    // append customizations to output
-    let do_write = |writer: &mut dyn Write| -> Result<()> {
+    // append rootfs and customizations to output
+    let mut do_write = |writer: &mut dyn Write| -> Result<()> {
         let mut buf = BufWriter::with_capacity(BUFFER_SIZE, writer);
+        if let Some(rootfs) = rootfs.take() {
+            append_rootfs(rootfs, &mut buf)?;
+        }
         buf.write_all(&initrd.to_bytes()?)
             .context("writing initrd")?;
         buf.flush().context("flushing initrd")
@@ -446,7 +605,7 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
             let mut tempfile = tempfile.unwrap();
             do_write(tempfile.as_file_mut())?;
             tempfile
-                .persist_noclobber(&path)
+                .persist_noclobber(path)
                 .map_err(|e| e.error)
                 .with_context(|| format!("persisting output file to {}", path))?;
             Ok(())
@@ -454,9 +613,30 @@ pub fn pxe_customize(config: PxeCustomizeConfig) -> Result<()> {
     }
 }
 
+/// Copy a live rootfs image to the output, verifying that it is one.
+fn append_rootfs(rootfs: impl Read, out: &mut dyn Write) -> Result<()> {
+    let filter = GlobMatcher::new(&[INITRD_ROOTFS_STAMP_PATH]).unwrap();
+    let rootfs = Initrd::from_reader_filtered(TeeReader::new(rootfs, out), &filter)
+        .context("reading/copying rootfs image")?;
+    if rootfs.get(INITRD_ROOTFS_STAMP_PATH).is_none() {
+        bail!("not a CoreOS live rootfs image");
+    }
+    Ok(())
+}
+
+fn warn_rootfs_size(size: u64) {
+    eprintln!(
+        "Warning: embedding the rootfs adds {} MiB to the initramfs.  Some firmware
+and bootloaders cannot load an initramfs this large, and the booted system
+needs enough RAM to hold it.",
+        size / (1024 * 1024)
+    );
+}
+
 #[derive(Serialize)]
 struct DevShowIsoOutput {
     header: IsoFs,
+    boot_catalog: Option<iso9660::BootCatalog>,
     records: Vec<String>,
 }
 
@@ -480,8 +660,10 @@ pub fn dev_show_iso(config: DevShowIsoConfig) -> Result<()> {
             .map(|r| r.map(|(path, _)| path))
             .collect::<Result<Vec<String>>>()
             .context("while walking ISO filesystem")?;
+        let boot_catalog = iso.get_boot_catalog()?;
         let info = DevShowIsoOutput {
             header: iso,
+            boot_catalog,
             records,
         };
 
@@ -530,13 +712,68 @@ pub fn dev_extract_initrd(config: DevExtractInitrdConfig) -> Result<()> {
     Ok(())
 }
 
+pub fn dev_create_initrd(config: DevCreateInitrdConfig) -> Result<()> {
+    if config.output.is_none() {
+        verify_stdout_not_tty()?;
+    }
+
+    let filter = glob_filter(&config.filter)?;
+    let base_path = Path::new(&config.directory);
+    let mut initrd = Initrd::default();
+    // the Initrd sorts its members, but sort anyway so errors are
+    // reported deterministically
+    for entry in WalkDir::new(base_path).min_depth(1).sort_by_file_name() {
+        let entry = entry.with_context(|| format!("walking {}", base_path.display()))?;
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            // parent directories are created automatically
+            continue;
+        }
+        let path = entry
+            .path()
+            .strip_prefix(base_path)
+            .with_context(|| format!("relativizing {}", entry.path().display()))?
+            .to_str()
+            .with_context(|| format!("path {} is not UTF-8", entry.path().display()))?;
+        if !filter.matches(path) {
+            continue;
+        }
+        let contents = if file_type.is_file() {
+            read(entry.path()).with_context(|| format!("reading {}", entry.path().display()))?
+        } else if file_type.is_symlink() {
+            read_link(entry.path())
+                .with_context(|| format!("reading link {}", entry.path().display()))?
+                .into_os_string()
+                .into_vec()
+        } else {
+            bail!(
+                "{} is not a regular file or symlink",
+                entry.path().display()
+            );
+        };
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("getting metadata for {}", entry.path().display()))?;
+        let mtime = match config.mtime {
+            Some(mtime) => mtime,
+            None => metadata.mtime().try_into().with_context(|| {
+                format!(
+                    "timestamp of {} out of range; use --mtime",
+                    entry.path().display()
+                )
+            })?,
+        };
+        initrd.add_with_metadata(path, contents, metadata.mode(), mtime);
+    }
+    if initrd.is_empty() {
+        bail!("no matching files found in {}", base_path.display());
+    }
+
+    write_live_pxe(&initrd, config.output.as_ref())
+}
+
 fn read_initrd(path: &str, filter: &[String]) -> Result<Initrd> {
-    let filter = if filter.is_empty() {
-        vec!["*"]
-    } else {
-        filter.iter().map(String::as_str).collect()
-    };
-    let filter = GlobMatcher::new(&filter).context("parsing glob patterns")?;
+    let filter = glob_filter(filter)?;
     match path {
         "-" => Initrd::from_reader_filtered(io::stdin().lock(), &filter),
         path => Initrd::from_reader_filtered(
@@ -550,6 +787,15 @@ fn read_initrd(path: &str, filter: &[String]) -> Result<Initrd> {
     .context("decoding initrd")
 }
 
+fn glob_filter(filter: &[String]) -> Result<GlobMatcher> {
+    let filter = if filter.is_empty() {
+        vec!["*"]
+    } else {
+        filter.iter().map(String::as_str).collect()
+    };
+    GlobMatcher::new(&filter).context("parsing glob patterns")
+}
+
 pub fn iso_extract_pxe(config: IsoExtractPxeConfig) -> Result<()> {
     let mut iso = IsoFs::from_file(open_live_iso(&config.input, None)?)?;
     let pxeboot = iso
@@ -565,21 +811,117 @@ pub fn iso_extract_pxe(config: IsoExtractPxeConfig) -> Result<()> {
         s
     };
 
+    // map from ISO filename to output filename
+    let mut written = HashMap::new();
+    // files to be combined into a single initrd
+    let mut combine = HashMap::new();
     for record in iso.list_dir(&pxeboot)? {
         match record? {
-            iso9660::DirectoryRecord::Directory(_) => continue,
+            iso9660::DirectoryRecord::Directory(_) | iso9660::DirectoryRecord::Symlink(_) => {
+                continue
+            }
             iso9660::DirectoryRecord::File(file) => {
+                let name = file.name.to_lowercase();
+                if config.single_initrd && (name == "initrd.img" || name == "rootfs.img") {
+                    combine.insert(name, file);
+                    continue;
+                }
                 let filename = {
                     let mut s = base.clone();
-                    s.push(file.name.to_lowercase());
+                    s.push(&name);
                     s
                 };
                 let path = Path::new(&config.output_dir).join(&filename);
                 println!("{}", path.display());
                 copy_file_from_iso(&mut iso, &file, &path)?;
+                written.insert(name, filename);
             }
         }
     }
+
+    let iso_config = IsoConfig::for_iso(&mut iso)?;
+    if config.single_initrd {
+        let (initrd, rootfs) = match (combine.get("initrd.img"), combine.get("rootfs.img")) {
+            (Some(initrd), Some(rootfs)) => (initrd, rootfs),
+            _ => bail!("ISO image has no PXE initrd.img or rootfs.img"),
+        };
+        let filename = {
+            let mut s = base.clone();
+            s.push("initrd.img");
+            s
+        };
+        let path = Path::new(&config.output_dir).join(&filename);
+        println!("{}", path.display());
+        warn_rootfs_size(rootfs.length.into());
+        let mut outf = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        let mut bufw = BufWriter::with_capacity(BUFFER_SIZE, &mut outf);
+        copy(&mut iso.read_file(initrd)?, &mut bufw)
+            .with_context(|| format!("writing {}", path.display()))?;
+        append_rootfs(iso.read_file(rootfs)?, &mut bufw)?;
+        if !iso_config.initrd().is_empty() {
+            // carry over the ISO's customizations
+            bufw.write_all(&iso_config.initrd().to_bytes()?)
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+        bufw.flush().context("flushing buffer")?;
+        written.insert("initrd.img".into(), filename);
+    }
+
+    if let Some(base_url) = &config.base_url {
+        let base = base.to_string_lossy();
+        let get = |name: &str| -> Result<String> {
+            Ok(written
+                .get(name)
+                .with_context(|| format!("ISO image has no PXE {}", name))?
+                .to_string_lossy()
+                .into_owned())
+        };
+        let kernel = get("vmlinuz").or_else(|_| get("kernel.img"))?;
+        let mut initrds = vec![get("initrd.img")?];
+        // a single initrd already includes the rootfs
+        let rootfs = if config.single_initrd {
+            None
+        } else {
+            Some(get("rootfs.img")?)
+        };
+
+        if !config.single_initrd && !iso_config.initrd().is_empty() {
+            // carry over the ISO's customizations
+            let filename = format!("{}ignition.img", base);
+            let path = Path::new(&config.output_dir).join(&filename);
+            println!("{}", path.display());
+            let data = iso_config.initrd().to_bytes()?;
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .and_then(|mut f| f.write_all(&data))
+                .with_context(|| format!("writing {}", path.display()))?;
+            initrds.push(filename);
+        }
+        let netboot = NetbootConfig::new(
+            base_url,
+            &kernel,
+            &initrds.iter().map(|s| s.as_str()).collect::<Vec<_>>(),
+            rootfs.as_deref(),
+            iso_config.kargs()?,
+            config.ignition_url.as_ref(),
+        )?;
+        for typ in &config.boot_config {
+            let path = Path::new(&config.output_dir).join(boot_config_filename(&base, *typ));
+            println!("{}", path.display());
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .and_then(|mut f| f.write_all(netboot.render(*typ).as_bytes()))
+                .with_context(|| format!("writing {}", path.display()))?;
+        }
+    }
     Ok(())
 }
 
@@ -650,6 +992,30 @@ pub fn iso_extract_minimal_iso(config: IsoExtractMinimalIsoConfig) -> Result<()>
     Ok(())
 }
 
+pub fn iso_extract_efiboot(config: IsoExtractEfibootConfig) -> Result<()> {
+    let mut iso = IsoFs::from_file(open_live_iso(&config.input, None)?)?;
+    let catalog = iso
+        .get_boot_catalog()?
+        .context("ISO image has no El Torito boot catalog.")?;
+    let entry = catalog
+        .entries
+        .iter()
+        .find(|e| e.platform == iso9660::BootPlatform::Efi)
+        .context("ISO image has no EFI boot entry.")?;
+    let file = iso.get_boot_image(entry)?;
+
+    if &config.output == "-" {
+        verify_stdout_not_tty()?;
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        copy(&mut iso.read_file(&file)?, &mut out).context("writing output")?;
+        out.flush().context("flushing output")?;
+    } else {
+        copy_file_from_iso(&mut iso, &file, Path::new(&config.output))?;
+    }
+    Ok(())
+}
+
 pub fn pack_minimal_iso(config: PackMinimalIsoConfig) -> Result<()> {
     let mut full_iso = IsoFs::from_file(open_live_iso(&config.full, Some(None))?)?;
     let mut minimal_iso = IsoFs::from_file(open_live_iso(&config.minimal, None)?)?;
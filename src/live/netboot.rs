@@ -0,0 +1,262 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Netboot configs for extracted PXE artifacts
+
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+
+use crate::cmdline::BootConfigType;
+use crate::io::KargsEditor;
+
+const MENU_LABEL: &str = "CoreOS (live)";
+
+#[derive(Debug)]
+pub(super) struct NetbootConfig {
+    kernel: Url,
+    initrds: Vec<Url>,
+    kargs: String,
+}
+
+impl NetbootConfig {
+    /// Build a config for the specified artifact filenames under `base_url`.
+    /// `kargs` are the live ISO kargs, which will be adapted for PXE.
+    /// `rootfs` is None if the rootfs is embedded in the initrds.
+    pub fn new(
+        base_url: &Url,
+        kernel: &str,
+        initrds: &[&str],
+        rootfs: Option<&str>,
+        kargs: &str,
+        ignition_url: Option<&Url>,
+    ) -> Result<Self> {
+        if base_url.cannot_be_a_base() {
+            bail!("invalid base URL: {}", base_url);
+        }
+        // treat the base URL as a directory
+        let mut base_url = base_url.clone();
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        let join = |name: &str| -> Result<Url> {
+            base_url
+                .join(name)
+                .with_context(|| format!("joining {} to {}", name, base_url))
+        };
+
+        // the ISO label is meaningless when netbooting
+        let liveiso_kargs: Vec<String> = kargs
+            .split_ascii_whitespace()
+            .filter(|karg| karg.starts_with("coreos.liveiso="))
+            .map(|karg| karg.to_string())
+            .collect();
+        let mut append = Vec::new();
+        if let Some(rootfs) = rootfs {
+            append.push(format!("coreos.live.rootfs_url={}", join(rootfs)?));
+        }
+        if let Some(url) = ignition_url {
+            append.push(format!("ignition.config.url={}", url));
+        }
+        let kargs = KargsEditor::new()
+            .delete(&liveiso_kargs)
+            .append(&append)
+            .apply_to(kargs)?;
+
+        Ok(Self {
+            kernel: join(kernel)?,
+            initrds: initrds
+                .iter()
+                .map(|name| join(name))
+                .collect::<Result<_>>()?,
+            kargs,
+        })
+    }
+
+    pub fn render(&self, typ: BootConfigType) -> String {
+        match typ {
+            BootConfigType::Ipxe => self.ipxe(),
+            BootConfigType::Pxelinux => self.pxelinux(),
+            BootConfigType::Grub => self.grub(),
+        }
+    }
+
+    fn ipxe(&self) -> String {
+        // UEFI iPXE only passes initrds named on the kernel command line
+        let names: Vec<String> = (0..self.initrds.len())
+            .map(|i| format!("initrd{}", i))
+            .collect();
+        let mut ret = format!(
+            "#!ipxe\nkernel {} {} {}\n",
+            self.kernel,
+            names
+                .iter()
+                .map(|n| format!("initrd={}", n))
+                .collect::<Vec<_>>()
+                .join(" "),
+            self.kargs
+        );
+        for (name, url) in names.iter().zip(&self.initrds) {
+            ret.push_str(&format!("initrd --name {} {}\n", name, url));
+        }
+        ret.push_str("boot\n");
+        ret
+    }
+
+    fn pxelinux(&self) -> String {
+        format!(
+            "DEFAULT coreos\n\nLABEL coreos\n    MENU LABEL {}\n    KERNEL {}\n    APPEND initrd={} {}\n",
+            MENU_LABEL,
+            self.kernel,
+            self.initrds
+                .iter()
+                .map(|u| u.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            self.kargs
+        )
+    }
+
+    fn grub(&self) -> String {
+        // GRUB resolves paths against the server it was loaded from
+        format!(
+            "menuentry '{}' {{\n    linux {} {}\n    initrd {}\n}}\n",
+            MENU_LABEL,
+            self.kernel.path(),
+            self.kargs,
+            self.initrds
+                .iter()
+                .map(|u| u.path())
+                .collect::<Vec<_>>()
+                .join(" ")
+        )
+    }
+}
+
+/// Filename of a boot config written alongside artifacts with the
+/// specified filename prefix.
+pub(super) fn boot_config_filename(prefix: &str, typ: BootConfigType) -> String {
+    match typ {
+        BootConfigType::Ipxe => format!("{}boot.ipxe", prefix),
+        BootConfigType::Pxelinux => format!("{}pxelinux.cfg", prefix),
+        BootConfigType::Grub => format!("{}grub.cfg", prefix),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(ignition_url: Option<&Url>) -> NetbootConfig {
+        NetbootConfig::new(
+            &Url::parse("http://example.com/coreos/").unwrap(),
+            "live-vmlinuz",
+            &["live-initrd.img", "live-ignition.img"],
+            Some("live-rootfs.img"),
+            "mitigations=auto,nosmt coreos.liveiso=fedora-coreos-36 ignition.firstboot ignition.platform.id=metal",
+            ignition_url,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_kargs() {
+        assert_eq!(
+            config(None).kargs,
+            "mitigations=auto,nosmt ignition.firstboot ignition.platform.id=metal coreos.live.rootfs_url=http://example.com/coreos/live-rootfs.img"
+        );
+        assert_eq!(
+            config(Some(&Url::parse("https://example.com/config.ign").unwrap())).kargs,
+            "mitigations=auto,nosmt ignition.firstboot ignition.platform.id=metal coreos.live.rootfs_url=http://example.com/coreos/live-rootfs.img ignition.config.url=https://example.com/config.ign"
+        );
+        // base URL without trailing slash
+        assert_eq!(
+            NetbootConfig::new(
+                &Url::parse("http://example.com/coreos").unwrap(),
+                "a",
+                &["b"],
+                Some("c"),
+                "",
+                None,
+            )
+            .unwrap()
+            .kargs,
+            "coreos.live.rootfs_url=http://example.com/coreos/c"
+        );
+        // embedded rootfs
+        assert_eq!(
+            NetbootConfig::new(
+                &Url::parse("http://example.com/coreos/").unwrap(),
+                "a",
+                &["b"],
+                None,
+                "coreos.liveiso=x ignition.firstboot",
+                None,
+            )
+            .unwrap()
+            .kargs,
+            "ignition.firstboot"
+        );
+        NetbootConfig::new(
+            &Url::parse("data:text/plain,foo").unwrap(),
+            "a",
+            &["b"],
+            Some("c"),
+            "",
+            None,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_render() {
+        let config = config(None);
+        let kargs = &config.kargs;
+        assert_eq!(
+            config.render(BootConfigType::Ipxe),
+            format!(
+                "#!ipxe
+kernel http://example.com/coreos/live-vmlinuz initrd=initrd0 initrd=initrd1 {}
+initrd --name initrd0 http://example.com/coreos/live-initrd.img
+initrd --name initrd1 http://example.com/coreos/live-ignition.img
+boot
+",
+                kargs
+            )
+        );
+        assert_eq!(
+            config.render(BootConfigType::Pxelinux),
+            format!(
+                "DEFAULT coreos
+
+LABEL coreos
+    MENU LABEL CoreOS (live)
+    KERNEL http://example.com/coreos/live-vmlinuz
+    APPEND initrd=http://example.com/coreos/live-initrd.img,http://example.com/coreos/live-ignition.img {}
+",
+                kargs
+            )
+        );
+        assert_eq!(
+            config.render(BootConfigType::Grub),
+            format!(
+                "menuentry 'CoreOS (live)' {{
+    linux /coreos/live-vmlinuz {}
+    initrd /coreos/live-initrd.img /coreos/live-ignition.img
+}}
+",
+                kargs
+            )
+        );
+    }
+}
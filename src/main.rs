@@ -15,15 +15,18 @@
 use anyhow::Result;
 use clap::Parser;
 
-use libcoreinst::{cmdline, download, install, live, osmet, source};
+use libcoreinst::{cmdline, devices, download, install, live, osmet, source, util::Interrupted};
 
 use cmdline::*;
 
 fn main() -> Result<()> {
-    match Cmd::parse() {
+    let result = match Cmd::parse() {
         Cmd::Download(c) => download::download(c),
         Cmd::Install(c) => install::install(c),
         Cmd::ListStream(c) => source::list_stream(c),
+        Cmd::ListDevices(c) => devices::list_devices(c),
+        Cmd::Inspect(c) => devices::inspect(c),
+        Cmd::Reconfigure(c) => install::reconfigure(c),
         Cmd::Iso(c) => match c {
             IsoCmd::Customize(c) => live::iso_customize(c),
             IsoCmd::Embed(c) => live::iso_embed(c),
@@ -47,8 +50,11 @@ fn main() -> Result<()> {
             IsoCmd::Extract(c) => match c {
                 IsoExtractCmd::Pxe(c) => live::iso_extract_pxe(c),
                 IsoExtractCmd::MinimalIso(c) => live::iso_extract_minimal_iso(c),
+                IsoExtractCmd::Efiboot(c) => live::iso_extract_efiboot(c),
             },
             IsoCmd::Reset(c) => live::iso_reset(c),
+            IsoCmd::Verify(c) => live::iso_verify(c),
+            IsoCmd::Inspect(c) => live::iso_inspect(c),
         },
         Cmd::Pxe(c) => match c {
             PxeCmd::Customize(c) => live::pxe_customize(c),
@@ -60,6 +66,7 @@ fn main() -> Result<()> {
                 PxeNetworkCmd::Wrap(c) => live::pxe_network_wrap(c),
                 PxeNetworkCmd::Unwrap(c) => live::pxe_network_unwrap(c),
             },
+            PxeCmd::Inspect(c) => live::pxe_inspect(c),
         },
         Cmd::Pack(c) => match c {
             PackCmd::Osmet(c) => osmet::pack_osmet(c),
@@ -72,11 +79,27 @@ fn main() -> Result<()> {
                 DevShowCmd::Iso(c) => live::dev_show_iso(c),
                 DevShowCmd::Initrd(c) => live::dev_show_initrd(c),
                 DevShowCmd::Fiemap(c) => osmet::dev_show_fiemap(c),
+                DevShowCmd::Osmet(c) => osmet::dev_show_osmet(c),
             },
             DevCmd::Extract(c) => match c {
                 DevExtractCmd::Osmet(c) => osmet::dev_extract_osmet(c),
                 DevExtractCmd::Initrd(c) => live::dev_extract_initrd(c),
             },
+            DevCmd::Create(c) => match c {
+                DevCreateCmd::Initrd(c) => live::dev_create_initrd(c),
+            },
+            DevCmd::Convert(c) => match c {
+                DevConvertCmd::Osmet(c) => osmet::dev_convert_osmet(c),
+            },
+            DevCmd::Verify(c) => match c {
+                DevVerifyCmd::Osmet(c) => osmet::dev_verify_osmet(c),
+            },
         },
+    };
+    // exit with the conventional status for death by the signal
+    if let Some(interrupted) = result.as_ref().err().and_then(Interrupted::find) {
+        eprintln!("Error: {}", interrupted);
+        std::process::exit(interrupted.exit_status());
     }
+    result
 }
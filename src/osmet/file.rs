@@ -12,14 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+//! osmet file format
+//!
+//! All osmet files start with a magic value and a bincode-encoded format
+//! version.
+//!
+//! Version 1 continues with a bincode-serialized header and `Osmet`,
+//! followed by the xz-compressed packed image.  bincode doesn't support
+//! backward-compatible changes, so any change to those structs requires a
+//! new file version.
+//!
+//! Version 2 continues with a series of sections.  Each section has a
+//! 4-byte ASCII tag, a little-endian u64 length, and its contents.  As in
+//! PNG, a tag starting with an uppercase letter marks a critical section
+//! which readers must understand, and readers skip unknown sections whose
+//! tag starts with a lowercase letter.  Metadata sections are JSON, so
+//! fields can be added compatibly.  Mappings are bincode for compactness;
+//! an incompatible change to them requires a new section tag.  The packed
+//! image section must come last.
+
+use std::fmt;
 use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter, Read};
+use std::io::{self, BufReader, BufWriter, Read, Seek, Write};
 use std::path::Path;
+use std::str::FromStr;
 
 use anyhow::{bail, Context, Result};
 use bincode::Options;
 use clap::crate_version;
 use serde::{Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
 use xz2::bufread::XzDecoder;
 
 use crate::io::{bincoder, BUFFER_SIZE};
@@ -29,19 +51,30 @@ use super::*;
 /// Magic header value for osmet binary.
 const OSMET_FILE_HEADER_MAGIC: [u8; 8] = *b"OSMET\0\0\0";
 
-/// Basic versioning. Used as a safety check that we're unpacking something we understand. Bump
-/// this when making changes to the format.
-const OSMET_FILE_VERSION: u32 = 1;
+/// Original bincode-only format.
+const OSMET_FILE_VERSION_1: u32 = 1;
+
+/// Sectioned format.  Bump this only for changes that can't be expressed
+/// with new sections or fields.
+pub(super) const OSMET_FILE_VERSION: u32 = 2;
+
+/// File header (JSON)
+const SECTION_HEADER: [u8; 4] = *b"HEAD";
+/// Image size and checksum, and partition offsets (JSON)
+const SECTION_PARTITIONS: [u8; 4] = *b"PART";
+/// Mappings for each partition (bincode)
+const SECTION_MAPPINGS: [u8; 4] = *b"MAPS";
+/// Optional checksums of each unpacked partition (JSON)
+const SECTION_CHECKSUMS: [u8; 4] = *b"csum";
+/// xz-compressed packed image
+const SECTION_PACKED_IMAGE: [u8; 4] = *b"PACK";
 
-/// We currently use bincode for serialization. Note bincode does not support backwards compatible
-/// changes well. However we do not currently care about backcompatibility. If that changes, we
-/// should change serializer.
+/// In version 1 this is bincode-serialized directly after the format
+/// version; in version 2 it's the JSON contents of the header section.
 #[derive(Serialize, Deserialize, Debug)]
 pub(super) struct OsmetFileHeader {
-    magic: [u8; 8],
-    version: u32,
     /// For informational purposes only.
-    app_version: String,
+    pub(super) app_version: String,
     /// Required sector size of target block device during unpacking.
     pub(super) sector_size: u32,
     pub(super) os_description: String,
@@ -51,8 +84,6 @@ pub(super) struct OsmetFileHeader {
 impl OsmetFileHeader {
     pub(super) fn new(sector_size: u32, os_description: &str) -> Result<Self> {
         Ok(Self {
-            magic: OSMET_FILE_HEADER_MAGIC,
-            version: OSMET_FILE_VERSION,
             app_version: crate_version!().into(),
             sector_size,
             os_description: os_description.into(),
@@ -72,11 +103,65 @@ impl OsmetFileHeader {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct OsmetV1 {
+    partitions: Vec<OsmetPartitionV1>,
+    checksum: Sha256Digest,
+    size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OsmetPartitionV1 {
+    start_offset: u64,
+    end_offset: u64,
+    mappings: Vec<Mapping>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PartitionsSection {
+    size: u64,
+    checksum: HexDigest,
+    partitions: Vec<PartitionOffsets>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PartitionOffsets {
+    start_offset: u64,
+    end_offset: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChecksumsSection {
+    partitions: Vec<HexDigest>,
+}
+
+/// Sha256Digest serialized as a hex string.
+#[derive(SerializeDisplay, DeserializeFromStr)]
+struct HexDigest(Sha256Digest);
+
+impl fmt::Display for HexDigest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&hex::encode(self.0 .0))
+    }
+}
+
+impl FromStr for HexDigest {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let digest = hex::decode(s).context("decoding hex digest")?;
+        Ok(Self(Sha256Digest(digest.try_into().map_err(|_| {
+            anyhow::anyhow!("SHA-256 digest has wrong length")
+        })?)))
+    }
+}
+
 pub(super) fn osmet_file_write(
     path: &Path,
     header: OsmetFileHeader,
     osmet: Osmet,
-    mut xzpacked_image: File,
+    mut xzpacked_image: impl Read,
+    xzpacked_size: u64,
 ) -> Result<()> {
     validate_osmet(&osmet).context("validating before writing")?;
 
@@ -90,16 +175,61 @@ pub(super) fn osmet_file_write(
             .tempfile_in(path.parent().unwrap())?,
     );
 
-    let coder = &mut bincoder();
-    coder
-        .serialize_into(&mut f, &header)
-        .context("failed to serialize osmet file header")?;
-    coder
-        .serialize_into(&mut f, &osmet)
-        .context("failed to serialize osmet")?;
+    bincoder()
+        .serialize_into(&mut f, &(OSMET_FILE_HEADER_MAGIC, OSMET_FILE_VERSION))
+        .context("failed to serialize osmet file version")?;
+    write_section(
+        &mut f,
+        SECTION_HEADER,
+        &serde_json::to_vec(&header).context("failed to serialize osmet file header")?,
+    )?;
+    let partitions = PartitionsSection {
+        size: osmet.size,
+        checksum: HexDigest(osmet.checksum),
+        partitions: osmet
+            .partitions
+            .iter()
+            .map(|p| PartitionOffsets {
+                start_offset: p.start_offset,
+                end_offset: p.end_offset,
+            })
+            .collect(),
+    };
+    write_section(
+        &mut f,
+        SECTION_PARTITIONS,
+        &serde_json::to_vec(&partitions).context("failed to serialize osmet partitions")?,
+    )?;
+    let mappings: Vec<&Vec<Mapping>> = osmet.partitions.iter().map(|p| &p.mappings).collect();
+    write_section(
+        &mut f,
+        SECTION_MAPPINGS,
+        &bincoder()
+            .serialize(&mappings)
+            .context("failed to serialize osmet mappings")?,
+    )?;
+    let checksums: Option<Vec<HexDigest>> = osmet
+        .partitions
+        .into_iter()
+        .map(|p| p.checksum.map(HexDigest))
+        .collect();
+    if let Some(checksums) = checksums {
+        write_section(
+            &mut f,
+            SECTION_CHECKSUMS,
+            &serde_json::to_vec(&ChecksumsSection {
+                partitions: checksums,
+            })
+            .context("failed to serialize osmet partition checksums")?,
+        )?;
+    }
 
-    // and followed by the xz-compressed packed image
-    copy(&mut xzpacked_image, &mut f)?;
+    // and finally the xz-compressed packed image
+    write_section_header(&mut f, SECTION_PACKED_IMAGE, xzpacked_size)?;
+    let n = copy(&mut xzpacked_image, &mut f).context("copying packed image")?;
+    if n != xzpacked_size {
+        bail!("packed image is {} bytes but expected {}", n, xzpacked_size);
+    }
 
     f.into_inner()
         .context("failed to flush write buffer")?
@@ -109,49 +239,224 @@ pub(super) fn osmet_file_write(
     Ok(())
 }
 
-/// Reads in the header, and does some basic sanity checking.
-fn read_and_check_header(mut f: &mut impl Read) -> Result<OsmetFileHeader> {
-    let header: OsmetFileHeader = bincoder()
-        .deserialize_from(&mut f)
+fn write_section_header(w: &mut impl Write, tag: [u8; 4], len: u64) -> Result<()> {
+    w.write_all(&tag)
+        .and_then(|_| w.write_all(&len.to_le_bytes()))
+        .with_context(|| format!("writing {} section header", section_name(&tag)))
+}
+
+fn write_section(w: &mut impl Write, tag: [u8; 4], contents: &[u8]) -> Result<()> {
+    write_section_header(w, tag, contents.len() as u64)?;
+    w.write_all(contents)
+        .with_context(|| format!("writing {} section", section_name(&tag)))
+}
+
+/// Reads the next section header, returning the tag and length.
+fn read_section_header(f: &mut impl Read) -> Result<([u8; 4], u64)> {
+    let mut tag = [0u8; 4];
+    let mut len = [0u8; 8];
+    f.read_exact(&mut tag)
+        .and_then(|_| f.read_exact(&mut len))
+        .context("reading osmet section header")?;
+    Ok((tag, u64::from_le_bytes(len)))
+}
+
+fn read_section(f: &mut impl Read, tag: &[u8; 4], len: u64) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    f.take(len)
+        .read_to_end(&mut buf)
+        .with_context(|| format!("reading {} section", section_name(tag)))?;
+    if buf.len() as u64 != len {
+        bail!("osmet file truncated in {} section", section_name(tag));
+    }
+    Ok(buf)
+}
+
+fn skip_section(f: &mut impl Read, tag: &[u8; 4], len: u64) -> Result<()> {
+    if tag[0].is_ascii_uppercase() {
+        bail!("unsupported osmet file section {}", section_name(tag));
+    }
+    let n = copy(&mut f.take(len), &mut io::sink())
+        .with_context(|| format!("skipping {} section", section_name(tag)))?;
+    if n != len {
+        bail!("osmet file truncated in {} section", section_name(tag));
+    }
+    Ok(())
+}
+
+fn section_name(tag: &[u8; 4]) -> String {
+    String::from_utf8_lossy(tag).into_owned()
+}
+
+/// Reads in the magic and version, and does some basic sanity checking.
+fn read_and_check_version(f: &mut impl Read) -> Result<u32> {
+    let (magic, version): ([u8; 8], u32) = bincoder()
+        .deserialize_from(f)
         .context("failed to deserialize osmet file")?;
-    if header.magic != OSMET_FILE_HEADER_MAGIC {
+    if magic != OSMET_FILE_HEADER_MAGIC {
         bail!("not an OSMET file!");
     }
-    if header.version != OSMET_FILE_VERSION {
-        bail!("incompatible OSMET file version {}", header.version);
+    if version != OSMET_FILE_VERSION_1 && version != OSMET_FILE_VERSION {
+        bail!("incompatible OSMET file version {}", version);
     }
+    Ok(version)
+}
 
-    Ok(header)
+/// Reads in the header.  In version 2 files, the header section must come
+/// first.
+fn read_header(f: &mut impl Read, version: u32) -> Result<OsmetFileHeader> {
+    if version == OSMET_FILE_VERSION_1 {
+        return bincoder()
+            .deserialize_from(f)
+            .context("failed to deserialize osmet file header");
+    }
+    let (tag, len) = read_section_header(f)?;
+    if tag != SECTION_HEADER {
+        bail!(
+            "expected {} section, found {}",
+            section_name(&SECTION_HEADER),
+            section_name(&tag)
+        );
+    }
+    serde_json::from_slice(&read_section(f, &tag, len)?)
+        .context("failed to deserialize osmet file header")
 }
 
-pub(super) fn osmet_file_read_header(path: &Path) -> Result<OsmetFileHeader> {
-    let mut f = BufReader::with_capacity(
+fn open_osmet_file(path: &Path) -> Result<BufReader<File>> {
+    Ok(BufReader::with_capacity(
         BUFFER_SIZE,
         OpenOptions::new()
             .read(true)
             .open(path)
             .with_context(|| format!("opening {:?}", path))?,
-    );
+    ))
+}
 
-    read_and_check_header(&mut f)
+pub(super) fn osmet_file_read_header(path: &Path) -> Result<OsmetFileHeader> {
+    let mut f = open_osmet_file(path)?;
+    let version = read_and_check_version(&mut f)?;
+    read_header(&mut f, version)
 }
 
-pub(super) fn osmet_file_read(path: &Path) -> Result<(OsmetFileHeader, Osmet, impl Read + Send)> {
-    let mut f = BufReader::with_capacity(
-        BUFFER_SIZE,
-        OpenOptions::new()
-            .read(true)
-            .open(path)
-            .with_context(|| format!("opening {:?}", path))?,
-    );
+/// Reads an osmet file, returning its format version, header, mappings,
+/// and a reader for the xz-compressed packed image and its size.
+pub(super) fn osmet_file_read_raw(
+    path: &Path,
+) -> Result<(u32, OsmetFileHeader, Osmet, impl Read + Send, u64)> {
+    let mut f = open_osmet_file(path)?;
+    let version = read_and_check_version(&mut f)?;
+    let header = read_header(&mut f, version)?;
 
-    let header = read_and_check_header(&mut f)?;
-    let osmet: Osmet = bincoder()
-        .deserialize_from(&mut f)
-        .context("failed to deserialize osmet file")?;
+    let (osmet, xzpacked_size) = if version == OSMET_FILE_VERSION_1 {
+        let osmet: OsmetV1 = bincoder()
+            .deserialize_from(&mut f)
+            .context("failed to deserialize osmet file")?;
+        let osmet = Osmet {
+            partitions: osmet
+                .partitions
+                .into_iter()
+                .map(|p| OsmetPartition {
+                    start_offset: p.start_offset,
+                    end_offset: p.end_offset,
+                    mappings: p.mappings,
+                    checksum: None,
+                })
+                .collect(),
+            checksum: osmet.checksum,
+            size: osmet.size,
+        };
+        // the packed image extends to EOF
+        let len = f
+            .get_ref()
+            .metadata()
+            .with_context(|| format!("getting metadata for {:?}", path))?
+            .len();
+        let offset = f.stream_position().context("getting osmet file offset")?;
+        (osmet, len.saturating_sub(offset))
+    } else {
+        let mut partitions: Option<PartitionsSection> = None;
+        let mut mappings: Option<Vec<Vec<Mapping>>> = None;
+        let mut checksums: Option<ChecksumsSection> = None;
+        let xzpacked_size = loop {
+            let (tag, len) = read_section_header(&mut f)?;
+            match tag {
+                SECTION_PACKED_IMAGE => break len,
+                SECTION_PARTITIONS => {
+                    partitions = Some(
+                        serde_json::from_slice(&read_section(&mut f, &tag, len)?)
+                            .context("failed to deserialize osmet partitions")?,
+                    )
+                }
+                SECTION_MAPPINGS => {
+                    mappings = Some(
+                        bincoder()
+                            .deserialize(&read_section(&mut f, &tag, len)?)
+                            .context("failed to deserialize osmet mappings")?,
+                    )
+                }
+                SECTION_CHECKSUMS => {
+                    checksums = Some(
+                        serde_json::from_slice(&read_section(&mut f, &tag, len)?)
+                            .context("failed to deserialize osmet partition checksums")?,
+                    )
+                }
+                _ => skip_section(&mut f, &tag, len)?,
+            }
+        };
+        let partitions = partitions
+            .with_context(|| format!("missing {} section", section_name(&SECTION_PARTITIONS)))?;
+        let mappings = mappings
+            .with_context(|| format!("missing {} section", section_name(&SECTION_MAPPINGS)))?;
+        if mappings.len() != partitions.partitions.len() {
+            bail!(
+                "found mappings for {} partitions but expected {}",
+                mappings.len(),
+                partitions.partitions.len()
+            );
+        }
+        let checksums: Vec<Option<Sha256Digest>> = match checksums {
+            Some(c) => {
+                if c.partitions.len() != mappings.len() {
+                    bail!(
+                        "found checksums for {} partitions but expected {}",
+                        c.partitions.len(),
+                        mappings.len()
+                    );
+                }
+                c.partitions.into_iter().map(|d| Some(d.0)).collect()
+            }
+            None => mappings.iter().map(|_| None).collect(),
+        };
+        let osmet = Osmet {
+            partitions: partitions
+                .partitions
+                .into_iter()
+                .zip(mappings)
+                .zip(checksums)
+                .map(|((p, mappings), checksum)| OsmetPartition {
+                    start_offset: p.start_offset,
+                    end_offset: p.end_offset,
+                    mappings,
+                    checksum,
+                })
+                .collect(),
+            checksum: partitions.checksum.0,
+            size: partitions.size,
+        };
+        (osmet, xzpacked_size)
+    };
 
     validate_osmet(&osmet).context("validating after reading")?;
-    Ok((header, osmet, XzDecoder::new(f)))
+    Ok((version, header, osmet, f.take(xzpacked_size), xzpacked_size))
+}
+
+pub(super) fn osmet_file_read(path: &Path) -> Result<(OsmetFileHeader, Osmet, impl Read + Send)> {
+    let (_, header, osmet, xzpacked_image, _) = osmet_file_read_raw(path)?;
+    Ok((
+        header,
+        osmet,
+        XzDecoder::new(BufReader::with_capacity(BUFFER_SIZE, xzpacked_image)),
+    ))
 }
 
 fn validate_osmet(osmet: &Osmet) -> Result<()> {
@@ -207,3 +512,187 @@ fn verify_canonical(mappings: &[Mapping]) -> Result<u64> {
 
     Ok(cursor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xz2::write::XzEncoder;
+
+    fn header() -> OsmetFileHeader {
+        OsmetFileHeader {
+            app_version: "0.1.0".into(),
+            sector_size: 512,
+            os_description: "Fedora CoreOS 36".into(),
+            os_architecture: "x86_64".into(),
+        }
+    }
+
+    fn osmet(checksums: bool) -> Osmet {
+        let partition = |start_offset, end_offset, object: u8| OsmetPartition {
+            start_offset,
+            end_offset,
+            mappings: vec![Mapping {
+                extent: Extent {
+                    logical: 0,
+                    physical: 4096,
+                    length: 8192,
+                },
+                object: Sha256Digest([object; 32]),
+            }],
+            checksum: if checksums {
+                Some(Sha256Digest([object + 1; 32]))
+            } else {
+                None
+            },
+        };
+        Osmet {
+            partitions: vec![
+                partition(1 << 20, 2 << 20, 1),
+                partition(2 << 20, 8 << 20, 3),
+            ],
+            checksum: Sha256Digest([9; 32]),
+            size: 9 << 20,
+        }
+    }
+
+    fn xzpacked() -> (Vec<u8>, Vec<u8>) {
+        let data = b"packed image".repeat(100);
+        let mut encoder = XzEncoder::new(Vec::new(), 0);
+        encoder.write_all(&data).unwrap();
+        (data, encoder.finish().unwrap())
+    }
+
+    fn check(path: &Path, expected_version: u32, checksums: bool) {
+        let (data, _) = xzpacked();
+        let (version, _, _, _, _) = osmet_file_read_raw(path).unwrap();
+        assert_eq!(version, expected_version);
+        assert_eq!(
+            osmet_file_read_header(path).unwrap().os_description,
+            "Fedora CoreOS 36"
+        );
+        let (header, osmet, mut image) = osmet_file_read(path).unwrap();
+        assert_eq!(header.sector_size, 512);
+        assert_eq!(header.os_architecture, "x86_64");
+        assert_eq!(osmet.size, 9 << 20);
+        assert_eq!(osmet.checksum, Sha256Digest([9; 32]));
+        assert_eq!(osmet.partitions.len(), 2);
+        assert_eq!(osmet.partitions[1].start_offset, 2 << 20);
+        assert_eq!(osmet.partitions[1].end_offset, 8 << 20);
+        assert_eq!(osmet.partitions[1].mappings[0].extent.length, 8192);
+        assert_eq!(
+            osmet.partitions[1].mappings[0].object,
+            Sha256Digest([3; 32])
+        );
+        assert_eq!(
+            osmet.partitions[1].checksum,
+            if checksums {
+                Some(Sha256Digest([4; 32]))
+            } else {
+                None
+            }
+        );
+        let mut buf = Vec::new();
+        image.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, xz) = xzpacked();
+        for checksums in [false, true] {
+            let path = dir.path().join("osmet");
+            osmet_file_write(&path, header(), osmet(checksums), &xz[..], xz.len() as u64).unwrap();
+            check(&path, OSMET_FILE_VERSION, checksums);
+        }
+
+        // wrong packed image size
+        osmet_file_write(
+            &dir.path().join("bad"),
+            header(),
+            osmet(false),
+            &xz[..],
+            xz.len() as u64 + 1,
+        )
+        .unwrap_err();
+    }
+
+    #[test]
+    fn test_read_v1() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("osmet");
+        let osmet = osmet(false);
+        let v1 = OsmetV1 {
+            partitions: osmet
+                .partitions
+                .into_iter()
+                .map(|p| OsmetPartitionV1 {
+                    start_offset: p.start_offset,
+                    end_offset: p.end_offset,
+                    mappings: p.mappings,
+                })
+                .collect(),
+            checksum: osmet.checksum,
+            size: osmet.size,
+        };
+        let mut f = File::create(&path).unwrap();
+        let coder = &mut bincoder();
+        coder
+            .serialize_into(&mut f, &(OSMET_FILE_HEADER_MAGIC, OSMET_FILE_VERSION_1))
+            .unwrap();
+        coder.serialize_into(&mut f, &header()).unwrap();
+        coder.serialize_into(&mut f, &v1).unwrap();
+        f.write_all(&xzpacked().1).unwrap();
+        drop(f);
+        check(&path, OSMET_FILE_VERSION_1, false);
+
+        // convert
+        let (_, header, osmet, image, size) = osmet_file_read_raw(&path).unwrap();
+        let converted = dir.path().join("converted");
+        osmet_file_write(&converted, header, osmet, image, size).unwrap();
+        check(&converted, OSMET_FILE_VERSION, false);
+    }
+
+    #[test]
+    fn test_sections() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, xz) = xzpacked();
+        let path = dir.path().join("osmet");
+        osmet_file_write(&path, header(), osmet(true), &xz[..], xz.len() as u64).unwrap();
+        let orig = std::fs::read(&path).unwrap();
+        // end of magic, version, and header section
+        let (_, len) = read_section_header(&mut &orig[9..]).unwrap();
+        let offset = 9 + 12 + len as usize;
+
+        // insert a section before the PART section
+        let insert = |tag: &[u8; 4]| {
+            let mut data = orig[..offset].to_vec();
+            write_section(&mut data, *tag, b"future").unwrap();
+            data.extend(&orig[offset..]);
+            std::fs::write(&path, data).unwrap();
+        };
+
+        // unknown optional sections are skipped
+        insert(b"zzzz");
+        check(&path, OSMET_FILE_VERSION, true);
+        // unknown critical sections are rejected
+        insert(b"ZZZZ");
+        assert!(osmet_file_read(&path)
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("unsupported osmet file section ZZZZ"));
+        // but don't prevent reading the header
+        osmet_file_read_header(&path).unwrap();
+
+        // truncated
+        std::fs::write(&path, &orig[..offset + 20]).unwrap();
+        osmet_file_read(&path).err().unwrap();
+
+        // unknown version
+        let mut data = orig.clone();
+        data[8] = 3;
+        std::fs::write(&path, data).unwrap();
+        osmet_file_read_header(&path).unwrap_err();
+    }
+}
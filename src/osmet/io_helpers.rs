@@ -12,7 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::io::Write;
+use std::fs::File;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
 use std::path::Path;
 
 use anyhow::{bail, Context, Result};
@@ -56,12 +57,67 @@ pub fn checksum_to_object_path(chksum: &Sha256Digest, buf: &mut Vec<u8>) -> Resu
     Ok(())
 }
 
+/// Copies a reader to a file, seeking over blocks of zeroes rather than writing them, so the
+/// file is sparse. The file should be empty. Returns the number of bytes copied.
+pub fn copy_sparse(r: &mut impl Read, f: &mut File) -> Result<u64> {
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut total: u64 = 0;
+    loop {
+        // fill the whole buffer if we can, so our block boundaries are
+        // consistent
+        let mut n = 0;
+        while n < buf.len() {
+            match r.read(&mut buf[n..]) {
+                Ok(0) => break,
+                Ok(count) => n += count,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).context("reading input"),
+            }
+        }
+        if n == 0 {
+            break;
+        }
+        if buf[..n].iter().all(|b| *b == 0) {
+            f.seek(SeekFrom::Current(n as i64))
+                .context("seeking over zeroes")?;
+        } else {
+            f.write_all(&buf[..n]).context("writing output")?;
+        }
+        total += n as u64;
+    }
+    // extend the file over any trailing zeroes
+    f.set_len(total).context("setting file length")?;
+    Ok(total)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::ffi::OsStr;
     use std::os::unix::ffi::OsStrExt;
 
+    #[test]
+    fn test_copy_sparse() {
+        use std::os::unix::fs::MetadataExt;
+
+        let mut data = vec![0u8; 1024 * 1024];
+        data[100] = 1;
+        data[512 * 1024] = 2;
+        let mut f = tempfile::tempfile().unwrap();
+        assert_eq!(
+            copy_sparse(&mut &data[..], &mut f).unwrap(),
+            data.len() as u64
+        );
+        let metadata = f.metadata().unwrap();
+        assert_eq!(metadata.len(), data.len() as u64);
+        // two 64 KiB blocks, assuming the filesystem supports holes
+        assert!(metadata.blocks() * 512 < data.len() as u64);
+        let mut found = Vec::new();
+        f.seek(SeekFrom::Start(0)).unwrap();
+        f.read_to_end(&mut found).unwrap();
+        assert_eq!(found, data);
+    }
+
     #[test]
     fn test_checksum_to_object_path() {
         let mut chksum = Sha256Digest([0u8; 32]);
@@ -36,6 +36,7 @@ use xz2::write::XzEncoder;
 use crate::blockdev::*;
 use crate::cmdline::*;
 use crate::io::*;
+use crate::util::format_key_values;
 
 mod fiemap;
 mod file;
@@ -56,14 +57,16 @@ struct Mapping {
     object: Sha256Digest,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
 struct OsmetPartition {
     start_offset: u64,
     end_offset: u64,
     mappings: Vec<Mapping>,
+    /// Checksum of the unpacked partition, if known.
+    checksum: Option<Sha256Digest>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Debug)]
 struct Osmet {
     /// Partitions for which we've registered mappings.
     partitions: Vec<OsmetPartition>,
@@ -89,6 +92,139 @@ pub fn dev_show_fiemap(config: DevShowFiemapConfig) -> Result<()> {
     Ok(())
 }
 
+#[derive(Serialize)]
+struct OsmetInfo {
+    format_version: u32,
+    header: OsmetFileHeader,
+    image: OsmetImageInfo,
+    partitions: Vec<OsmetPartitionInfo>,
+}
+
+#[derive(Serialize)]
+struct OsmetImageInfo {
+    size: u64,
+    checksum: String,
+    /// Bytes read from OSTree objects when unpacking.
+    mapped_bytes: u64,
+    /// Bytes read from the packed image when unpacking.
+    packed_bytes: u64,
+    /// Size of the xz-compressed packed image in the osmet file.
+    packed_compressed_bytes: u64,
+}
+
+#[derive(Serialize)]
+struct OsmetPartitionInfo {
+    start_offset: u64,
+    end_offset: u64,
+    mappings: usize,
+    mapped_bytes: u64,
+    checksum: Option<String>,
+}
+
+impl OsmetInfo {
+    fn new(
+        format_version: u32,
+        header: OsmetFileHeader,
+        osmet: &Osmet,
+        packed_compressed_bytes: u64,
+    ) -> Result<Self> {
+        let partitions = osmet
+            .partitions
+            .iter()
+            .map(|p| {
+                Ok(OsmetPartitionInfo {
+                    start_offset: p.start_offset,
+                    end_offset: p.end_offset,
+                    mappings: p.mappings.len(),
+                    mapped_bytes: p.mappings.iter().map(|m| m.extent.length).sum(),
+                    checksum: p.checksum.as_ref().map(|c| c.to_hex_string()).transpose()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mapped_bytes = partitions.iter().map(|p| p.mapped_bytes).sum();
+        Ok(Self {
+            format_version,
+            header,
+            image: OsmetImageInfo {
+                size: osmet.size,
+                checksum: osmet.checksum.to_hex_string()?,
+                mapped_bytes,
+                packed_bytes: osmet.size.saturating_sub(mapped_bytes),
+                packed_compressed_bytes,
+            },
+            partitions,
+        })
+    }
+
+    fn to_text(&self) -> String {
+        let percent = |n: u64| {
+            if self.image.size > 0 {
+                n as f64 * 100.0 / self.image.size as f64
+            } else {
+                0.0
+            }
+        };
+        let mut lines: Vec<(String, String)> = vec![
+            ("Format version".into(), self.format_version.to_string()),
+            ("App version".into(), self.header.app_version.clone()),
+            ("OS description".into(), self.header.os_description.clone()),
+            (
+                "OS architecture".into(),
+                self.header.os_architecture.clone(),
+            ),
+            ("Sector size".into(), self.header.sector_size.to_string()),
+            ("Image size".into(), format!("{} bytes", self.image.size)),
+            ("Image SHA-256".into(), self.image.checksum.clone()),
+            (
+                "From OSTree objects".into(),
+                format!(
+                    "{} bytes ({:.1}%)",
+                    self.image.mapped_bytes,
+                    percent(self.image.mapped_bytes)
+                ),
+            ),
+            (
+                "From packed image".into(),
+                format!(
+                    "{} bytes ({:.1}%), {} bytes compressed",
+                    self.image.packed_bytes,
+                    percent(self.image.packed_bytes),
+                    self.image.packed_compressed_bytes
+                ),
+            ),
+        ];
+        for (i, p) in self.partitions.iter().enumerate() {
+            lines.push((
+                format!("Partition {}", i),
+                format!(
+                    "offsets {}-{}, {} mappings, {} bytes mapped",
+                    p.start_offset, p.end_offset, p.mappings, p.mapped_bytes
+                ),
+            ));
+            if let Some(checksum) = &p.checksum {
+                lines.push((format!("Partition {} SHA-256", i), checksum.clone()));
+            }
+        }
+
+        format_key_values(&lines)
+    }
+}
+
+pub fn dev_show_osmet(config: DevShowOsmetConfig) -> Result<()> {
+    let (version, header, osmet, _, xzpacked_size) = osmet_file_read_raw(Path::new(&config.osmet))?;
+    let info = OsmetInfo::new(version, header, &osmet, xzpacked_size)?;
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if config.json {
+        serde_json::to_writer_pretty(&mut out, &info).context("failed to serialize osmet info")?;
+        out.write_all(b"\n").context("failed to write newline")?;
+    } else {
+        out.write_all(info.to_text().as_bytes())
+            .context("failed to write osmet info")?;
+    }
+    Ok(())
+}
+
 pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
     // First, mount the two main partitions we want to suck out data from: / and /boot. Note
     // MS_RDONLY; this also ensures that the partition isn't already mounted rw elsewhere.
@@ -105,7 +241,7 @@ pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
 
     let boot_partition = scan_boot_partition(&boot, mapped_boot_files)?;
 
-    let partitions = vec![boot_partition, root_partition];
+    let mut partitions = vec![boot_partition, root_partition];
 
     // create a first tempfile to store the packed image
     eprintln!("Packing image");
@@ -114,7 +250,7 @@ pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
 
     // verify that re-packing will yield the expected checksum
     eprintln!("Verifying that repacked image matches digest");
-    let (checksum, unpacked_size) =
+    let (checksum, unpacked_size, partition_checksums) =
         get_unpacked_image_digest(&mut xzpacked_image, &partitions, &root)?;
     xzpacked_image
         .seek(SeekFrom::Start(0))
@@ -137,6 +273,10 @@ pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
         );
     }
 
+    for (partition, checksum) in partitions.iter_mut().zip(partition_checksums) {
+        partition.checksum = Some(checksum);
+    }
+
     let sector_size = get_sector_size_for_path(Path::new(&config.device))?.get();
     let header = OsmetFileHeader::new(sector_size, &config.description)?;
 
@@ -147,41 +287,124 @@ pub fn pack_osmet(config: PackOsmetConfig) -> Result<()> {
         size,
     };
 
-    osmet_file_write(Path::new(&config.output), header, osmet, xzpacked_image)?;
+    let xzpacked_size = xzpacked_image
+        .metadata()
+        .context("getting size of packed image")?
+        .len();
+    osmet_file_write(
+        Path::new(&config.output),
+        header,
+        osmet,
+        xzpacked_image,
+        xzpacked_size,
+    )?;
     eprintln!("Packing successful!");
 
     Ok(())
 }
 
-pub fn dev_extract_osmet(config: DevExtractOsmetConfig) -> Result<()> {
-    // open output device for writing
-    let mut dev = OpenOptions::new()
-        .write(true)
-        .open(Path::new(&config.device))
-        .with_context(|| format!("opening {:?}", &config.device))?;
+pub fn dev_convert_osmet(config: DevConvertOsmetConfig) -> Result<()> {
+    let (version, header, osmet, xzpacked_image, xzpacked_size) =
+        osmet_file_read_raw(Path::new(&config.input))?;
+    if version == OSMET_FILE_VERSION {
+        bail!(
+            "{} is already an osmet version {} file",
+            &config.input,
+            version
+        );
+    }
 
-    if !dev
-        .metadata()
-        .with_context(|| format!("getting metadata for {:?}", &config.device))?
-        .file_type()
-        .is_block_device()
-    {
-        bail!("{:?} is not a block device", &config.device);
+    osmet_file_write(
+        Path::new(&config.output),
+        header,
+        osmet,
+        xzpacked_image,
+        xzpacked_size,
+    )?;
+    eprintln!(
+        "Converted osmet version {} file to version {}",
+        version, OSMET_FILE_VERSION
+    );
+    Ok(())
+}
+
+pub fn dev_verify_osmet(config: DevVerifyOsmetConfig) -> Result<()> {
+    let (_, osmet, packed_image) = osmet_file_read(Path::new(&config.osmet))?;
+    if osmet.partitions.iter().all(|p| p.checksum.is_none()) {
+        eprintln!("osmet file has no partition checksums; only checking the whole image");
+    }
+    let problems = verify_unpacked_image(&osmet, packed_image, Path::new(&config.repo))?;
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("{}", problem);
+        }
+        bail!("osmet file does not match OSTree repo");
     }
+    println!("osmet file matches OSTree repo");
+    Ok(())
+}
 
-    let mut unpacker = OsmetUnpacker::new(Path::new(&config.osmet), Path::new(&config.repo))?;
-    copy(&mut unpacker, &mut dev)
-        .with_context(|| format!("copying to block device {}", &config.device))?;
+pub fn dev_extract_osmet(config: DevExtractOsmetConfig) -> Result<()> {
+    match (&config.output, &config.device) {
+        (Some(output), _) if output == "-" => {
+            let mut unpacker =
+                OsmetUnpacker::new(Path::new(&config.osmet), Path::new(&config.repo))?;
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            copy(&mut unpacker, &mut out).context("copying to stdout")?;
+            out.flush().context("flushing stdout")?;
+        }
+        (Some(output), _) => {
+            let mut unpacker =
+                OsmetUnpacker::new(Path::new(&config.osmet), Path::new(&config.repo))?;
+            let mut f = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(output)
+                .with_context(|| format!("opening {:?}", output))?;
+            copy_sparse(&mut unpacker, &mut f).with_context(|| format!("copying to {}", output))?;
+        }
+        (None, Some(device)) => {
+            // open output device for writing
+            let mut dev = OpenOptions::new()
+                .write(true)
+                .open(Path::new(device))
+                .with_context(|| format!("opening {:?}", device))?;
+
+            if !dev
+                .metadata()
+                .with_context(|| format!("getting metadata for {:?}", device))?
+                .file_type()
+                .is_block_device()
+            {
+                bail!("{:?} is not a block device", device);
+            }
+
+            let mut unpacker =
+                OsmetUnpacker::new(Path::new(&config.osmet), Path::new(&config.repo))?;
+            copy(&mut unpacker, &mut dev)
+                .with_context(|| format!("copying to block device {}", device))?;
+        }
+        // clap ensures we have one or the other
+        (None, None) => unreachable!(),
+    }
 
     Ok(())
 }
 
+/// Find the first osmet file (by filename) matching the architecture and
+/// sector size, and optionally whose OS version or description matches
+/// `version`.
 pub fn find_matching_osmet_in_dir(
     osmet_dir: &Path,
     architecture: &str,
     sector_size: u32,
+    version: Option<&glob::Pattern>,
 ) -> Result<Option<(PathBuf, String)>> {
-    for entry in WalkDir::new(osmet_dir).max_depth(1) {
+    for entry in WalkDir::new(osmet_dir)
+        .max_depth(1)
+        .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+    {
         let entry = entry.with_context(|| format!("walking {:?}", osmet_dir))?;
 
         if !entry.file_type().is_file() {
@@ -189,7 +412,12 @@ pub fn find_matching_osmet_in_dir(
         }
 
         let header = osmet_file_read_header(entry.path())?;
-        if header.os_architecture == architecture && header.sector_size == sector_size {
+        if header.os_architecture == architecture
+            && header.sector_size == sector_size
+            && version.map_or(true, |v| {
+                description_matches_version(&header.os_description, v)
+            })
+        {
             return Ok(Some((entry.into_path(), header.os_description)));
         }
     }
@@ -197,6 +425,17 @@ pub fn find_matching_osmet_in_dir(
     Ok(None)
 }
 
+/// Check whether an osmet OS description (e.g. "Fedora CoreOS 35.20211203.3.0")
+/// matches a version glob.  The glob can match either the version, which is
+/// the last word of the description, or the full description.
+fn description_matches_version(description: &str, version: &glob::Pattern) -> bool {
+    version.matches(description)
+        || description
+            .split_whitespace()
+            .last()
+            .map_or(false, |v| version.matches(v))
+}
+
 fn scan_root_partition(
     root: &Mount,
     mut boot_files: HashMap<u64, PathBuf>,
@@ -284,6 +523,7 @@ fn scan_root_partition(
             start_offset,
             end_offset,
             mappings,
+            checksum: None,
         },
         mapped_boot_files,
     ))
@@ -342,6 +582,7 @@ fn scan_boot_partition(
         start_offset,
         end_offset,
         mappings,
+        checksum: None,
     })
 }
 
@@ -366,7 +607,7 @@ fn write_xzpacked_image_to_file(
 
     let mut dev = OpenOptions::new()
         .read(true)
-        .open(&block_device)
+        .open(block_device)
         .with_context(|| format!("opening {:?}", block_device))?;
 
     let total_bytes_skipped = write_packed_image(&mut dev, &mut xz_tmpf, partitions)?;
@@ -509,6 +750,87 @@ mod tests {
     use super::*;
     use std::default::Default;
 
+    #[test]
+    fn test_description_matches_version() {
+        let desc = "Fedora CoreOS 35.20211203.3.0";
+        let m = |v| description_matches_version(desc, &glob::Pattern::new(v).unwrap());
+        assert!(m("35.20211203.3.0"));
+        assert!(m("35.*"));
+        assert!(m("Fedora CoreOS 35.*"));
+        assert!(m("*"));
+        assert!(!m("36.*"));
+        assert!(!m("35.20211203.3"));
+        assert!(!m("CoreOS"));
+        assert!(!description_matches_version(
+            "",
+            &glob::Pattern::new("35.*").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_osmet_info() {
+        let mapping = |physical, length| Mapping {
+            extent: Extent {
+                logical: 0,
+                physical,
+                length,
+            },
+            object: Sha256Digest::default(),
+        };
+        let osmet = Osmet {
+            partitions: vec![
+                OsmetPartition {
+                    start_offset: 1000,
+                    end_offset: 2000,
+                    mappings: vec![mapping(0, 100)],
+                    checksum: None,
+                },
+                OsmetPartition {
+                    start_offset: 2000,
+                    end_offset: 4000,
+                    mappings: vec![mapping(0, 200), mapping(500, 300)],
+                    checksum: Some(Sha256Digest([0xab; 32])),
+                },
+            ],
+            checksum: Sha256Digest([1; 32]),
+            size: 5000,
+        };
+        let header = OsmetFileHeader {
+            app_version: "0.16.0".into(),
+            sector_size: 512,
+            os_description: "Fedora CoreOS 36".into(),
+            os_architecture: "x86_64".into(),
+        };
+        let info = OsmetInfo::new(2, header, &osmet, 1234).unwrap();
+        assert_eq!(info.image.mapped_bytes, 600);
+        assert_eq!(info.image.packed_bytes, 4400);
+        assert_eq!(info.partitions[0].mappings, 1);
+        assert_eq!(info.partitions[1].mappings, 2);
+        assert_eq!(info.partitions[1].mapped_bytes, 500);
+        assert_eq!(info.partitions[0].checksum, None);
+        assert_eq!(info.partitions[1].checksum, Some("ab".repeat(32)));
+        assert_eq!(
+            info.to_text(),
+            format!(
+                "Format version:      2
+App version:         0.16.0
+OS description:      Fedora CoreOS 36
+OS architecture:     x86_64
+Sector size:         512
+Image size:          5000 bytes
+Image SHA-256:       {}
+From OSTree objects: 600 bytes (12.0%)
+From packed image:   4400 bytes (88.0%), 1234 bytes compressed
+Partition 0:         offsets 1000-2000, 1 mappings, 100 bytes mapped
+Partition 1:         offsets 2000-4000, 2 mappings, 500 bytes mapped
+Partition 1 SHA-256: {}
+",
+                "01".repeat(32),
+                "ab".repeat(32)
+            )
+        );
+    }
+
     #[test]
     fn test_canonicalize() {
         let mut mappings: Vec<Mapping> = Vec::new();
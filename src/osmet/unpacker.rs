@@ -12,14 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::VecDeque;
 use std::ffi::OsStr;
 use std::fs::{File, OpenOptions};
 use std::io::{self, copy, ErrorKind, Read, Seek, SeekFrom, Write};
 use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use openssl::hash::{Hasher, MessageDigest};
 use xz2::read::XzDecoder;
 
@@ -29,6 +31,17 @@ use crate::io::WriteHasher;
 /// Path to OSTree repo of sysroot.
 const SYSROOT_OSTREE_REPO: &str = "/sysroot/ostree/repo";
 
+/// Number of threads reading OSTree objects.
+const OBJECT_READ_THREADS: usize = 4;
+/// Number of object chunks to read ahead of the writer.
+const OBJECT_READ_AHEAD: usize = 32;
+/// Maximum size of an object chunk.
+const OBJECT_CHUNK_SIZE: u64 = 1024 * 1024;
+/// Number of decompressed chunks of the packed image to buffer.
+const PACKED_READ_AHEAD: usize = 16;
+/// Size of a decompressed chunk of the packed image.
+const READ_AHEAD_CHUNK_SIZE: usize = 256 * 1024;
+
 pub struct OsmetUnpacker {
     thread_handle: Option<thread::JoinHandle<Result<()>>>,
     reader: pipe::PipeReader,
@@ -93,22 +106,31 @@ pub(super) fn get_unpacked_image_digest(
     xzpacked_image: &mut File,
     partitions: &[OsmetPartition],
     root: &Mount,
-) -> Result<(Sha256Digest, u64)> {
+) -> Result<(Sha256Digest, u64, Vec<Sha256Digest>)> {
     let mut hasher = Hasher::new(MessageDigest::sha256()).context("creating SHA256 hasher")?;
     let repo = root.mountpoint().join("ostree/repo");
     let mut packed_image = XzDecoder::new(xzpacked_image);
-    let n = write_unpacked_image(&mut packed_image, &mut hasher, partitions, &repo)?;
-    Ok((hasher.try_into()?, n))
+    let mut partition_checksums = Vec::new();
+    let n = write_unpacked_image(
+        &mut packed_image,
+        &mut hasher,
+        partitions,
+        &repo,
+        Some(&mut partition_checksums),
+    )?;
+    Ok((hasher.try_into()?, n, partition_checksums))
 }
 
 fn osmet_unpack_to_writer(
     osmet: Osmet,
-    mut packed_image: impl Read,
+    packed_image: impl Read + Send + 'static,
     repo: PathBuf,
     writer: impl Write,
 ) -> Result<()> {
+    // decompress the packed image in the background while we're writing
+    let mut packed_image = ReadAhead::new(packed_image, PACKED_READ_AHEAD);
     let mut w = WriteHasher::new_sha256(writer)?;
-    let n = write_unpacked_image(&mut packed_image, &mut w, &osmet.partitions, &repo)?;
+    let n = write_unpacked_image(&mut packed_image, &mut w, &osmet.partitions, &repo, None)?;
     if n != osmet.size {
         bail!("wrote {} bytes but expected {}", n, osmet.size);
     }
@@ -125,20 +147,98 @@ fn osmet_unpack_to_writer(
     Ok(())
 }
 
+/// Unpacks an image without writing it anywhere, and returns a description of each way in which it
+/// differs from the expected size and checksums.
+pub(super) fn verify_unpacked_image(
+    osmet: &Osmet,
+    packed_image: impl Read + Send + 'static,
+    repo: &Path,
+) -> Result<Vec<String>> {
+    let mut packed_image = ReadAhead::new(packed_image, PACKED_READ_AHEAD);
+    let mut w = WriteHasher::new_sha256(io::sink())?;
+    let mut partition_checksums = Vec::new();
+    let size = write_unpacked_image(
+        &mut packed_image,
+        &mut w,
+        &osmet.partitions,
+        repo,
+        Some(&mut partition_checksums),
+    )?;
+    Ok(compare_unpacked_image(
+        osmet,
+        size,
+        &w.try_into()?,
+        &partition_checksums,
+    ))
+}
+
+fn compare_unpacked_image(
+    osmet: &Osmet,
+    size: u64,
+    checksum: &Sha256Digest,
+    partition_checksums: &[Sha256Digest],
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (i, (partition, found)) in osmet.partitions.iter().zip(partition_checksums).enumerate() {
+        if let Some(expected) = &partition.checksum {
+            if expected != found {
+                problems.push(format!(
+                    "partition {} (offsets {}-{}, {} mappings) has checksum {} but expected {}",
+                    i,
+                    partition.start_offset,
+                    partition.end_offset,
+                    partition.mappings.len(),
+                    hex::encode(found.0),
+                    hex::encode(expected.0)
+                ));
+            }
+        }
+    }
+    if size != osmet.size {
+        problems.push(format!(
+            "image has {} bytes but expected {}",
+            size, osmet.size
+        ));
+    }
+    if *checksum != osmet.checksum {
+        problems.push(format!(
+            "image has checksum {} but expected {}",
+            hex::encode(checksum.0),
+            hex::encode(osmet.checksum.0)
+        ));
+    }
+    problems
+}
+
+/// If `partition_checksums` is specified, also compute the checksum of
+/// each unpacked partition.
 fn write_unpacked_image(
     packed_image: &mut impl Read,
     w: &mut impl Write,
     partitions: &[OsmetPartition],
     repo: &Path,
+    mut partition_checksums: Option<&mut Vec<Sha256Digest>>,
 ) -> Result<u64> {
     let mut buf = [0u8; 8192];
+    let pool = ObjectReaderPool::new(OBJECT_READ_THREADS);
 
     // start streaming writes to device, interspersing OSTree objects
     let mut cursor: u64 = 0;
-    for partition in partitions {
+    for (i, partition) in partitions.iter().enumerate() {
         assert!(partition.start_offset >= cursor);
         cursor += copy_exactly_n(packed_image, w, partition.start_offset - cursor, &mut buf)?;
-        cursor += write_partition(w, partition, packed_image, repo, &mut buf)?;
+        match partition_checksums.as_mut() {
+            Some(checksums) => {
+                let mut hasher = WriteHasher::new_sha256(&mut *w)?;
+                cursor += write_partition(&mut hasher, partition, packed_image, repo, &pool)
+                    .with_context(|| format!("unpacking partition {}", i))?;
+                checksums.push(hasher.try_into()?);
+            }
+            None => {
+                cursor += write_partition(w, partition, packed_image, repo, &pool)
+                    .with_context(|| format!("unpacking partition {}", i))?
+            }
+        }
     }
 
     // and copy the rest
@@ -147,13 +247,67 @@ fn write_unpacked_image(
     Ok(cursor)
 }
 
+/// A piece of an unpacked partition.
+enum Segment<T> {
+    /// Bytes to copy from the packed image.
+    Packed(u64),
+    /// A chunk of an OSTree object: a pending read, or a description of one.
+    Object(T),
+}
+
 fn write_partition(
     w: &mut impl Write,
     partition: &OsmetPartition,
     packed_image: &mut impl Read,
     ostree_repo: &Path,
-    buf: &mut [u8],
+    pool: &ObjectReaderPool,
 ) -> Result<u64> {
+    let mut buf = [0u8; 8192];
+
+    // Read the mapped objects in parallel, some distance ahead of the writer, and then write them
+    // out in order interspersed with the packed image.
+    let mut segments = partition_segments(partition, ostree_repo)?.into_iter();
+    let mut pending: VecDeque<Segment<(usize, PendingRead)>> = VecDeque::new();
+    let mut in_flight = 0;
+    let mut cursor = partition.start_offset;
+    loop {
+        while in_flight < OBJECT_READ_AHEAD {
+            match segments.next() {
+                Some(Segment::Packed(n)) => pending.push_back(Segment::Packed(n)),
+                Some(Segment::Object(chunk)) => {
+                    let mapping = chunk.mapping;
+                    pending.push_back(Segment::Object((mapping, pool.read(chunk)?)));
+                    in_flight += 1;
+                }
+                None => break,
+            }
+        }
+        match pending.pop_front() {
+            Some(Segment::Packed(n)) => {
+                cursor += copy_exactly_n(packed_image, w, n, &mut buf)?;
+            }
+            Some(Segment::Object((mapping, result))) => {
+                in_flight -= 1;
+                let data = result
+                    .recv()
+                    .context("object reader thread exited unexpectedly")?
+                    .with_context(|| format!("reading mapping {}", mapping))?;
+                w.write_all(&data).context("writing object data")?;
+                cursor += data.len() as u64;
+            }
+            None => break,
+        }
+    }
+
+    // subtract back the partition offset here so we only return the actual size of the partition
+    Ok(cursor - partition.start_offset)
+}
+
+/// Splits a partition into runs of the packed image and chunks of OSTree objects.
+fn partition_segments(
+    partition: &OsmetPartition,
+    ostree_repo: &Path,
+) -> Result<Vec<Segment<ObjectChunk>>> {
     // Set up a reusable buffer for building object paths instead of re-allocating each time. It's
     // easier to maintain it as a Vec<u8> than a PathBuf so we can just use e.g. `write!()`.
     let mut object_pathbuf = {
@@ -164,63 +318,355 @@ fn write_partition(
     object_pathbuf.push(b'/');
     let object_pathbuf_n = object_pathbuf.len();
 
+    let mut segments = Vec::new();
     let mut cursor = partition.start_offset;
-    for mapping in partition.mappings.iter() {
+    for (i, mapping) in partition.mappings.iter().enumerate() {
         let extent_start = mapping.extent.physical + partition.start_offset;
         assert!(extent_start >= cursor);
         if cursor < extent_start {
-            cursor += copy_exactly_n(packed_image, w, extent_start - cursor, buf)?;
+            segments.push(Segment::Packed(extent_start - cursor));
         }
 
         checksum_to_object_path(&mapping.object, &mut object_pathbuf)?;
-        cursor += write_partition_mapping(
-            &mapping.extent,
-            Path::new(OsStr::from_bytes(object_pathbuf.as_slice())),
-            w,
-            buf,
-        )?;
+        let path = Path::new(OsStr::from_bytes(object_pathbuf.as_slice())).to_path_buf();
+        // split large objects so the read-ahead buffers have bounded size
+        let mut offset = 0;
+        while offset < mapping.extent.length {
+            let length = (mapping.extent.length - offset).min(OBJECT_CHUNK_SIZE);
+            segments.push(Segment::Object(ObjectChunk {
+                mapping: i,
+                path: path.clone(),
+                offset: mapping.extent.logical + offset,
+                length,
+            }));
+            offset += length;
+        }
         object_pathbuf.truncate(object_pathbuf_n);
+        cursor = extent_start + mapping.extent.length;
     }
 
     // and copy to the rest of the partition
     assert!(partition.end_offset >= cursor);
-    cursor += copy_exactly_n(packed_image, w, partition.end_offset - cursor, buf)?;
+    if cursor < partition.end_offset {
+        segments.push(Segment::Packed(partition.end_offset - cursor));
+    }
 
-    // subtract back the partition offset here so we only return the actual size of the partition
-    Ok(cursor - partition.start_offset)
+    Ok(segments)
 }
 
-fn write_partition_mapping(
-    extent: &Extent,
-    object: &Path,
-    w: &mut impl Write,
-    buf: &mut [u8],
-) -> Result<u64> {
-    // really, we should be e.g. caching the last N used objects here as open fds so we don't
-    // re-open them each time; in practice we don't really encounter much fragmentation, so we can
-    // afford to be lazy and keep the code simpler
-    let mut object = OpenOptions::new()
-        .read(true)
-        .open(object)
-        .with_context(|| format!("opening {:?}", object))?;
+/// A range of an OSTree object to be read.  If the object is shorter than
+/// the range, the rest is filled with zeroes.
+struct ObjectChunk {
+    /// Index of the mapping within its partition, for error messages.
+    mapping: usize,
+    path: PathBuf,
+    offset: u64,
+    length: u64,
+}
 
-    let mut objlen = object
-        .metadata()
-        .with_context(|| format!("getting metadata for {:?}", object))?
-        .len();
+impl ObjectChunk {
+    fn read(&self) -> Result<Vec<u8>> {
+        let mut object = OpenOptions::new()
+            .read(true)
+            .open(&self.path)
+            .with_context(|| format!("opening {:?}", self.path))?;
+        object
+            .seek(SeekFrom::Start(self.offset))
+            .with_context(|| format!("seeking {:?}", self.path))?;
+        let mut data = Vec::with_capacity(self.length as usize);
+        object
+            .take(self.length)
+            .read_to_end(&mut data)
+            .with_context(|| format!("reading {:?}", self.path))?;
+        data.resize(self.length as usize, 0);
+        Ok(data)
+    }
+}
+
+type ObjectJob = (ObjectChunk, mpsc::SyncSender<Result<Vec<u8>>>);
+type PendingRead = mpsc::Receiver<Result<Vec<u8>>>;
+
+/// Threads reading chunks of OSTree objects.
+struct ObjectReaderPool {
+    jobs: Option<mpsc::Sender<ObjectJob>>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
 
-    if extent.logical > 0 {
-        object.seek(SeekFrom::Start(extent.logical))?;
-        objlen -= extent.logical;
+impl ObjectReaderPool {
+    fn new(count: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<ObjectJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..count)
+            .map(|_| {
+                let receiver = receiver.clone();
+                thread::spawn(move || loop {
+                    // hold the lock only while receiving
+                    let job = receiver.lock().expect("locking job queue").recv();
+                    match job {
+                        // the requester may have gone away after an error
+                        Ok((chunk, result)) => result.send(chunk.read()).unwrap_or(()),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        Self {
+            jobs: Some(sender),
+            threads,
+        }
+    }
+
+    /// Start reading a chunk, returning a channel which will receive the result.
+    fn read(&self, chunk: ObjectChunk) -> Result<PendingRead> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.jobs
+            .as_ref()
+            .expect("job queue missing")
+            .send((chunk, sender))
+            .map_err(|_| anyhow!("object reader threads exited unexpectedly"))?;
+        Ok(receiver)
+    }
+}
+
+impl Drop for ObjectReaderPool {
+    fn drop(&mut self) {
+        // closing the queue causes the threads to exit
+        self.jobs.take();
+        for thread in self.threads.drain(..) {
+            thread.join().expect("joining object reader thread");
+        }
+    }
+}
+
+/// A reader which reads from the underlying reader in a background thread,
+/// buffering up to the specified number of chunks.
+struct ReadAhead {
+    receiver: Option<mpsc::Receiver<io::Result<Vec<u8>>>>,
+    thread: Option<thread::JoinHandle<()>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ReadAhead {
+    fn new(mut reader: impl Read + Send + 'static, chunks: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(chunks);
+        let thread = thread::spawn(move || loop {
+            let mut chunk = vec![0u8; READ_AHEAD_CHUNK_SIZE];
+            let mut n = 0;
+            while n < chunk.len() {
+                match reader.read(&mut chunk[n..]) {
+                    Ok(0) => break,
+                    Ok(count) => n += count,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        sender.send(Err(e)).unwrap_or(());
+                        return;
+                    }
+                }
+            }
+            if n == 0 {
+                // EOF; dropping the sender tells the reader
+                return;
+            }
+            chunk.truncate(n);
+            if sender.send(Ok(chunk)).is_err() {
+                // reader went away
+                return;
+            }
+        });
+        Self {
+            receiver: Some(receiver),
+            thread: Some(thread),
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.receiver.as_ref().map(|r| r.recv()) {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // EOF
+                Some(Err(_)) | None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+impl Drop for ReadAhead {
+    fn drop(&mut self) {
+        // closing the channel causes the thread to exit
+        self.receiver.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().expect("joining read-ahead thread");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_unpacked_image() {
+        let partition = |checksum: Option<u8>| OsmetPartition {
+            start_offset: 100,
+            end_offset: 200,
+            mappings: Vec::new(),
+            checksum: checksum.map(|c| Sha256Digest([c; 32])),
+        };
+        let osmet = Osmet {
+            partitions: vec![partition(Some(1)), partition(None), partition(Some(3))],
+            checksum: Sha256Digest([4; 32]),
+            size: 1000,
+        };
+        let good = [
+            Sha256Digest([1; 32]),
+            Sha256Digest([2; 32]),
+            Sha256Digest([3; 32]),
+        ];
+        assert!(compare_unpacked_image(&osmet, 1000, &Sha256Digest([4; 32]), &good).is_empty());
+
+        let bad = [
+            Sha256Digest([1; 32]),
+            Sha256Digest([5; 32]),
+            Sha256Digest([5; 32]),
+        ];
+        let problems = compare_unpacked_image(&osmet, 999, &Sha256Digest([5; 32]), &bad);
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("partition 2 (offsets 100-200, 0 mappings) has checksum"));
+        assert_eq!(problems[1], "image has 999 bytes but expected 1000");
+        assert!(problems[2].starts_with("image has checksum 0505"));
     }
 
-    let mut n = 0;
-    if objlen < extent.length {
-        n += copy_exactly_n(&mut object, w, objlen, buf)?;
-        n += copy_exactly_n(&mut io::repeat(0), w, extent.length - objlen, buf)?;
-    } else {
-        n += copy_exactly_n(&mut object, w, extent.length, buf)?;
+    #[test]
+    fn test_read_ahead() {
+        let data: Vec<u8> = (0..3 * READ_AHEAD_CHUNK_SIZE + 5)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut found = Vec::new();
+        ReadAhead::new(io::Cursor::new(data.clone()), 2)
+            .read_to_end(&mut found)
+            .unwrap();
+        assert_eq!(found, data);
+
+        // empty
+        let mut found = Vec::new();
+        ReadAhead::new(io::empty(), 2)
+            .read_to_end(&mut found)
+            .unwrap();
+        assert!(found.is_empty());
+
+        // dropped early
+        let mut reader = ReadAhead::new(io::Cursor::new(data), 1);
+        let mut buf = [0u8; 10];
+        reader.read_exact(&mut buf).unwrap();
+        drop(reader);
+
+        // errors are passed through
+        struct Failing;
+        impl Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::new(ErrorKind::Other, "failed"))
+            }
+        }
+        let mut found = Vec::new();
+        ReadAhead::new(Failing, 2)
+            .read_to_end(&mut found)
+            .unwrap_err();
     }
 
-    Ok(n)
+    #[test]
+    fn test_write_unpacked_image() {
+        let repo = tempfile::tempdir().unwrap();
+        let add_object = |object: u8, contents: &[u8]| {
+            let digest = Sha256Digest([object; 32]);
+            let mut path = b"objects/".to_vec();
+            checksum_to_object_path(&digest, &mut path).unwrap();
+            let path = repo.path().join(OsStr::from_bytes(&path));
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+            digest
+        };
+        // larger than a chunk
+        let big: Vec<u8> = (0..OBJECT_CHUNK_SIZE as usize * 2 + 100)
+            .map(|i| (i % 253) as u8)
+            .collect();
+        let big_object = add_object(1, &big);
+        // shorter than its extent
+        let short_object = add_object(2, b"short");
+        let mapping = |physical, logical, length, object: &Sha256Digest| Mapping {
+            extent: Extent {
+                logical,
+                physical,
+                length,
+            },
+            object: object.clone(),
+        };
+        let partitions = vec![
+            OsmetPartition {
+                start_offset: 100,
+                end_offset: 200,
+                mappings: vec![mapping(10, 1, 20, &short_object)],
+                checksum: None,
+            },
+            OsmetPartition {
+                start_offset: 300,
+                end_offset: 300 + big.len() as u64 + 50,
+                mappings: vec![
+                    mapping(0, 0, big.len() as u64, &big_object),
+                    mapping(big.len() as u64 + 10, 5, 30, &big_object),
+                ],
+                checksum: None,
+            },
+        ];
+
+        // build the expected image, using 0xff for packed bytes
+        let mut expected = vec![0xffu8; 300 + big.len() + 50 + 70];
+        expected[110..114].copy_from_slice(b"hort");
+        expected[114..130].fill(0);
+        expected[300..300 + big.len()].copy_from_slice(&big);
+        let start = 300 + big.len() + 10;
+        expected[start..start + 30].copy_from_slice(&big[5..35]);
+        let packed = vec![0xffu8; expected.len() - 20 - big.len() - 30];
+
+        let mut found = Vec::new();
+        let mut checksums = Vec::new();
+        let n = write_unpacked_image(
+            &mut ReadAhead::new(io::Cursor::new(packed), 4),
+            &mut found,
+            &partitions,
+            repo.path(),
+            Some(&mut checksums),
+        )
+        .unwrap();
+        assert_eq!(n, expected.len() as u64);
+        assert_eq!(found, expected);
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(
+            checksums[1],
+            Sha256Digest::from_reader(&mut &expected[300..300 + big.len() + 50]).unwrap()
+        );
+
+        // missing object
+        let mut partitions = partitions;
+        partitions[0].mappings[0].object = Sha256Digest([3; 32]);
+        write_unpacked_image(
+            &mut io::Cursor::new(vec![0u8; expected.len()]),
+            &mut Vec::new(),
+            &partitions,
+            repo.path(),
+            None,
+        )
+        .unwrap_err();
+    }
 }
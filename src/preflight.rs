@@ -0,0 +1,347 @@
+// Copyright 2022 Red Hat, Inc.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks run by install before anything is written to the destination
+
+use anyhow::{bail, Context, Result};
+use std::fs::{read_to_string, OpenOptions};
+use std::path::Path;
+
+use crate::blockdev::get_block_device_size;
+use crate::cmdline::*;
+use crate::devices::format_size;
+
+/// Architectures whose images only boot via UEFI.
+const UEFI_ONLY_ARCHITECTURES: [&str; 1] = ["aarch64"];
+
+/// Sector sizes for which CoreOS images are built.
+const SUPPORTED_SECTOR_SIZES: [u32; 2] = [512, 4096];
+
+/// Minimum RAM for booting the installed system.
+const MIN_MEMORY: u64 = 2 << 30;
+
+/// Minimum destination size for an installed system, checked when we don't
+/// know the size of the image.  Disks are sold in decimal units, so an
+/// "8 GB" disk must pass.
+const MIN_DEST_SIZE: u64 = 8_000_000_000;
+
+/// The system we're running on.
+#[derive(Debug)]
+struct Host {
+    architecture: String,
+    efi: bool,
+    /// Total RAM in bytes, if known.
+    memory: Option<u64>,
+}
+
+/// What we're about to install, and where.
+#[derive(Debug)]
+pub struct Target<'a> {
+    pub device: &'a str,
+    pub architecture: &'a str,
+    pub sector_size: u32,
+    /// Uncompressed size of the image, if known.
+    pub image_size: Option<u64>,
+    /// The destination contains an OS we weren't asked to overwrite.
+    pub existing_os: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Problem {
+    /// Installing would fail or produce a system that can't boot.
+    Error(String),
+    /// Installing might not produce what the user wants.
+    Warning(String),
+}
+
+/// Check the destination, host, and image for problems which would
+/// otherwise only be discovered after the destination is overwritten.
+/// Report all of them, then fail if any are fatal.
+pub fn preflight(target: &Target) -> Result<()> {
+    let host = Host::probe().context("probing host")?;
+    let dest_size = get_block_device_size(
+        &OpenOptions::new()
+            .read(true)
+            .open(target.device)
+            .with_context(|| format!("opening {}", target.device))?,
+    )
+    .with_context(|| format!("getting size of {}", target.device))?
+    .get();
+
+    let problems = check(&host, target, dest_size);
+    let mut errors = 0;
+    for problem in &problems {
+        match problem {
+            Problem::Error(msg) => {
+                eprintln!("Error: {}", msg);
+                errors += 1;
+            }
+            Problem::Warning(msg) => eprintln!("Warning: {}", msg),
+        }
+    }
+    if errors > 0 {
+        bail!("{} preflight check(s) failed", errors);
+    }
+    Ok(())
+}
+
+fn check(host: &Host, target: &Target, dest_size: u64) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    if target.existing_os {
+        problems.push(Problem::Error(format!(
+            "refusing to overwrite existing OS on {}; use --force-overwrite-os to override",
+            target.device
+        )));
+    }
+
+    match target.image_size {
+        Some(image_size) if image_size > dest_size => {
+            problems.push(Problem::Error(format!(
+                "image is {} but {} is only {}",
+                format_size(image_size),
+                target.device,
+                format_size(dest_size)
+            )));
+        }
+        None if dest_size < MIN_DEST_SIZE => {
+            problems.push(Problem::Error(format!(
+                "{} is {} but at least {} is required",
+                target.device,
+                format_size(dest_size),
+                format_size(MIN_DEST_SIZE)
+            )));
+        }
+        _ => (),
+    }
+
+    if !SUPPORTED_SECTOR_SIZES.contains(&target.sector_size) {
+        problems.push(Problem::Warning(format!(
+            "found non-standard sector size {} for {}, assuming 512b-compatible",
+            target.sector_size, target.device
+        )));
+    }
+
+    // The remaining checks only make sense if the installed system will
+    // run on this machine.
+    if target.architecture != host.architecture {
+        problems.push(Problem::Warning(format!(
+            "installing {} image from {} system; it won't boot on this machine",
+            target.architecture, host.architecture
+        )));
+        return problems;
+    }
+
+    if UEFI_ONLY_ARCHITECTURES.contains(&target.architecture) && !host.efi {
+        problems.push(Problem::Error(format!(
+            "{} images require UEFI, but this system wasn't booted via UEFI",
+            target.architecture
+        )));
+    }
+
+    if let Some(memory) = host.memory {
+        if memory < MIN_MEMORY {
+            problems.push(Problem::Warning(format!(
+                "system has {} of RAM; at least {} is recommended",
+                format_size(memory),
+                format_size(MIN_MEMORY)
+            )));
+        }
+    }
+
+    problems
+}
+
+impl Host {
+    fn probe() -> Result<Self> {
+        let meminfo = read_to_string("/proc/meminfo").context("reading /proc/meminfo")?;
+        Ok(Self {
+            architecture: DefaultedString::<Architecture>::default().to_string(),
+            efi: Path::new("/sys/firmware/efi").exists(),
+            memory: parse_mem_total(&meminfo),
+        })
+    }
+}
+
+/// Get the MemTotal value from /proc/meminfo, in bytes.
+fn parse_mem_total(meminfo: &str) -> Option<u64> {
+    meminfo
+        .lines()
+        .find_map(|l| l.strip_prefix("MemTotal:"))
+        .and_then(|v| v.trim().strip_suffix(" kB"))
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(|kb| kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::ImageSource;
+
+    const GIB: u64 = 1 << 30;
+
+    #[test]
+    fn test_check() {
+        let host = Host {
+            architecture: "x86_64".into(),
+            efi: false,
+            memory: Some(4 * GIB),
+        };
+        let target = Target {
+            device: "/dev/vda",
+            architecture: "x86_64",
+            sector_size: 512,
+            image_size: Some(10 * GIB),
+            existing_os: false,
+        };
+
+        // all good
+        assert_eq!(check(&host, &target, 10 * GIB), vec![]);
+        let unknown_size = Target {
+            image_size: None,
+            ..target
+        };
+        assert_eq!(check(&host, &unknown_size, 8_000_000_000), vec![]);
+
+        // too small for any image
+        assert_eq!(
+            check(&host, &unknown_size, 4 * GIB),
+            vec![Problem::Error(
+                "/dev/vda is 4 GiB but at least 7.5 GiB is required".into()
+            )]
+        );
+
+        // small image on a small disk
+        assert_eq!(
+            check(
+                &host,
+                &Target {
+                    image_size: Some(GIB),
+                    ..target
+                },
+                4 * GIB
+            ),
+            vec![]
+        );
+
+        // several problems at once
+        let small_host = Host {
+            memory: Some(GIB),
+            ..host
+        };
+        assert_eq!(
+            check(
+                &small_host,
+                &Target {
+                    sector_size: 520,
+                    existing_os: true,
+                    ..target
+                },
+                8 * GIB
+            ),
+            vec![
+                Problem::Error(
+                    "refusing to overwrite existing OS on /dev/vda; use --force-overwrite-os to override"
+                        .into()
+                ),
+                Problem::Error("image is 10 GiB but /dev/vda is only 8 GiB".into()),
+                Problem::Warning(
+                    "found non-standard sector size 520 for /dev/vda, assuming 512b-compatible"
+                        .into()
+                ),
+                Problem::Warning("system has 1 GiB of RAM; at least 2 GiB is recommended".into()),
+            ]
+        );
+
+        // UEFI-only image on BIOS system
+        let arm_host = Host {
+            architecture: "aarch64".into(),
+            efi: false,
+            memory: None,
+        };
+        let arm_target = Target {
+            architecture: "aarch64",
+            ..target
+        };
+        assert_eq!(
+            check(&arm_host, &arm_target, 10 * GIB),
+            vec![Problem::Error(
+                "aarch64 images require UEFI, but this system wasn't booted via UEFI".into()
+            )]
+        );
+        assert_eq!(
+            check(
+                &Host {
+                    efi: true,
+                    ..arm_host
+                },
+                &arm_target,
+                10 * GIB
+            ),
+            vec![]
+        );
+
+        // cross-architecture install skips host checks
+        assert_eq!(
+            check(&small_host, &arm_target, 10 * GIB),
+            vec![Problem::Warning(
+                "installing aarch64 image from x86_64 system; it won't boot on this machine".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_check_compressed_image_size() {
+        // a compressed image, as from stream metadata, whose size isn't
+        // known until we look inside
+        let mut source = ImageSource {
+            reader: Box::new(&include_bytes!("../fixtures/gpt-512.xz")[..]),
+            length_hint: None,
+            image_size: None,
+            signature: None,
+            filename: "disk.img.xz".into(),
+            artifact_type: "disk".into(),
+        };
+        let image_size = source.peek_image_size().unwrap();
+        assert_eq!(image_size, Some(10 << 20));
+
+        let host = Host {
+            architecture: "x86_64".into(),
+            efi: true,
+            memory: None,
+        };
+        let target = Target {
+            device: "/dev/vda",
+            architecture: "x86_64",
+            sector_size: 512,
+            image_size,
+            existing_os: false,
+        };
+        assert_eq!(
+            check(&host, &target, 8 << 20),
+            vec![Problem::Error(
+                "image is 10 MiB but /dev/vda is only 8 MiB".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_mem_total() {
+        assert_eq!(
+            parse_mem_total("MemTotal:        8029876 kB\nMemFree:          123456 kB\n"),
+            Some(8029876 * 1024)
+        );
+        assert_eq!(parse_mem_total("MemFree:          123456 kB\n"), None);
+    }
+}
@@ -15,15 +15,21 @@
 use anyhow::{anyhow, bail, Context, Result};
 use reqwest::{blocking, StatusCode, Url};
 use serde::Deserialize;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
 use std::fs::OpenOptions;
-use std::io::{Read, Seek, SeekFrom};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::mem;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::thread::sleep;
 use std::time::Duration;
 
+use crate::blockdev::detect_formatted_sector_size;
 use crate::cmdline::*;
+use crate::io::{DecompressReader, TeeReader};
 use crate::osmet::*;
 use crate::util::set_die_on_sigpipe;
 
@@ -80,16 +86,54 @@ pub struct StreamLocation {
     platform: String,
     format: String,
     retries: FetchRetries,
+    /// Stream metadata for our architecture and platform, fetched on
+    /// first use.
+    platform_metadata: RefCell<Option<Rc<Platform>>>,
 }
 
 pub struct ImageSource {
     pub reader: Box<dyn Read>,
     pub length_hint: Option<u64>,
+    /// Uncompressed size of the image, if known
+    pub image_size: Option<u64>,
     pub signature: Option<Vec<u8>>,
     pub filename: String,
     pub artifact_type: String,
 }
 
+impl ImageSource {
+    /// Find the size of the disk image by decompressing its start and
+    /// reading the location of the backup GPT header.  The data consumed
+    /// is pushed back, so the image can still be read from the beginning.
+    pub fn peek_image_size(&mut self) -> Result<Option<u64>> {
+        // enough for a GPT header in 4096-byte sectors
+        let mut start = [0u8; 4096 + 512];
+        let mut consumed = Vec::new();
+        let result = DecompressReader::new(BufReader::new(TeeReader::new(
+            &mut self.reader,
+            &mut consumed,
+        )))
+        .and_then(|mut reader| {
+            reader
+                .read_exact(&mut start)
+                .context("decompressing start of image")
+        });
+        let rest = mem::replace(&mut self.reader, Box::new(io::empty()));
+        self.reader = Box::new(Cursor::new(consumed).chain(rest));
+        result?;
+        Ok(gpt_image_size(&start))
+    }
+}
+
+/// Get the size of a GPT disk image from the start of the image.
+fn gpt_image_size(buf: &[u8]) -> Option<u64> {
+    let sector_size = detect_formatted_sector_size(buf)?.get() as u64;
+    let header = &buf[sector_size as usize..];
+    // the backup header is in the last sector
+    let backup_lba = u64::from_le_bytes(header[32..40].try_into().unwrap());
+    backup_lba.checked_add(1)?.checked_mul(sector_size)
+}
+
 impl FileLocation {
     pub fn new(path: &str) -> Self {
         Self {
@@ -146,6 +190,7 @@ impl ImageLocation for FileLocation {
         Ok(vec![ImageSource {
             reader: Box::new(out),
             length_hint: Some(length),
+            image_size: None,
             signature,
             filename,
             artifact_type: "disk".to_string(),
@@ -219,6 +264,7 @@ impl ImageLocation for UrlLocation {
         Ok(vec![ImageSource {
             reader: Box::new(resp),
             length_hint,
+            image_size: None,
             signature,
             filename,
             artifact_type: self.artifact_type.clone(),
@@ -243,8 +289,38 @@ impl StreamLocation {
             platform: platform.to_string(),
             format: format.to_string(),
             retries,
+            platform_metadata: RefCell::new(None),
         })
     }
+
+    /// Return the stream metadata entry for our architecture and platform,
+    /// fetching the metadata if we haven't already.
+    fn get_platform(&self) -> Result<Rc<Platform>> {
+        if let Some(platform) = &*self.platform_metadata.borrow() {
+            return Ok(platform.clone());
+        }
+        let client = new_http_client()?;
+        let mut stream = fetch_stream(client, &self.stream_url, self.retries)?;
+        let platform = stream
+            .architectures
+            .remove(&self.architecture)
+            .and_then(|mut arch| arch.artifacts.remove(&self.platform))
+            .with_context(|| {
+                format!(
+                    "couldn't find architecture {}, platform {} in stream metadata",
+                    self.architecture, self.platform
+                )
+            })?;
+        let platform = Rc::new(platform);
+        *self.platform_metadata.borrow_mut() = Some(platform.clone());
+        Ok(platform)
+    }
+
+    /// Return the OS release currently referenced by the stream, if the
+    /// stream metadata specifies one.
+    pub fn release(&self) -> Result<Option<String>> {
+        Ok(self.get_platform()?.release.clone())
+    }
 }
 
 impl Display for StreamLocation {
@@ -267,24 +343,14 @@ impl Display for StreamLocation {
 
 impl ImageLocation for StreamLocation {
     fn sources(&self) -> Result<Vec<ImageSource>> {
-        // fetch and parse stream metadata
-        let client = new_http_client()?;
-        let stream = fetch_stream(client, &self.stream_url, self.retries)?;
-
-        // descend it
-        let artifacts = stream
-            .architectures
-            .get(&self.architecture)
-            .map(|arch| arch.artifacts.get(&self.platform))
-            .unwrap_or(None)
-            .map(|platform| platform.formats.get(&self.format))
-            .unwrap_or(None)
-            .with_context(|| {
-                format!(
-                    "couldn't find architecture {}, platform {}, format {} in stream metadata",
-                    self.architecture, self.platform, self.format
-                )
-            })?;
+        // fetch and parse stream metadata, and descend it
+        let platform = self.get_platform()?;
+        let artifacts = platform.formats.get(&self.format).with_context(|| {
+            format!(
+                "couldn't find architecture {}, platform {}, format {} in stream metadata",
+                self.architecture, self.platform, self.format
+            )
+        })?;
 
         // build sources, letting UrlLocation handle the details
         let mut sources: Vec<ImageSource> = Vec::new();
@@ -304,14 +370,18 @@ impl ImageLocation for StreamLocation {
 }
 
 impl OsmetLocation {
-    pub fn new(architecture: &str, sector_size: u32) -> Result<Option<Self>> {
+    pub fn new(
+        architecture: &str,
+        sector_size: u32,
+        version: Option<&glob::Pattern>,
+    ) -> Result<Option<Self>> {
         let osmet_dir = Path::new(OSMET_FILES_DIR);
         if !osmet_dir.exists() {
             return Ok(None);
         }
 
         if let Some((osmet_path, description)) =
-            find_matching_osmet_in_dir(osmet_dir, architecture, sector_size)?
+            find_matching_osmet_in_dir(osmet_dir, architecture, sector_size, version)?
         {
             Ok(Some(Self {
                 osmet_path,
@@ -361,6 +431,7 @@ impl ImageLocation for OsmetLocation {
         Ok(vec![ImageSource {
             reader: Box::new(unpacker),
             length_hint: Some(length),
+            image_size: Some(length),
             signature: None,
             filename,
             artifact_type: "disk".to_string(),
@@ -522,6 +593,7 @@ struct Arch {
 
 #[derive(Debug, Deserialize)]
 struct Platform {
+    release: Option<String>,
     formats: HashMap<String, HashMap<String, Artifact>>,
 }
 
@@ -539,4 +611,51 @@ mod tests {
     fn test_new_http_client() {
         let _ = new_http_client().unwrap();
     }
+
+    #[test]
+    fn test_peek_image_size() {
+        for (fixture, sector_size) in [
+            (&include_bytes!("../fixtures/gpt-512.xz")[..], 512),
+            (&include_bytes!("../fixtures/gpt-4096.xz")[..], 4096),
+        ] {
+            let mut source = ImageSource {
+                reader: Box::new(fixture),
+                length_hint: None,
+                image_size: None,
+                signature: None,
+                filename: "disk.img.xz".into(),
+                artifact_type: "disk".into(),
+            };
+            let size = source.peek_image_size().unwrap().unwrap();
+            assert_eq!(size % sector_size, 0);
+
+            // the source is unchanged, and the size matches
+            let mut compressed = Vec::new();
+            source.reader.read_to_end(&mut compressed).unwrap();
+            assert_eq!(compressed, fixture);
+            let mut image = Vec::new();
+            DecompressReader::new(BufReader::new(&*compressed))
+                .unwrap()
+                .read_to_end(&mut image)
+                .unwrap();
+            assert_eq!(size, image.len() as u64);
+        }
+
+        // not a GPT image
+        let mut source = ImageSource {
+            reader: Box::new(&[0u8; 8192][..]),
+            length_hint: None,
+            image_size: None,
+            signature: None,
+            filename: "disk.img".into(),
+            artifact_type: "disk".into(),
+        };
+        assert_eq!(source.peek_image_size().unwrap(), None);
+        // too short
+        let mut source = ImageSource {
+            reader: Box::new(&[0u8; 512][..]),
+            ..source
+        };
+        source.peek_image_size().unwrap_err();
+    }
 }
@@ -13,7 +13,14 @@
 // limitations under the License.
 
 use anyhow::{bail, Context, Result};
+use nix::sys::signal::Signal;
+use std::convert::TryFrom;
+use std::fmt;
 use std::process::Command;
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// The number of the first SIGINT or SIGTERM we caught, or 0.
+pub(crate) static INTERRUPTED: AtomicI32 = AtomicI32::new(0);
 
 /// Runs the provided command. The first macro argument is the executable, and following arguments
 /// are passed to the command. Returns a Result<()> describing whether the command failed. Errors
@@ -64,6 +71,27 @@ pub fn cmd_output(cmd: &mut Command) -> Result<String> {
         .with_context(|| format!("decoding as UTF-8 output of `{:#?}`", cmd))
 }
 
+/// Formats key/value pairs as lines of text, with the values aligned.
+pub fn format_key_values<K: AsRef<str>, V: fmt::Display>(lines: &[(K, V)]) -> String {
+    let width = lines
+        .iter()
+        .map(|(k, _)| k.as_ref().len())
+        .max()
+        .unwrap_or(0)
+        + 1;
+    lines
+        .iter()
+        .map(|(k, v)| {
+            format!(
+                "{:width$} {}\n",
+                format!("{}:", k.as_ref()),
+                v,
+                width = width
+            )
+        })
+        .collect()
+}
+
 /// Rust ignores SIGPIPE by default, which causes verbose failures when
 /// our output is piped to a program that exits.  Unignore SIGPIPE to avoid
 /// this.  This will give the program no chance to clean up, so is only
@@ -80,3 +108,129 @@ pub fn set_die_on_sigpipe() -> Result<()> {
     .map(|_| ())
     .context("resetting SIGPIPE handler")
 }
+
+/// Catch SIGINT and SIGTERM rather than exiting, so a long-running
+/// operation can notice with `check_interrupted()`, stop at a safe point,
+/// and clean up.  A second signal exits immediately.
+pub fn catch_interrupts() -> Result<()> {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet};
+    extern "C" fn handler(signal: libc::c_int) {
+        if INTERRUPTED.swap(signal, Ordering::SeqCst) != 0 {
+            // async-signal-safe
+            unsafe { libc::_exit(128 + signal) };
+        }
+    }
+    for signal in [Signal::SIGINT, Signal::SIGTERM] {
+        unsafe {
+            sigaction(
+                signal,
+                &SigAction::new(
+                    SigHandler::Handler(handler),
+                    SaFlags::empty(),
+                    SigSet::empty(),
+                ),
+            )
+        }
+        .with_context(|| format!("setting {} handler", signal))?;
+    }
+    Ok(())
+}
+
+/// Return the signal which interrupted us, if we've caught one since
+/// calling `catch_interrupts()`.
+pub fn interrupted() -> Option<Interrupted> {
+    Interrupted::from_flag(&INTERRUPTED)
+}
+
+/// Fail if we've caught SIGINT or SIGTERM since calling
+/// `catch_interrupts()`.
+pub fn check_interrupted() -> Result<()> {
+    match interrupted() {
+        Some(interrupted) => Err(interrupted.into()),
+        None => Ok(()),
+    }
+}
+
+/// Error indicating that an operation was stopped by a signal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interrupted(pub Signal);
+
+impl Interrupted {
+    /// Decode a flag set by the signal handler.
+    pub(crate) fn from_flag(flag: &AtomicI32) -> Option<Self> {
+        match flag.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Signal::try_from(signal).ok().map(Self),
+        }
+    }
+
+    /// Find the interruption which caused an error, if any, including
+    /// one wrapped in an I/O error by `InterruptReader`.
+    pub fn find(err: &anyhow::Error) -> Option<Self> {
+        err.chain().find_map(|e| {
+            e.downcast_ref::<Self>().copied().or_else(|| {
+                e.downcast_ref::<std::io::Error>()
+                    .and_then(|e| e.get_ref())
+                    .and_then(|e| e.downcast_ref::<Self>())
+                    .copied()
+            })
+        })
+    }
+
+    /// The conventional exit status for a process killed by the signal.
+    pub fn exit_status(&self) -> i32 {
+        128 + self.0 as i32
+    }
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted by {}", self.0)
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_interrupted() {
+        let flag = AtomicI32::new(0);
+        assert_eq!(Interrupted::from_flag(&flag), None);
+        flag.store(libc::SIGTERM, Ordering::SeqCst);
+        assert_eq!(
+            Interrupted::from_flag(&flag),
+            Some(Interrupted(Signal::SIGTERM))
+        );
+
+        assert_eq!(Interrupted(Signal::SIGINT).exit_status(), 130);
+        assert_eq!(Interrupted(Signal::SIGTERM).exit_status(), 143);
+        assert_eq!(
+            Interrupted(Signal::SIGINT).to_string(),
+            "interrupted by SIGINT"
+        );
+    }
+
+    #[test]
+    fn test_find_interrupted() {
+        let int = Interrupted(Signal::SIGINT);
+        // returned directly
+        let err = anyhow::Error::from(int);
+        assert_eq!(Interrupted::find(&err), Some(int));
+        // with context
+        let err = err.context("install failed");
+        assert_eq!(Interrupted::find(&err), Some(int));
+        // from InterruptReader
+        let err = anyhow::Error::from(io::Error::new(io::ErrorKind::Other, int))
+            .context("decompressing image")
+            .context("writing image");
+        assert_eq!(Interrupted::find(&err), Some(int));
+        // something else
+        let err = anyhow::Error::from(io::Error::new(io::ErrorKind::Other, "oops"))
+            .context("writing image");
+        assert_eq!(Interrupted::find(&err), None);
+    }
+}
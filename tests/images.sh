@@ -32,6 +32,8 @@ if [ -n "${1:-}" ]; then
     call iso-network.sh "${basedir}"/*.iso
     call iso-kargs.sh "${basedir}"/*.iso
     call dev-show-iso.sh "${basedir}"/*.iso
+    call iso-verify.sh "${basedir}"/*.iso
+    call iso-inspect.sh "${basedir}"/*.iso
     call iso-extract-pxe.sh "${basedir}"
     call customize.sh "${basedir}"
 fi
@@ -46,6 +48,10 @@ call iso-kargs.sh ${fixtures}/iso/embed-areas-2021-01.iso.xz
 call iso-kargs.sh ${fixtures}/iso/embed-areas-2021-09.iso.xz
 call iso-kargs.sh ${fixtures}/iso/embed-areas-2021-12.iso.xz
 call iso-kargs.sh ${fixtures}/iso/embed-areas-2022-02.iso.xz
+call iso-verify.sh ${fixtures}/iso/embed-areas-2021-09.iso.xz
+call iso-verify.sh ${fixtures}/iso/embed-areas-2022-02.iso.xz
+call iso-inspect.sh ${fixtures}/iso/embed-areas-2021-12.iso.xz
+call iso-inspect.sh ${fixtures}/iso/embed-areas-2022-02.iso.xz
 call unsupported.sh
 
 # other image tests
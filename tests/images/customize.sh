@@ -119,11 +119,16 @@ qemu_iso() {
 }
 
 qemu_pxe() {
+    # pass "embedded" if the rootfs is already in the initrd
+    local rootfs="initrd tftp://10.0.2.2/src-rootfs"
+    if [ "${1:-}" = embedded ]; then
+        rootfs=
+    fi
     cat > ipxe <<EOF
 #!ipxe
 kernel tftp://10.0.2.2/src-kernel ignition.firstboot ignition.platform.id=qemu ${kargs_common[*]}
 initrd tftp://10.0.2.2/initrd
-initrd tftp://10.0.2.2/src-rootfs
+${rootfs}
 boot
 EOF
     qemu-img create -f qcow2 disk 8G
@@ -232,6 +237,17 @@ cmp -n "${orig_size}" src-initrd initrd
 [ $(dd if=initrd skip="$((${orig_size} + 1))" bs=1 count=4 status=none) = 7zXZ ]
 rm initrd
 
+# Check PXE rootfs embedding
+pxe_customize --embed-rootfs src-rootfs
+rootfs_size=$(stat -Lc %s src-rootfs)
+cmp -n "${orig_size}" src-initrd initrd
+cmp -n "${rootfs_size}" src-rootfs <(tail -c +"$((${orig_size} + 1))" initrd)
+(coreos-installer pxe customize initrd -o initrd2 --embed-rootfs src-rootfs \
+    2>&1 ||:) | grepq "already contains a rootfs image"
+rm initrd
+(pxe_customize --embed-rootfs src-initrd 2>&1 ||:) |
+    grepq "not a CoreOS live rootfs image"
+
 # Check equivalence of PXE outputs
 coreos-installer pxe customize src-initrd -o initrd \
     "${opts_common[@]}" "${opts_install[@]}"
@@ -311,6 +327,11 @@ pxe_customize "${opts_common[@]}"
 qemu_pxe
 check_live_noinstall
 
+echo "=== PXE with embedded rootfs ==="
+pxe_customize --embed-rootfs src-rootfs "${opts_common[@]}"
+qemu_pxe embedded
+check_live_noinstall
+
 echo "=== PXE with install ==="
 pxe_customize "${opts_common[@]}" "${opts_install[@]}"
 qemu_pxe
@@ -75,5 +75,25 @@ rm -r d
 (coreos-installer dev extract initrd \
     "${fixtures}/initrd/traversal-relative.img" 2>&1 ||:) | grepq traversal
 
+# dev create initrd
+coreos-installer dev extract initrd -C tree compressed.img
+ln -s hello tree/gzip/link
+chmod 755 tree/xz/hello
+coreos-installer dev create initrd -C tree -o created.img
+coreos-installer dev show initrd created.img | diff <(files) -
+coreos-installer dev extract initrd -C d created.img
+check d gzip uncompressed-1 uncompressed-2 xz
+rm -r d
+coreos-installer dev create initrd -C tree --mtime 0 -o created-1.img 'gzip/*'
+touch tree/gzip/hello
+coreos-installer dev create initrd -C tree --mtime 0 -o created-2.img 'gzip/*'
+cmp created-1.img created-2.img
+(echo gzip/hello; echo gzip/world) | diff - <(coreos-installer dev show initrd created-1.img)
+cat compressed.img created-1.img > combined.img
+coreos-installer dev show initrd combined.img > out
+files | diff - out
+(coreos-installer dev create initrd -C tree -o none.img 'z*' 2>&1 ||:) | grepq "no matching"
+rm -r tree
+
 # Done
 echo "Success."
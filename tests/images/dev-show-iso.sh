@@ -31,11 +31,23 @@ jq -e '.header.descriptors[]|select(.type == "primary")|.system_id|contains("LIN
 jq -e '.header.descriptors[]|select(.type == "primary")|.volume_id|contains("fedora-coreos")' inspect.json
 jq -e '.header.descriptors[]|select(.type == "boot")|.boot_system_id|contains("EL TORITO")' inspect.json
 
+# check the El Torito boot catalog
+jq -e '.header.descriptors[]|select(.type == "boot")|.catalog > 0' inspect.json
+jq -e '.boot_catalog.entries[]|select(.platform == "x86" and .bootable)' inspect.json
+jq -e '.boot_catalog.entries[]|select(.platform == "efi" and .bootable)' inspect.json
+coreos-installer iso extract efiboot "${iso}" efiboot.img
+[ -s efiboot.img ]
+coreos-installer iso extract efiboot "${iso}" | cmp - efiboot.img
+
+# check that we're using Rock Ridge names
+jq -e '.header.extension == "rock-ridge"' inspect.json
+jq -e '.header.descriptors[]|select(.type == "joliet")' inspect.json
+
 # check that it found some various files and directories at various depths
 jq -e '.records|index("EFI") >= 0' inspect.json
-jq -e '.records|index("IMAGES/PXEBOOT") >= 0' inspect.json
-jq -e '.records|index("IMAGES/PXEBOOT/ROOTFS.IMG") >= 0' inspect.json
-jq -e '.records|index("ZIPL.PRM") >= 0' inspect.json
+jq -e '.records|index("images/pxeboot") >= 0' inspect.json
+jq -e '.records|index("images/pxeboot/rootfs.img") >= 0' inspect.json
+jq -e '.records|index("zipl.prm") >= 0' inspect.json
 
 # Done
 echo "Success."
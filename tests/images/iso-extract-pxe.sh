@@ -46,5 +46,44 @@ compare_digests "${base}-initrd.img" ${builddir}/*-initramfs.*.img
 # shellcheck disable=SC2086
 compare_digests "${base}-rootfs.img" ${builddir}/*-rootfs.*.img
 
+# check single initrd
+mkdir single
+coreos-installer iso extract pxe -o single --single-initrd "${iso}"
+if [ -e "single/${base}-rootfs.img" ]; then
+    fatal "Single initrd mode wrote separate rootfs"
+fi
+# shellcheck disable=SC2086
+compare_digests "single/${base}-initrd.img" <(cat ${builddir}/*-initramfs.*.img ${builddir}/*-rootfs.*.img)
+
+# check boot configs
+mkdir bootcfg
+coreos-installer iso customize --live-karg-append foobar=val -o custom.iso "${iso}"
+coreos-installer iso extract pxe -o bootcfg --boot-config ipxe,pxelinux,grub \
+    --base-url http://example.com/pxe --ignition-url http://example.com/config.ign custom.iso
+ls bootcfg/custom-boot.ipxe bootcfg/custom-pxelinux.cfg bootcfg/custom-grub.cfg
+for cfg in bootcfg/custom-boot.ipxe bootcfg/custom-pxelinux.cfg bootcfg/custom-grub.cfg; do
+    grep -F "coreos.live.rootfs_url=http://example.com/pxe/custom-rootfs.img" "${cfg}"
+    grep -F "ignition.config.url=http://example.com/config.ign" "${cfg}"
+    grep -F "foobar=val" "${cfg}"
+    if grep -F "coreos.liveiso=" "${cfg}"; then
+        fatal "${cfg} contains coreos.liveiso karg"
+    fi
+done
+grep -Fx "initrd --name initrd0 http://example.com/pxe/custom-initrd.img" bootcfg/custom-boot.ipxe
+# shellcheck disable=SC2086
+compare_digests bootcfg/custom-initrd.img ${builddir}/*-initramfs.*.img
+mkdir bootcfg-single
+coreos-installer iso extract pxe -o bootcfg-single --single-initrd --boot-config ipxe \
+    --base-url http://example.com/pxe custom.iso
+if grep -F "coreos.live.rootfs_url=" bootcfg-single/custom-boot.ipxe; then
+    fatal "Single initrd boot config contains rootfs URL"
+fi
+if grep -F "custom-ignition.img" bootcfg-single/custom-boot.ipxe; then
+    fatal "Single initrd boot config references separate customizations"
+fi
+if coreos-installer iso extract pxe --boot-config ipxe "${iso}"; then
+    fatal "Boot config didn't require base URL"
+fi
+
 # Done
 echo "Success."
@@ -70,6 +70,25 @@ coreos-installer iso ignition embed -f -i <(echo "${config}") "${iso}" -o "${out
 (coreos-installer iso ignition embed -i <(echo "${config}") "${iso}" -o - 2>&1 ||:) | grepq "already has an embedded Ignition config"
 coreos-installer iso ignition embed -f -i <(echo "${config}") "${iso}" -o - >/dev/null
 
+# Test merging
+config1='{"ignition": {"version": "3.0.0"}}'
+config2='{"ignition": {"version": "3.3.0"}, "passwd": {"users": [{"name": "admin"}]}}'
+coreos-installer iso ignition embed -f -i <(echo "${config1}") "${iso}"
+coreos-installer iso ignition embed --merge --show-diff -i <(echo "${config2}") "${iso}" 2> diff
+grepq -Fx '~ /ignition/version: "3.0.0" -> "3.3.0"' diff
+grepq -Fx '+ /passwd: {"users":[{"name":"admin"}]}' diff
+coreos-installer iso ignition show "${iso}" | jq -e '.ignition.config.merge | length == 2'
+(coreos-installer iso ignition embed --merge -f -i <(echo "${config2}") "${iso}" 2>&1 ||:) | grepq "cannot be used with"
+rm "${out_iso}"
+coreos-installer iso ignition remove "${iso}" -o "${out_iso}"
+coreos-installer iso ignition embed --merge -i <(echo "${config2}") "${out_iso}"
+coreos-installer iso ignition show "${out_iso}" | cmp - <(echo "${config2}")
+echo "${config1}" | coreos-installer pxe ignition wrap -o base.img
+coreos-installer pxe ignition wrap --merge base.img --show-diff -i <(echo "${config2}") -o merged.img 2> diff
+grepq -Fx '+ /passwd: {"users":[{"name":"admin"}]}' diff
+coreos-installer pxe ignition unwrap merged.img | jq -e '.ignition.config.merge | length == 2'
+coreos-installer iso ignition embed -f -i <(echo "${config}") "${iso}"
+
 # Test `remove`
 hash=$(coreos-installer iso ignition remove "${iso}" -o - | digest)
 if [ "${orig_hash}" != "${hash}" ]; then
@@ -0,0 +1,74 @@
+#!/bin/bash
+set -xeuo pipefail
+PS4='${LINENO}: '
+
+fatal() {
+    echo "$@" >&2
+    exit 1
+}
+
+iso=$1; shift
+iso=$(realpath "${iso}")
+
+tmpd=$(mktemp -d)
+trap 'rm -rf "${tmpd}"' EXIT
+cd "${tmpd}"
+
+if [ "${iso%.xz}" != "${iso}" ]; then
+    xz -dc "${iso}" > test.iso
+else
+    cp --reflink=auto "${iso}" "test.iso"
+fi
+iso=test.iso
+
+echo '{"ignition": {"version": "3.3.0"}}' > config.ign
+printf '[connection]\nid=eth0\n' > eth0.nmconnection
+printf '#!/bin/sh\ntrue\n' > hook
+
+# Uncustomized
+coreos-installer iso inspect "${iso}" > out
+grep -Fx "Ignition config:    none" out
+grep -Fx "Live kargs:         default" out
+coreos-installer iso inspect --json "${iso}" > out.json
+jq -e '.ignition == null' out.json
+jq -e '.network_keyfiles == []' out.json
+jq -e '.kargs.appended == [] and .kargs.deleted == []' out.json
+jq -e '.embed_areas.initrd.used == 0' out.json
+volume_id=$(coreos-installer dev show iso "${iso}" | jq -r '.header.descriptors[] | select(.type == "primary") | .volume_id')
+jq -e --arg id "${volume_id}" '.volume_id == $id' out.json
+
+# Customized
+coreos-installer iso customize \
+    --dest-device /dev/vda \
+    --dest-ignition config.ign \
+    --network-keyfile eth0.nmconnection \
+    --pre-install hook \
+    --live-karg-append foobar=val \
+    "${iso}"
+coreos-installer iso inspect "${iso}" > out
+grep -Fx "Network keyfiles:   eth0.nmconnection" out
+grep -Fx "Live kargs:         appended foobar=val" out
+grep -Fx "Pre-install hooks:  hook" out
+coreos-installer iso inspect --json "${iso}" > out.json
+jq -e '.ignition.version == "3.3.0"' out.json
+jq -e --arg digest "sha256-$(coreos-installer iso ignition show "${iso}" | sha256sum | awk '{print $1}')" \
+    '.ignition.digest == $digest' out.json
+jq -e '.network_keyfiles == ["eth0.nmconnection"]' out.json
+jq -e '.kargs.appended == ["foobar=val"]' out.json
+jq -e '.installer_config[0].contents | contains("dest-device: /dev/vda")' out.json
+jq -e '.pre_install == ["hook"] and .post_install == []' out.json
+jq -e '.embed_areas.initrd.used > 0' out.json
+jq -e '.embed_areas.initrd.used + .embed_areas.initrd.free == .embed_areas.initrd.capacity' out.json
+
+# PXE
+coreos-installer pxe ignition wrap -i config.ign > ignition.img
+coreos-installer pxe network wrap -k eth0.nmconnection > network.img
+cat ignition.img network.img | coreos-installer pxe inspect --json - > out.json
+jq -e '.ignition.version == "3.3.0"' out.json
+jq -e '.network_keyfiles == ["eth0.nmconnection"]' out.json
+jq -e 'has("embed_areas") | not' out.json
+coreos-installer pxe inspect ignition.img > out
+grep -Fx "Network keyfiles:   none" out
+
+# Done
+echo "Success."
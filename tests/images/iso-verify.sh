@@ -0,0 +1,72 @@
+#!/bin/bash
+set -xeuo pipefail
+PS4='${LINENO}: '
+
+fatal() {
+    echo "$@" >&2
+    exit 1
+}
+
+iso=$1; shift
+iso=$(realpath "${iso}")
+
+tmpd=$(mktemp -d)
+trap 'rm -rf "${tmpd}"' EXIT
+cd "${tmpd}"
+
+if [ "${iso%.xz}" != "${iso}" ]; then
+    xz -dc "${iso}" > test.iso
+else
+    cp --reflink=auto "${iso}" "test.iso"
+fi
+iso=test.iso
+out_iso="${iso}.out"
+
+corrupt() {
+    # Flip a byte near the end of the image, outside the embed areas and
+    # the skipped sectors
+    local sectors=$(od -An -tu4 --endian=big -j $((16 * 2048 + 84)) -N4 "$1" | tr -d ' ')
+    local offset=$(((sectors - 20) * 2048))
+    local byte=$(dd if="$1" bs=1 skip=${offset} count=1 status=none | od -An -tu1 | tr -d ' ')
+    printf "\\$(printf %03o $((byte ^ 255)))" | dd of="$1" bs=1 seek=${offset} conv=notrunc status=none
+}
+
+# Implant a checksum without customizing
+coreos-installer iso reset "${iso}"
+coreos-installer iso customize --implant-checksum -f "${iso}"
+coreos-installer iso verify "${iso}" | grep -Fx "Checksum OK"
+if command -v checkisomd5 >/dev/null; then
+    checkisomd5 "${iso}"
+fi
+
+# Customizing without re-implanting
+coreos-installer iso kargs modify -a foobar=val "${iso}"
+coreos-installer iso verify "${iso}" | grep -Fx "Checksum OK (ignoring customizations)"
+coreos-installer iso kargs reset "${iso}"
+coreos-installer iso verify "${iso}" | grep -Fx "Checksum OK"
+
+# Customizing and re-implanting to a new file
+coreos-installer iso customize -f --live-karg-append foobar=val --implant-checksum -o "${out_iso}" "${iso}"
+coreos-installer iso verify "${out_iso}" | grep -Fx "Checksum OK"
+if command -v checkisomd5 >/dev/null; then
+    checkisomd5 "${out_iso}"
+fi
+if coreos-installer iso customize --implant-checksum -f -o - "${iso}" > /dev/null; then
+    fatal "Implanted checksum when writing to stdout"
+fi
+
+# Corruption
+corrupt "${out_iso}"
+if out=$(coreos-installer iso verify "${out_iso}" 2>&1); then
+    fatal "Failed to detect corrupt ISO"
+fi
+echo "${out}" | grep -F "Checksum mismatch"
+
+# Checksums from implantisomd5
+if command -v implantisomd5 >/dev/null; then
+    implantisomd5 -f "${iso}"
+    coreos-installer iso verify "${iso}" | grep -Fx "Checksum OK"
+fi
+
+# Done
+echo "Success."